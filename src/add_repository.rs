@@ -0,0 +1,460 @@
+//! Adding third-party repositories, as `add-apt-repository` does, without network access.
+//!
+//! A repository is given as one-line entries, a deb822 stanza, or a `ppa:owner/name` shorthand
+//! along with the codename of the release. The file which holds it is named after the
+//! repository in **etc/apt/sources.list.d**, and its key, if given, is written to a keyring of
+//! its own, which the entries refer to with `Signed-By`.
+
+use crate::changes::restyled;
+use crate::openpgp::{dearmor, rooted};
+use crate::source_deb822::SourceListDeb822;
+use crate::{
+    AddRepositoryError, FileOperation, KeyError, Keyring, Signature, SourceEntry, SourceError,
+    SourceFormat, SourceLine, SourceListLineStyle, SourceListType, SourcesList, SourcesLists,
+};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The archive which hosts the PPAs of Launchpad.
+const PPA_ARCHIVE: &str = "https://ppa.launchpadcontent.net";
+
+/// What `AddRepository::add` did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddedRepository {
+    /// The sources file which was created.
+    pub path: PathBuf,
+    /// The keyring which is written, if a key was given.
+    pub keyring: Option<PathBuf>,
+    /// The entries of the new file.
+    pub entries: Vec<SourceEntry>,
+}
+
+impl AddedRepository {
+    /// The new sources file.
+    pub fn sources_list(&self) -> SourcesList {
+        let entries = match SourceFormat::from_path(&self.path) {
+            Some(SourceFormat::OneLine) => SourceListType::SourceLine(SourceListLineStyle(
+                self.entries
+                    .iter()
+                    .cloned()
+                    .map(SourceLine::Entry)
+                    .collect(),
+            )),
+            _ => SourceListType::Deb822(SourceListDeb822 {
+                entries: self.entries.clone(),
+            }),
+        };
+
+        SourcesList {
+            path: self.path.clone(),
+            entries,
+        }
+    }
+}
+
+/// Adds a repository in a new file of **etc/apt/sources.list.d**.
+#[derive(Clone, Debug)]
+pub struct AddRepository {
+    source: String,
+    codename: Option<String>,
+    format: Option<SourceFormat>,
+    name: Option<String>,
+    key: Option<Vec<u8>>,
+    keyrings_dir: PathBuf,
+}
+
+impl AddRepository {
+    /// Takes one-line entries, a deb822 stanza, or a `ppa:owner/name` shorthand.
+    pub fn new(source: &str) -> Self {
+        AddRepository {
+            source: source.trim().to_owned(),
+            codename: None,
+            format: None,
+            name: None,
+            key: None,
+            keyrings_dir: PathBuf::from("/etc/apt/keyrings"),
+        }
+    }
+
+    /// The codename of the release, which a PPA requires.
+    pub fn codename(mut self, codename: &str) -> Self {
+        self.codename = Some(codename.to_owned());
+        self
+    }
+
+    /// The format of the new file.
+    ///
+    /// By default, the deb822 format is used, unless every file of the system is in the
+    /// one-line format.
+    pub fn format(mut self, format: SourceFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// The name of the new file, without extension, rather than one derived from the
+    /// repository.
+    pub fn file_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// The key of the repository, in the binary or the armored format, which is written to a
+    /// keyring of its own.
+    pub fn key(mut self, data: &[u8]) -> Self {
+        self.key = Some(data.to_vec());
+        self
+    }
+
+    /// The directory of the keyring of the repository, **/etc/apt/keyrings** by default.
+    pub fn keyrings_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.keyrings_dir = dir.into();
+        self
+    }
+
+    /// Parses the repository, and works out the file and entries which would be added.
+    ///
+    /// Fails if any of the entries is already enabled in the lists. Nothing is modified.
+    pub fn plan<P: AsRef<Path>>(
+        &self,
+        lists: &SourcesLists,
+        root: P,
+    ) -> Result<AddedRepository, AddRepositoryError> {
+        let (entries, ppa_name) = self.parse()?;
+
+        for entry in &entries {
+            if let Some((location, _)) = lists
+                .located_entries()
+                .find(|(_, e)| e.enabled && e.same_target(entry))
+            {
+                return Err(AddRepositoryError::Exists { location });
+            }
+        }
+
+        let format = self.format.unwrap_or_else(|| {
+            let all_one_line = lists.iter().next().is_some()
+                && lists.iter().all(|l| l.format() == SourceFormat::OneLine);
+            if all_one_line {
+                SourceFormat::OneLine
+            } else {
+                SourceFormat::Deb822
+            }
+        });
+
+        let name = match (&self.name, ppa_name) {
+            (Some(name), _) => name.clone(),
+            (None, Some(name)) => name,
+            (None, None) => file_name(&entries[0]),
+        };
+
+        let extension = match format {
+            SourceFormat::OneLine => "list",
+            SourceFormat::Deb822 => "sources",
+        };
+
+        let path = rooted(root.as_ref(), Path::new("/etc/apt/sources.list.d"))
+            .join(format!("{}.{}", name, extension));
+
+        let keyring = self
+            .key
+            .as_ref()
+            .map(|_| self.keyrings_dir.join(format!("{}.gpg", name)));
+
+        let entries = entries
+            .into_iter()
+            .map(|mut entry| {
+                if let Some(keyring) = &keyring {
+                    entry.signed_by = Some(Signature::KeyPath(vec![keyring.clone()]));
+                }
+
+                restyled(&entry, format)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(AddedRepository {
+            path,
+            keyring,
+            entries,
+        })
+    }
+
+    /// Adds the repository to the lists, along with its keyring below the root.
+    ///
+    /// Both files are written by `SourcesLists::write_sync`, and neither may exist yet.
+    pub fn add<P: AsRef<Path>>(
+        &self,
+        lists: &mut SourcesLists,
+        root: P,
+    ) -> Result<AddedRepository, AddRepositoryError> {
+        let root = root.as_ref();
+        let added = self.plan(lists, root)?;
+
+        // Checks that both files are new before changing anything.
+        let taken = |path: &Path| path.exists() || lists.writes_to(path);
+        if lists.iter().any(|list| list.path == added.path) || taken(&added.path) {
+            return Err(SourceError::FileExists {
+                path: added.path.clone(),
+            }
+            .into());
+        }
+
+        let keyring = match (&self.key, &added.keyring) {
+            (Some(key), Some(keyring)) => {
+                let path = rooted(root, keyring);
+                if taken(&path) {
+                    return Err(KeyError::KeyringExists { path }.into());
+                }
+
+                Some(FileOperation::Write {
+                    path,
+                    contents: keyring_data(key)?,
+                })
+            }
+            _ => None,
+        };
+
+        lists.add_file(added.sources_list())?;
+        lists.operations.extend(keyring);
+
+        Ok(added)
+    }
+
+    /// The entries of the repository, and the name of its file if it is a PPA.
+    fn parse(&self) -> Result<(Vec<SourceEntry>, Option<String>), AddRepositoryError> {
+        let unrecognized = || AddRepositoryError::Unrecognized {
+            input: self.source.clone(),
+        };
+
+        if let Some(ppa) = self.source.strip_prefix("ppa:") {
+            let (owner, name) = ppa
+                .split_once('/')
+                .filter(|(owner, name)| valid_ppa_part(owner) && valid_ppa_part(name))
+                .ok_or_else(unrecognized)?;
+            let codename =
+                self.codename
+                    .as_deref()
+                    .ok_or_else(|| AddRepositoryError::MissingCodename {
+                        ppa: self.source.clone(),
+                    })?;
+
+            let entry = format!(
+                "deb {}/{}/{}/ubuntu {} main",
+                PPA_ARCHIVE, owner, name, codename
+            );
+            let entry = entry.parse::<SourceEntry>()?;
+            return Ok((
+                vec![entry],
+                Some(format!("{}-ubuntu-{}-{}", owner, name, codename)),
+            ));
+        }
+
+        let first = self.source.lines().next().unwrap_or_default();
+        let entries = if first.starts_with("deb ") || first.starts_with("deb-src ") {
+            self.source
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::parse::<SourceEntry>)
+                .collect::<Result<Vec<_>, _>>()?
+        } else if first
+            .split_once(':')
+            .is_some_and(|(field, _)| !field.is_empty() && !field.contains(char::is_whitespace))
+        {
+            self.source.parse::<SourceListDeb822>()?.entries
+        } else {
+            return Err(unrecognized());
+        };
+
+        if entries.is_empty() {
+            return Err(unrecognized());
+        }
+
+        Ok((entries, None))
+    }
+}
+
+/// Owners and names of PPAs are made of lowercase letters, digits and a few symbols.
+fn valid_ppa_part(part: &str) -> bool {
+    !part.is_empty()
+        && part
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-+.".contains(c))
+}
+
+/// Derives the name of a file from the host and path of an entry, such as
+/// `download.docker.com-linux-ubuntu`.
+fn file_name(entry: &SourceEntry) -> String {
+    let text = match Url::parse(&entry.url) {
+        Ok(url) => format!("{}{}", url.host_str().unwrap_or_default(), url.path()),
+        Err(_) => entry.url.clone(),
+    };
+
+    let mut name = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+
+    let name = name.trim_end_matches('-');
+    if name.is_empty() {
+        "repository".to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+/// The contents of the keyring file, in the binary format.
+fn keyring_data(key: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let keyring = Keyring::parse(key)?;
+    if keyring.certificates.is_empty() {
+        return Err(KeyError::Malformed {
+            why: "no key was given".into(),
+        });
+    }
+
+    match std::str::from_utf8(key) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP") => dearmor(text),
+        _ => Ok(key.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, TempRoot};
+    use std::fs;
+
+    fn lists(data: &str) -> SourcesLists {
+        tests::lists(&[("/etc/apt/sources.list", data)])
+    }
+
+    #[test]
+    fn ppa() {
+        let lists = lists("deb http://archive.ubuntu.com/ubuntu noble main\n");
+        let added = AddRepository::new("ppa:deadsnakes/ppa")
+            .codename("noble")
+            .plan(&lists, "/")
+            .unwrap();
+
+        assert_eq!(
+            added.path,
+            Path::new("/etc/apt/sources.list.d/deadsnakes-ubuntu-ppa-noble.list")
+        );
+        assert_eq!(
+            added.entries[0].to_string(),
+            "deb https://ppa.launchpadcontent.net/deadsnakes/ppa/ubuntu noble main"
+        );
+
+        assert!(matches!(
+            AddRepository::new("ppa:deadsnakes/ppa").plan(&lists, "/"),
+            Err(AddRepositoryError::MissingCodename { .. })
+        ));
+        assert!(matches!(
+            AddRepository::new("ppa:Dead Snakes").plan(&lists, "/"),
+            Err(AddRepositoryError::Unrecognized { .. })
+        ));
+    }
+
+    #[test]
+    fn formats() {
+        let lists = lists("deb http://deb.debian.org/debian bookworm main\n");
+
+        // The system only uses one-line files, unless told otherwise.
+        let line = "deb [arch=amd64] https://download.docker.com/linux/debian bookworm stable";
+        let added = AddRepository::new(line).plan(&lists, "/").unwrap();
+        assert_eq!(
+            added.path,
+            Path::new("/etc/apt/sources.list.d/download.docker.com-linux-debian.list")
+        );
+
+        let added = AddRepository::new(line)
+            .format(SourceFormat::Deb822)
+            .file_name("docker")
+            .plan(&lists, "/")
+            .unwrap();
+        assert_eq!(
+            added.path,
+            Path::new("/etc/apt/sources.list.d/docker.sources")
+        );
+        assert!(added.entries[0].is_deb822);
+
+        let stanza = "Types: deb deb-src\n\
+                      URIs: https://example.org/repo\n\
+                      Suites: stable\n\
+                      Components: main\n";
+        let added = AddRepository::new(stanza).plan(&lists, "/").unwrap();
+        assert_eq!(added.entries.len(), 2);
+        assert_eq!(
+            added.path,
+            Path::new("/etc/apt/sources.list.d/example.org-repo.list")
+        );
+
+        // An equivalent entry is refused, wherever it is.
+        let existing = AddRepository::new("deb http://deb.debian.org/debian/ bookworm contrib");
+        assert!(matches!(
+            existing.plan(&lists, "/"),
+            Err(AddRepositoryError::Exists { .. })
+        ));
+        assert!(matches!(
+            AddRepository::new("rpm http://example.org/").plan(&lists, "/"),
+            Err(AddRepositoryError::Unrecognized { .. })
+        ));
+    }
+
+    #[test]
+    fn key() {
+        let root = TempRoot::new("add-repository");
+        fs::create_dir_all(root.join("etc/apt/sources.list.d")).unwrap();
+        let key = fs::read("tests/data/root/usr/share/keyrings/example-archive.asc").unwrap();
+
+        let mut lists = lists("");
+        let added = AddRepository::new("deb https://example.org/repo stable main")
+            .key(&key)
+            .add(&mut lists, &root)
+            .unwrap();
+
+        let keyring = Path::new("/etc/apt/keyrings/example.org-repo.gpg");
+        assert_eq!(added.keyring.as_deref(), Some(keyring));
+        assert_eq!(
+            added.entries[0].to_string(),
+            "deb [signed-by=/etc/apt/keyrings/example.org-repo.gpg] https://example.org/repo stable main"
+        );
+
+        let keyring_path = root.join("etc/apt/keyrings/example.org-repo.gpg");
+        assert!(!keyring_path.exists());
+        lists.write_sync().unwrap();
+        let written = Keyring::open(&keyring_path);
+        let list = fs::read_to_string(root.join("etc/apt/sources.list.d/example.org-repo.list"));
+        let invalid = AddRepository::new("deb https://example.org/other stable main")
+            .key(b"not a key")
+            .add(&mut lists, &root);
+
+        assert_eq!(written.unwrap(), Keyring::parse(&key).unwrap());
+        assert_eq!(list.unwrap(), format!("{}\n", added.entries[0]));
+        assert!(matches!(invalid, Err(AddRepositoryError::Key(_))));
+
+        // Files left on the disk by someone else are not overwritten.
+        let mut lists = self::lists("");
+        let again = AddRepository::new("deb https://example.org/repo stable main")
+            .key(&key)
+            .add(&mut lists, &root);
+        assert!(matches!(
+            again,
+            Err(AddRepositoryError::Source(SourceError::FileExists { .. }))
+        ));
+
+        fs::write(root.join("etc/apt/keyrings/other.gpg"), "").unwrap();
+        let taken = AddRepository::new("deb https://example.org/repo stable main")
+            .key(&key)
+            .file_name("other")
+            .add(&mut lists, &root);
+        assert!(matches!(
+            taken,
+            Err(AddRepositoryError::Key(KeyError::KeyringExists { .. }))
+        ));
+        assert!(lists.files[0].to_string().is_empty());
+        assert!(lists.pending_operations().is_empty());
+    }
+}
@@ -0,0 +1,580 @@
+//! Security audit of the repository configuration.
+//!
+//! Unlike validation, which reports entries that apt would reject, the audit reports entries
+//! that apt accepts but which weaken the authentication of repositories. Disabled entries are
+//! not audited.
+
+use crate::openpgp::rooted;
+use crate::validate::same_fields;
+use crate::{EntryLocation, KeyReference, Keyring, Severity, Signature, SourceEntry, SourcesLists};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Options which disable some of the checks that apt makes on repositories.
+const INSECURE_OPTIONS: &[&str] = &[
+    "allow-insecure",
+    "allow-weak",
+    "allow-downgrade-to-insecure",
+];
+
+/// A risky setting found by the audit.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum AuditIssue {
+    /// The repository is trusted even if it is not signed.
+    TrustedEntry,
+    /// An option which disables signature checks is enabled.
+    InsecureOption { name: String },
+    /// The repository is fetched over plain HTTP, and may be signed by any trusted key.
+    UnsignedHttp { uri: String },
+    /// A keyring can be modified by any user, through itself or one of its parents.
+    WorldWritableKeyring { path: PathBuf, writable: PathBuf },
+    /// A keyring referenced by `signed-by` does not exist.
+    MissingKeyFile { path: PathBuf },
+    /// The same key is allowed to sign repositories of unrelated hosts.
+    SharedKey {
+        fingerprint: String,
+        hosts: Vec<String>,
+    },
+}
+
+impl AuditIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            AuditIssue::UnsignedHttp { .. } | AuditIssue::SharedKey { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// How the issue may be fixed.
+    pub fn suggestion(&self) -> String {
+        match self {
+            AuditIssue::TrustedEntry => {
+                "remove the trusted option, and set signed-by to the key of the repository".into()
+            }
+            AuditIssue::InsecureOption { name } => format!("remove the {} option", name),
+            AuditIssue::UnsignedHttp { .. } => {
+                "set signed-by to the key of the repository, or fetch it over https".into()
+            }
+            AuditIssue::WorldWritableKeyring { writable, .. } => {
+                format!(
+                    "remove the write permission of others from {}",
+                    writable.display()
+                )
+            }
+            AuditIssue::MissingKeyFile { path } => {
+                format!(
+                    "install the keyring at {}, or fix the signed-by path",
+                    path.display()
+                )
+            }
+            AuditIssue::SharedKey { .. } => {
+                "store a dedicated keyring for each repository, and pin each to its own".into()
+            }
+        }
+    }
+}
+
+impl Display for AuditIssue {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            AuditIssue::TrustedEntry => fmt.write_str("repository is trusted without a signature"),
+            AuditIssue::InsecureOption { name } => {
+                write!(fmt, "option '{}' disables signature checks", name)
+            }
+            AuditIssue::UnsignedHttp { uri } => write!(
+                fmt,
+                "'{}' is fetched over plain HTTP without a signed-by key",
+                uri
+            ),
+            AuditIssue::WorldWritableKeyring { path, writable } => {
+                if path == writable {
+                    write!(fmt, "keyring {} is world-writable", path.display())
+                } else {
+                    write!(
+                        fmt,
+                        "keyring {} is stored in world-writable {}",
+                        path.display(),
+                        writable.display()
+                    )
+                }
+            }
+            AuditIssue::MissingKeyFile { path } => {
+                write!(fmt, "keyring {} does not exist", path.display())
+            }
+            AuditIssue::SharedKey { fingerprint, hosts } => write!(
+                fmt,
+                "key {} is allowed for unrelated hosts: {}",
+                fingerprint,
+                hosts.join(", ")
+            ),
+        }
+    }
+}
+
+/// A risky setting found by the audit, along with where it was found.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct AuditFinding {
+    pub location: EntryLocation,
+    pub severity: Severity,
+    pub issue: AuditIssue,
+}
+
+impl AuditFinding {
+    /// How the issue may be fixed.
+    pub fn suggestion(&self) -> String {
+        self.issue.suggestion()
+    }
+}
+
+impl Display for AuditFinding {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(
+            fmt,
+            "{}: {}: {} ({})",
+            self.location,
+            severity,
+            self.issue,
+            self.suggestion()
+        )
+    }
+}
+
+/// The host of the URI of an entry.
+fn host(entry: &SourceEntry) -> Option<String> {
+    Url::parse(&entry.url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+}
+
+/// Whether two hosts belong to the same site, such as `debian.org` and `deb.debian.org`.
+///
+/// Sibling hosts, such as `deb.debian.org` and `security.debian.org`, are not related: without
+/// a list of public suffixes, `example.co.uk` cannot be told apart from `co.uk`.
+fn related_hosts(a: &str, b: &str) -> bool {
+    let below = |host: &str, domain: &str| {
+        host.strip_suffix(domain)
+            .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+    };
+
+    below(a, b) || below(b, a)
+}
+
+/// The first of the file and its parents, below the root, which any user may write to.
+///
+/// When the path goes through symbolic links, the file they lead to and its parents are
+/// checked as well.
+#[cfg(unix)]
+fn world_writable(root: &Path, path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let resolved = resolve(root, path);
+    let writable = |path: &Path| {
+        path.ancestors()
+            .filter(|ancestor| ancestor.file_name().is_some())
+            .find(|ancestor| {
+                let Ok(metadata) = fs::symlink_metadata(rooted(root, ancestor)) else {
+                    return false;
+                };
+
+                // Directories with the sticky bit, such as /tmp, only let owners replace their
+                // files.
+                let mode = metadata.permissions().mode();
+                let sticky = metadata.is_dir() && mode & 0o1000 != 0;
+                !metadata.is_symlink() && !sticky && mode & 0o002 != 0
+            })
+            .map(Path::to_path_buf)
+    };
+
+    writable(path).or_else(|| resolved.as_deref().and_then(writable))
+}
+
+/// Resolves the symbolic links of an absolute path, as seen from within the root.
+///
+/// Returns `None` if a link cannot be read, or if there are too many of them.
+fn resolve(root: &Path, path: &Path) -> Option<PathBuf> {
+    use std::ffi::OsString;
+    use std::path::Component;
+
+    fn push(pending: &mut Vec<OsString>, path: &Path) {
+        let components = path.components().filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_owned()),
+            Component::ParentDir => Some("..".into()),
+            _ => None,
+        });
+        let start = pending.len();
+        pending.extend(components);
+        pending[start..].reverse();
+    }
+
+    let mut resolved = PathBuf::from("/");
+    let mut pending = Vec::new();
+    push(&mut pending, path);
+
+    let mut links = 0;
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+
+        let next = resolved.join(&name);
+        let is_symlink = fs::symlink_metadata(rooted(root, &next)).is_ok_and(|m| m.is_symlink());
+        if !is_symlink {
+            resolved = next;
+            continue;
+        }
+
+        // The limit of the kernel, beyond which a loop is assumed.
+        links += 1;
+        if links > 40 {
+            return None;
+        }
+
+        let target = fs::read_link(rooted(root, &next)).ok()?;
+        if target.is_absolute() {
+            resolved = PathBuf::from("/");
+        }
+        push(&mut pending, &target);
+    }
+
+    Some(resolved)
+}
+
+#[cfg(not(unix))]
+fn world_writable(_root: &Path, _path: &Path) -> Option<PathBuf> {
+    None
+}
+
+impl SourcesLists {
+    /// Reports the settings of enabled entries which weaken the authentication of
+    /// repositories.
+    ///
+    /// Keyrings referenced by `signed-by` are looked up below the root argument. As with
+    /// validation, each issue is only reported once per deb822 stanza.
+    pub fn audit<P: AsRef<Path>>(&self, root: P) -> Vec<AuditFinding> {
+        let root = root.as_ref();
+        let mut findings = Vec::new();
+        // The hosts of the entries allowed to use each key, by fingerprint.
+        let mut key_hosts: Vec<(String, Vec<(String, EntryLocation)>)> = Vec::new();
+        let mut stanza: Vec<AuditIssue> = Vec::new();
+        let mut previous: Option<(PathBuf, &SourceEntry)> = None;
+
+        for (location, entry) in self.located_entries() {
+            if !entry.enabled {
+                continue;
+            }
+
+            let shared = entry.is_deb822
+                && previous
+                    .as_ref()
+                    .is_some_and(|(path, p)| *path == location.path && same_fields(p, entry));
+            if !shared {
+                stanza.clear();
+            }
+
+            let mut issues = Vec::new();
+            if entry.trusted {
+                issues.push(AuditIssue::TrustedEntry);
+            }
+
+            for name in INSECURE_OPTIONS {
+                if let Some((key, _)) = entry.options.iter().find(|(k, v)| {
+                    k.eq_ignore_ascii_case(name) && matches!(v.as_slice(), [v] if v == "yes")
+                }) {
+                    issues.push(AuditIssue::InsecureOption { name: key.clone() });
+                }
+            }
+
+            if entry.signed_by.is_none()
+                && Url::parse(&entry.url).is_ok_and(|url| url.scheme() == "http")
+            {
+                issues.push(AuditIssue::UnsignedHttp {
+                    uri: entry.url.clone(),
+                });
+            }
+
+            let mut fingerprints = Vec::new();
+            match &entry.signed_by {
+                None => (),
+                Some(Signature::KeyBlock(armor)) => {
+                    if let Ok(keyring) = Keyring::parse(armor.as_bytes()) {
+                        fingerprints.extend(
+                            keyring
+                                .certificates
+                                .iter()
+                                .map(|c| c.fingerprint().to_owned()),
+                        );
+                    }
+                }
+                Some(signature) => {
+                    for key in signature.keys().unwrap_or_default() {
+                        match key {
+                            KeyReference::Fingerprint(fingerprint) => {
+                                fingerprints.push(fingerprint.hex().to_owned())
+                            }
+                            KeyReference::Path(path) => {
+                                // Absolute links lead to files below the root as well.
+                                let file =
+                                    rooted(root, &resolve(root, &path).unwrap_or(path.clone()));
+                                if !file.exists() {
+                                    issues.push(AuditIssue::MissingKeyFile { path });
+                                    continue;
+                                }
+
+                                if let Some(writable) = world_writable(root, &path) {
+                                    issues.push(AuditIssue::WorldWritableKeyring {
+                                        path: path.clone(),
+                                        writable,
+                                    });
+                                }
+
+                                if let Ok(keyring) = Keyring::open(&file) {
+                                    fingerprints.extend(
+                                        keyring
+                                            .certificates
+                                            .iter()
+                                            .map(|c| c.fingerprint().to_owned()),
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(host) = host(entry) {
+                for fingerprint in fingerprints {
+                    let index = match key_hosts.iter().position(|(f, _)| *f == fingerprint) {
+                        Some(index) => index,
+                        None => {
+                            key_hosts.push((fingerprint, Vec::new()));
+                            key_hosts.len() - 1
+                        }
+                    };
+
+                    let hosts = &mut key_hosts[index].1;
+                    if !hosts.iter().any(|(h, _)| *h == host) {
+                        hosts.push((host.clone(), location.clone()));
+                    }
+                }
+            }
+
+            previous = Some((location.path.clone(), entry));
+            for issue in issues {
+                if stanza.contains(&issue) {
+                    continue;
+                }
+
+                stanza.push(issue.clone());
+                findings.push(AuditFinding {
+                    location: location.clone(),
+                    severity: issue.severity(),
+                    issue,
+                });
+            }
+        }
+
+        // A key shared by unrelated hosts is reported at the first entry of an unrelated host.
+        for (fingerprint, hosts) in key_hosts {
+            let unrelated = hosts
+                .iter()
+                .find(|(host, _)| !related_hosts(host, &hosts[0].0));
+
+            if let Some((_, location)) = unrelated {
+                let issue = AuditIssue::SharedKey {
+                    fingerprint,
+                    hosts: hosts.iter().map(|(host, _)| host.clone()).collect(),
+                };
+
+                findings.push(AuditFinding {
+                    location: location.clone(),
+                    severity: issue.severity(),
+                    issue,
+                });
+            }
+        }
+
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, TempRoot};
+
+    const SOURCES: &str = "\
+deb [trusted=yes] http://a.example.com/debian stable main
+deb [allow-insecure=yes] https://b.example.com/debian stable main
+# deb [allow-insecure=yes] https://c.example.com/debian stable main
+deb [signed-by=/usr/share/keyrings/missing.gpg] https://d.example.com/debian stable main
+deb [signed-by=/srv/keys/example-archive.asc] https://example.org/debian stable main
+deb [signed-by=/usr/share/keyrings/example-archive.asc] https://mirror.example.net/debian stable main
+deb [signed-by=8356B50980AE5E15DD75D36B7EFE4E79A5238717] https://security.example.org/debian stable main
+";
+
+    const DEB822: &str = "\
+Types: deb deb-src
+URIs: https://b.example.com/debian
+Suites: stable
+Components: main
+Allow-Weak: yes
+Signed-By: /usr/share/keyrings/example-archive.asc
+";
+
+    fn findings(root: &Path) -> Vec<(PathBuf, usize, AuditIssue)> {
+        let lists = tests::lists(&[("sources.list", SOURCES), ("b.sources", DEB822)]);
+
+        lists
+            .audit(root)
+            .into_iter()
+            .map(|f| (f.location.path, f.location.index, f.issue))
+            .collect()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn audit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempRoot::new("audit");
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/root");
+        let keyring = "usr/share/keyrings/example-archive.asc";
+        fs::create_dir_all(root.join("usr/share/keyrings")).unwrap();
+        fs::create_dir_all(root.join("srv/keys")).unwrap();
+        fs::copy(fixtures.join(keyring), root.join(keyring)).unwrap();
+        fs::copy(
+            fixtures.join(keyring),
+            root.join("srv/keys/example-archive.asc"),
+        )
+        .unwrap();
+        fs::set_permissions(root.join("srv/keys"), fs::Permissions::from_mode(0o777)).unwrap();
+
+        let findings = findings(&root);
+
+        let list = PathBuf::from("sources.list");
+        assert_eq!(
+            findings,
+            [
+                (list.clone(), 0, AuditIssue::TrustedEntry),
+                (
+                    list.clone(),
+                    0,
+                    AuditIssue::UnsignedHttp {
+                        uri: "http://a.example.com/debian".into()
+                    }
+                ),
+                (
+                    list.clone(),
+                    1,
+                    AuditIssue::InsecureOption {
+                        name: "allow-insecure".into()
+                    }
+                ),
+                (
+                    list.clone(),
+                    3,
+                    AuditIssue::MissingKeyFile {
+                        path: "/usr/share/keyrings/missing.gpg".into()
+                    }
+                ),
+                (
+                    list.clone(),
+                    4,
+                    AuditIssue::WorldWritableKeyring {
+                        path: "/srv/keys/example-archive.asc".into(),
+                        writable: "/srv/keys".into()
+                    }
+                ),
+                (
+                    "b.sources".into(),
+                    0,
+                    AuditIssue::InsecureOption {
+                        name: "Allow-Weak".into()
+                    }
+                ),
+                (
+                    list,
+                    5,
+                    AuditIssue::SharedKey {
+                        fingerprint: "8356B50980AE5E15DD75D36B7EFE4E79A5238717".into(),
+                        hosts: vec![
+                            "example.org".into(),
+                            "mirror.example.net".into(),
+                            "security.example.org".into(),
+                            "b.example.com".into(),
+                        ]
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinked_keyring() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let root = TempRoot::new("audit-symlink");
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/root");
+        fs::create_dir_all(root.join("usr/share/keyrings")).unwrap();
+        fs::create_dir_all(root.join("srv/keys")).unwrap();
+        fs::copy(
+            fixtures.join("usr/share/keyrings/example-archive.asc"),
+            root.join("srv/keys/example-archive.asc"),
+        )
+        .unwrap();
+        fs::set_permissions(root.join("srv/keys"), fs::Permissions::from_mode(0o777)).unwrap();
+
+        // Absolute links are resolved below the root, like relative ones.
+        symlink(
+            "/srv/keys/example-archive.asc",
+            root.join("usr/share/keyrings/absolute.asc"),
+        )
+        .unwrap();
+        symlink(
+            "../../../srv/keys/example-archive.asc",
+            root.join("usr/share/keyrings/relative.asc"),
+        )
+        .unwrap();
+
+        for name in ["absolute.asc", "relative.asc"] {
+            let path = PathBuf::from("/usr/share/keyrings").join(name);
+            let line = format!(
+                "deb [signed-by={}] https://example.org/debian stable main\n",
+                path.display()
+            );
+            let lists = tests::lists(&[("sources.list", &line)]);
+
+            let issues = lists
+                .audit(&root)
+                .into_iter()
+                .map(|f| f.issue)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                issues,
+                [AuditIssue::WorldWritableKeyring {
+                    path,
+                    writable: "/srv/keys".into()
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn related() {
+        assert!(related_hosts("debian.org", "deb.debian.org"));
+        assert!(related_hosts("deb.debian.org", "deb.debian.org"));
+        assert!(!related_hosts("deb.debian.org", "security.debian.org"));
+        assert!(!related_hosts("example.co.uk", "other.co.uk"));
+        assert!(!related_hosts("example.org", "example.net"));
+        assert!(!related_hosts("example.org", "badexample.org"));
+    }
+}
@@ -0,0 +1,540 @@
+//! Inspects and edits the apt sources of a system.
+//!
+//! Paths and locations are given and shown as seen from `--root`, so that the same commands
+//! work on a chroot. Locations are written as `PATH:INDEX`, as in the output of `list`.
+
+use clap::{Parser, Subcommand};
+use oma_apt_sources_lists::*;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(
+    name = "apt-sources",
+    version,
+    about = "Inspect and edit apt sources lists"
+)]
+struct Cli {
+    /// Operate on the system below this directory, such as a chroot.
+    #[arg(long, global = true, default_value = "/")]
+    root: PathBuf,
+    /// Print the changes as unified diffs instead of writing them.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Print the output as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List every entry along with its location.
+    List,
+    /// Print a sources file.
+    Show { path: PathBuf },
+    /// Enable the entry at a location.
+    Enable { location: Location },
+    /// Disable the entry at a location.
+    Disable { location: Location },
+    /// Add a repository, given as a one-line entry, a deb822 stanza or a `ppa:owner/name`.
+    ///
+    /// The repository is added to a new file of `sources.list.d`, unless `--file` is given.
+    Add {
+        repository: String,
+        /// The file to add a one-line entry to, whose extension selects the format.
+        #[arg(long, conflicts_with_all = ["codename", "key"])]
+        file: Option<PathBuf>,
+        /// The codename of the release, which a PPA requires.
+        #[arg(long)]
+        codename: Option<String>,
+        /// A keyring file holding the key of the repository.
+        #[arg(long)]
+        key: Option<PathBuf>,
+    },
+    /// Remove the entry at a location.
+    Remove { location: Location },
+    /// Rewrite a file in the format of a new path, such as from `.list` to `.sources`.
+    Convert { from: PathBuf, to: PathBuf },
+    /// Check the entries for mistakes, conflicts and insecure settings.
+    Lint,
+    /// Plan the upgrade from a release to another.
+    UpgradePlan {
+        from: String,
+        to: String,
+        /// Apply the plan rather than only printing it.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Compare the backups and disabled files with the active files.
+    Diff,
+}
+
+/// A location given on the command line, as `PATH:INDEX`.
+#[derive(Clone, Debug)]
+struct Location {
+    path: PathBuf,
+    index: usize,
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (path, index) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("'{}' is not of the form PATH:INDEX", s))?;
+        let index = index
+            .parse()
+            .map_err(|_| format!("'{}' is not a valid index", index))?;
+
+        Ok(Location {
+            path: path.into(),
+            index,
+        })
+    }
+}
+
+struct App {
+    root: PathBuf,
+    dry_run: bool,
+    json: bool,
+}
+
+impl App {
+    /// The path of a file below the root.
+    fn rooted(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// The path of a file as seen from the root.
+    fn unrooted(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(path) => Path::new("/").join(path),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    fn location(&self, location: &Location) -> EntryLocation {
+        EntryLocation {
+            path: self.rooted(&location.path),
+            index: location.index,
+        }
+    }
+
+    fn unrooted_location(&self, location: &EntryLocation) -> EntryLocation {
+        EntryLocation {
+            path: self.unrooted(&location.path),
+            index: location.index,
+        }
+    }
+
+    fn print(&self, value: Value) {
+        println!("{:#}", value);
+    }
+
+    fn run(&self, command: Command) -> Result<ExitCode> {
+        let mut lists = SourcesLists::scan_from_root(&self.root)?;
+
+        match command {
+            Command::List => self.list(&lists),
+            Command::Show { path } => {
+                let path = self.rooted(&path);
+                let list = lists
+                    .iter()
+                    .find(|list| list.path == path)
+                    .ok_or(SourceError::FileNotFound)?;
+
+                if self.json {
+                    let mut list = list.clone();
+                    list.path = self.unrooted(&list.path);
+                    self.print(serde_json::to_value(&list)?);
+                } else {
+                    print!("{}", list);
+                }
+            }
+            Command::Enable { location } => self.set_enabled(&mut lists, &location, true)?,
+            Command::Disable { location } => self.set_enabled(&mut lists, &location, false)?,
+            Command::Add {
+                repository,
+                file: Some(file),
+                ..
+            } => {
+                let entry = repository.parse::<SourceEntry>()?;
+                lists.insert_entry(self.rooted(&file), entry)?;
+                self.commit(&mut lists)?;
+            }
+            Command::Add {
+                repository,
+                file: None,
+                codename,
+                key,
+            } => {
+                let mut add = AddRepository::new(&repository);
+                if let Some(codename) = codename {
+                    add = add.codename(&codename);
+                }
+                if let Some(key) = key {
+                    add = add.key(&std::fs::read(&key)?);
+                }
+
+                add.add(&mut lists, &self.root)?;
+                self.commit(&mut lists)?;
+            }
+            Command::Remove { location } => {
+                let change = Change::remove(&lists, self.location(&location))?;
+                self.apply(&mut lists, change)?;
+            }
+            Command::Convert { from, to } => {
+                let change = Change::convert_file(&lists, self.rooted(&from), self.rooted(&to))?;
+                self.apply(&mut lists, change)?;
+            }
+            Command::Lint => return Ok(self.lint(&lists)),
+            Command::UpgradePlan { from, to, apply } => {
+                let plan = lists.plan_upgrade(&from, &to);
+                if apply {
+                    lists.apply_upgrade_plan(&plan)?;
+                    self.commit(&mut lists)?;
+                } else {
+                    self.upgrade_plan(&plan);
+                }
+            }
+            Command::Diff => self.diff(&lists)?,
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn list(&self, lists: &SourcesLists) {
+        if self.json {
+            let entries = lists
+                .all_located_entries()
+                .map(|(location, entry)| {
+                    json!({
+                        "location": self.unrooted_location(&location),
+                        "entry": entry,
+                    })
+                })
+                .collect();
+            self.print(Value::Array(entries));
+            return;
+        }
+
+        for (location, entry) in lists.all_located_entries() {
+            println!(
+                "{}\t{}",
+                self.unrooted_location(&location),
+                entry.to_one_line()
+            );
+        }
+    }
+
+    /// Enables or disables an entry, doing nothing if it already is.
+    fn set_enabled(
+        &self,
+        lists: &mut SourcesLists,
+        location: &Location,
+        enabled: bool,
+    ) -> Result<()> {
+        let location = self.location(location);
+        let entry = lists
+            .entry_at(&location)
+            .ok_or(SourceError::EntryNotFound)?;
+        if entry.enabled == enabled {
+            return Ok(());
+        }
+
+        let change = if enabled {
+            Change::Enable { location }
+        } else {
+            Change::Disable { location }
+        };
+        self.apply(lists, change)
+    }
+
+    fn apply(&self, lists: &mut SourcesLists, change: Change) -> Result<()> {
+        let mut changes = ChangeSet::new();
+        changes.push(change);
+        changes.apply(lists)?;
+        self.commit(lists)
+    }
+
+    /// Writes the changes, or prints them on a dry run.
+    fn commit(&self, lists: &mut SourcesLists) -> Result<()> {
+        if !self.dry_run {
+            lists.write_sync()?;
+            return Ok(());
+        }
+
+        let previews = lists.preview().into_iter().map(|mut preview| {
+            preview.path = self.unrooted(&preview.path);
+            preview.renamed_from = preview.renamed_from.map(|path| self.unrooted(&path));
+            preview
+        });
+        if self.json {
+            let previews = previews
+                .map(|preview| {
+                    json!({
+                        "path": preview.path,
+                        "renamed-from": preview.renamed_from,
+                        "diff": preview.unified_diff(),
+                    })
+                })
+                .collect();
+            self.print(Value::Array(previews));
+        } else {
+            for preview in previews {
+                print!("{}", preview.unified_diff());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints every problem, failing if any of them is an error.
+    fn lint(&self, lists: &SourcesLists) -> ExitCode {
+        let mut problems: Vec<(&str, EntryLocation, Severity, String)> = Vec::new();
+
+        for mut diagnostic in lists.validate() {
+            diagnostic.location = self.unrooted_location(&diagnostic.location);
+            problems.push((
+                "validation",
+                diagnostic.location,
+                diagnostic.severity,
+                diagnostic.issue.to_string(),
+            ));
+        }
+
+        for mut conflict in lists.find_conflicts() {
+            conflict.first = self.unrooted_location(&conflict.first);
+            conflict.second = self.unrooted_location(&conflict.second);
+            problems.push((
+                "conflict",
+                conflict.first.clone(),
+                conflict.severity(),
+                conflict.to_string(),
+            ));
+        }
+
+        for finding in lists.audit(&self.root) {
+            problems.push((
+                "audit",
+                self.unrooted_location(&finding.location),
+                finding.severity,
+                format!("{} ({})", finding.issue, finding.suggestion()),
+            ));
+        }
+
+        let severity = |severity| match severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        if self.json {
+            let problems = problems
+                .iter()
+                .map(|(check, location, level, message)| {
+                    json!({
+                        "check": check,
+                        "location": location,
+                        "severity": severity(*level),
+                        "message": message,
+                    })
+                })
+                .collect();
+            self.print(Value::Array(problems));
+        } else {
+            for (_, location, level, message) in &problems {
+                println!("{}: {}: {}", location, severity(*level), message);
+            }
+        }
+
+        if problems
+            .iter()
+            .any(|(.., level, _)| *level == Severity::Error)
+        {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+
+    fn upgrade_plan(&self, plan: &UpgradePlan) {
+        let action = |action: &PlannedAction| match action {
+            PlannedAction::Upgrade { suite } => ("upgrade", format!("to {}", suite)),
+            PlannedAction::Keep(reason) => ("keep", reason.to_string()),
+            PlannedAction::Disable(reason) => ("disable", reason.to_string()),
+            PlannedAction::NeedsAttention(reason) => ("review", reason.to_string()),
+        };
+
+        if self.json {
+            let entries = plan
+                .entries
+                .iter()
+                .map(|planned| {
+                    let (action, detail) = action(&planned.action);
+                    json!({
+                        "location": self.unrooted_location(&planned.location),
+                        "entry": planned.original,
+                        "action": action,
+                        "detail": detail,
+                    })
+                })
+                .collect();
+            self.print(Value::Array(entries));
+            return;
+        }
+
+        for planned in &plan.entries {
+            let (action, detail) = action(&planned.action);
+            println!(
+                "{}\t{} ({})\t{}",
+                self.unrooted_location(&planned.location),
+                action,
+                detail,
+                planned.original.to_one_line()
+            );
+        }
+    }
+
+    fn diff(&self, lists: &SourcesLists) -> Result<()> {
+        let inactive = SourcesLists::scan_inactive_from_root(&self.root)?;
+
+        if self.json {
+            let files = inactive
+                .iter()
+                .map(|file| {
+                    let changes = file
+                        .diff(lists)
+                        .iter()
+                        .map(|c| {
+                            let (kind, old, new) = split_change(c);
+                            json!({ "change": kind, "old": old, "new": new })
+                        })
+                        .collect::<Vec<_>>();
+                    json!({
+                        "path": self.unrooted(&file.list.path),
+                        "active-path": self.unrooted(&file.active_path),
+                        "kind": file.kind.suffix().trim_start_matches('.'),
+                        "changes": changes,
+                    })
+                })
+                .collect();
+            self.print(Value::Array(files));
+            return Ok(());
+        }
+
+        for file in &inactive {
+            println!(
+                "{} -> {}",
+                self.unrooted(&file.list.path).display(),
+                self.unrooted(&file.active_path).display()
+            );
+
+            for c in file.diff(lists) {
+                let (_, old, new) = split_change(&c);
+                if let Some(old) = old {
+                    println!("- {}", old.to_one_line());
+                }
+                if let Some(new) = new {
+                    println!("+ {}", new.to_one_line());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The name of a change, and the entries before and after it.
+fn split_change(change: &EntryChange) -> (&str, Option<&SourceEntry>, Option<&SourceEntry>) {
+    match change {
+        EntryChange::Added(entry) => ("added", None, Some(entry)),
+        EntryChange::Removed(entry) => ("removed", Some(entry), None),
+        EntryChange::Modified { old, new } => ("modified", Some(old), Some(new)),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let app = App {
+        root: cli.root,
+        dry_run: cli.dry_run,
+        json: cli.json,
+    };
+
+    match app.run(cli.command) {
+        Ok(code) => code,
+        Err(why) => {
+            eprintln!("apt-sources: {}", why);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(root: &str) -> App {
+        App {
+            root: root.into(),
+            dry_run: false,
+            json: false,
+        }
+    }
+
+    #[test]
+    fn location() {
+        let location = "/etc/apt/sources.list.d/a:b.list:3"
+            .parse::<Location>()
+            .unwrap();
+        assert_eq!(location.path, Path::new("/etc/apt/sources.list.d/a:b.list"));
+        assert_eq!(location.index, 3);
+
+        assert!("/etc/apt/sources.list".parse::<Location>().is_err());
+        assert!("/etc/apt/sources.list:first".parse::<Location>().is_err());
+        assert!("/etc/apt/sources.list:-1".parse::<Location>().is_err());
+    }
+
+    #[test]
+    fn rooted_paths() {
+        let chroot = app("/srv/chroot");
+        let path = Path::new("/etc/apt/sources.list");
+        assert_eq!(
+            chroot.rooted(path),
+            Path::new("/srv/chroot/etc/apt/sources.list")
+        );
+        assert_eq!(chroot.unrooted(&chroot.rooted(path)), path);
+        // Relative paths are taken from the root as well.
+        assert_eq!(
+            chroot.rooted(Path::new("etc/apt/sources.list")),
+            Path::new("/srv/chroot/etc/apt/sources.list")
+        );
+        // Paths outside of the root are shown as they are.
+        assert_eq!(
+            chroot.unrooted(Path::new("/tmp/x.list")),
+            Path::new("/tmp/x.list")
+        );
+
+        let system = app("/");
+        assert_eq!(system.rooted(path), path);
+        assert_eq!(system.unrooted(path), path);
+        assert_eq!(
+            system.unrooted_location(&system.location(&Location {
+                path: path.into(),
+                index: 1
+            })),
+            EntryLocation {
+                path: path.into(),
+                index: 1
+            }
+        );
+    }
+}
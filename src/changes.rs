@@ -0,0 +1,1069 @@
+//! Edits of sources files recorded as data.
+//!
+//! A `ChangeSet` lists changes which can be previewed, applied to a `SourcesLists` and
+//! inverted to undo them. Each change records the state that it expects, so that a change set
+//! is refused if the files have changed since it was made. Change sets are written as deb822
+//! stanzas, such as:
+//!
+//! ```text
+//! Change: set-suite
+//! Path: /etc/apt/sources.list
+//! Index: 2
+//! From: bookworm
+//! To: trixie
+//!
+//! Change: create-file
+//! Path: /etc/apt/sources.list.d/example.list
+//!
+//! Change: insert
+//! Path: /etc/apt/sources.list.d/example.list
+//! Index: 0
+//! Entry: deb [arch=amd64] https://example.org/debian stable main
+//! ```
+//!
+//! The following fields are understood:
+//!
+//! * `Change` is one of `enable`, `disable`, `set-suite`, `set-uri`, `add-component`,
+//!   `remove-component`, `add-architecture`, `remove-architecture`, `insert`, `remove`,
+//!   `create-file`, `remove-file` and `convert-file`
+//! * `Path` and `Index` give the location of the entry, as in `EntryLocation`, and `Path` alone
+//!   the file of `create-file` and `remove-file`
+//! * `From` and `To` are the suites of `set-suite`, the URIs of `set-uri`, and the paths of
+//!   `convert-file`
+//! * `Drops-Comments: yes` marks a `convert-file` of a file with comments
+//! * `Component` is the component of `add-component` and `remove-component`
+//! * `Architecture` is the architecture of `add-architecture` and `remove-architecture`
+//! * `Entry` is the entry of `insert` and `remove`, in the one-line style, along with
+//!   `Enabled: no` if it is disabled, and `Signed-By` if it embeds its key
+
+use crate::deb822::signature::Signature;
+use crate::source_deb822::SourceListDeb822;
+use crate::sources_list::add_modified;
+use crate::SourceEntry;
+use crate::{
+    ChangeSetError, EntryLocation, FilePreview, SourceError, SourceFormat, SourceLine,
+    SourceListLineStyle, SourceListType, SourceResult, SourcesList, SourcesLists,
+};
+use deb822_lossless::{Deb822, ParseError};
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A single edit of the sources files.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Change {
+    /// Enables a disabled entry.
+    Enable { location: EntryLocation },
+    /// Disables an enabled entry.
+    Disable { location: EntryLocation },
+    /// Replaces the suite of an entry.
+    SetSuite {
+        location: EntryLocation,
+        from: String,
+        to: String,
+    },
+    /// Replaces the URI of an entry.
+    SetUri {
+        location: EntryLocation,
+        from: String,
+        to: String,
+    },
+    /// Adds a component which the entry does not have.
+    AddComponent {
+        location: EntryLocation,
+        component: String,
+    },
+    /// Removes a component of the entry.
+    RemoveComponent {
+        location: EntryLocation,
+        component: String,
+    },
+    /// Adds an architecture to an entry which is restricted to others.
+    ///
+    /// Entries without architectures apply to every architecture, and are refused.
+    AddArchitecture {
+        location: EntryLocation,
+        architecture: String,
+    },
+    /// Removes an architecture of the entry.
+    ///
+    /// The only architecture of an entry cannot be removed, as the entry would then apply to
+    /// every architecture.
+    RemoveArchitecture {
+        location: EntryLocation,
+        architecture: String,
+    },
+    /// Inserts an entry at the location, in a file which exists.
+    Insert {
+        location: EntryLocation,
+        entry: SourceEntry,
+    },
+    /// Removes the entry at the location.
+    Remove {
+        location: EntryLocation,
+        entry: SourceEntry,
+    },
+    /// Creates an empty file, in the format given by its extension.
+    CreateFile { path: PathBuf },
+    /// Removes a file which has no line left.
+    RemoveFile { path: PathBuf },
+    /// Rewrites a file in the format given by the extension of its new path.
+    ///
+    /// Comments and empty lines of one-line files are not kept. A conversion which drops
+    /// them must say so, and cannot be inverted.
+    ConvertFile {
+        from: PathBuf,
+        to: PathBuf,
+        drops_comments: bool,
+    },
+}
+
+impl Change {
+    /// Records the removal of the entry at the location.
+    pub fn remove(lists: &SourcesLists, location: EntryLocation) -> SourceResult<Self> {
+        let entry = lists
+            .entry_at(&location)
+            .ok_or(SourceError::EntryNotFound)?
+            .clone();
+
+        Ok(Change::Remove { location, entry })
+    }
+
+    /// Records the conversion of a file to the format given by the extension of `to`.
+    pub fn convert_file(lists: &SourcesLists, from: PathBuf, to: PathBuf) -> SourceResult<Self> {
+        let list = lists
+            .iter()
+            .find(|list| list.path == from)
+            .ok_or(SourceError::FileNotFound)?;
+
+        Ok(Change::ConvertFile {
+            drops_comments: has_comments(list),
+            from,
+            to,
+        })
+    }
+
+    /// Records the replacement of the suite of the entry at the location.
+    pub fn set_suite(
+        lists: &SourcesLists,
+        location: EntryLocation,
+        to: &str,
+    ) -> SourceResult<Self> {
+        let from = lists
+            .entry_at(&location)
+            .ok_or(SourceError::EntryNotFound)?
+            .suite
+            .clone();
+
+        Ok(Change::SetSuite {
+            location,
+            from,
+            to: to.to_owned(),
+        })
+    }
+
+    /// The change which undoes this one.
+    ///
+    /// Fails for conversions which drop comments, as these cannot be restored.
+    pub fn invert(&self) -> SourceResult<Self> {
+        Ok(match self.clone() {
+            Change::Enable { location } => Change::Disable { location },
+            Change::Disable { location } => Change::Enable { location },
+            Change::SetSuite { location, from, to } => Change::SetSuite {
+                location,
+                from: to,
+                to: from,
+            },
+            Change::SetUri { location, from, to } => Change::SetUri {
+                location,
+                from: to,
+                to: from,
+            },
+            Change::AddComponent {
+                location,
+                component,
+            } => Change::RemoveComponent {
+                location,
+                component,
+            },
+            Change::RemoveComponent {
+                location,
+                component,
+            } => Change::AddComponent {
+                location,
+                component,
+            },
+            Change::AddArchitecture {
+                location,
+                architecture,
+            } => Change::RemoveArchitecture {
+                location,
+                architecture,
+            },
+            Change::RemoveArchitecture {
+                location,
+                architecture,
+            } => Change::AddArchitecture {
+                location,
+                architecture,
+            },
+            Change::Insert { location, entry } => Change::Remove { location, entry },
+            Change::Remove { location, entry } => Change::Insert { location, entry },
+            Change::CreateFile { path } => Change::RemoveFile { path },
+            Change::RemoveFile { path } => Change::CreateFile { path },
+            Change::ConvertFile {
+                from,
+                drops_comments: true,
+                ..
+            } => return Err(SourceError::LossyConversion { path: from }),
+            Change::ConvertFile { from, to, .. } => Change::ConvertFile {
+                from: to,
+                to: from,
+                drops_comments: false,
+            },
+        })
+    }
+
+    /// Applies the change, failing if the files are not in the state that it expects.
+    fn apply(&self, lists: &mut SourcesLists) -> SourceResult<()> {
+        let stale = |location: &EntryLocation| SourceError::StaleEntry {
+            location: location.clone(),
+        };
+        let unrestricted = |architecture: &str| SourceError::InvalidValue {
+            field: "arch",
+            value: architecture.to_owned(),
+        };
+
+        match self {
+            Change::Enable { location } | Change::Disable { location } => {
+                let enable = matches!(self, Change::Enable { .. });
+                let entry = entry_mut(lists, location)?;
+                if entry.enabled == enable {
+                    return Err(stale(location));
+                }
+
+                entry.enabled = enable;
+            }
+            Change::SetSuite { location, from, to } => {
+                let entry = entry_mut(lists, location)?;
+                if entry.suite != *from {
+                    return Err(stale(location));
+                }
+
+                entry.suite.clone_from(to);
+            }
+            Change::SetUri { location, from, to } => {
+                let entry = entry_mut(lists, location)?;
+                if entry.url != *from {
+                    return Err(stale(location));
+                }
+
+                entry.url.clone_from(to);
+            }
+            Change::AddComponent {
+                location,
+                component,
+            } => {
+                let entry = entry_mut(lists, location)?;
+                if entry.components.contains(component) {
+                    return Err(stale(location));
+                }
+
+                entry.components.push(component.clone());
+            }
+            Change::RemoveComponent {
+                location,
+                component,
+            } => {
+                let entry = entry_mut(lists, location)?;
+                let pos = entry
+                    .components
+                    .iter()
+                    .position(|c| c == component)
+                    .ok_or_else(|| stale(location))?;
+
+                entry.components.remove(pos);
+            }
+            Change::AddArchitecture {
+                location,
+                architecture,
+            } => {
+                let entry = entry_mut(lists, location)?;
+                let archs = entry
+                    .archs
+                    .as_mut()
+                    .ok_or_else(|| unrestricted(architecture))?;
+                if archs.contains(architecture) {
+                    return Err(stale(location));
+                }
+
+                archs.push(architecture.clone());
+            }
+            Change::RemoveArchitecture {
+                location,
+                architecture,
+            } => {
+                let entry = entry_mut(lists, location)?;
+                let archs = entry.archs.as_mut().ok_or_else(|| stale(location))?;
+                let pos = archs
+                    .iter()
+                    .position(|a| a == architecture)
+                    .ok_or_else(|| stale(location))?;
+
+                if archs.len() == 1 {
+                    return Err(unrestricted(architecture));
+                }
+
+                archs.remove(pos);
+            }
+            Change::Insert { location, entry } => {
+                let list = list_mut(lists, location)?;
+                let entry = restyled(entry, list.format())?;
+                match list.entries {
+                    SourceListType::SourceLine(ref mut lines)
+                        if location.index <= lines.0.len() =>
+                    {
+                        lines.0.insert(location.index, SourceLine::Entry(entry))
+                    }
+                    SourceListType::Deb822(ref mut e) if location.index <= e.entries.len() => {
+                        e.entries.insert(location.index, entry)
+                    }
+                    _ => return Err(stale(location)),
+                }
+            }
+            Change::Remove { location, entry } => {
+                let list = list_mut(lists, location)?;
+                let expected = restyled(entry, list.format())?;
+                if list.entry(location.index) != Some(&expected) {
+                    return Err(stale(location));
+                }
+
+                list.remove_at(location.index);
+            }
+            Change::CreateFile { path } => {
+                let format = SourceFormat::from_path(path)
+                    .ok_or_else(|| SourceError::UnknownFormat { path: path.clone() })?;
+                lists.add_file(SourcesList {
+                    path: path.clone(),
+                    entries: empty_entries(format),
+                })?;
+            }
+            Change::RemoveFile { path } => {
+                let list = lists
+                    .iter()
+                    .find(|list| list.path == *path)
+                    .ok_or(SourceError::FileNotFound)?;
+                if !list.to_string().is_empty() {
+                    return Err(SourceError::FileNotEmpty { path: path.clone() });
+                }
+
+                lists.remove_file(path)?;
+            }
+            Change::ConvertFile {
+                from,
+                to,
+                drops_comments,
+            } => {
+                let format = SourceFormat::from_path(to)
+                    .ok_or_else(|| SourceError::UnknownFormat { path: to.clone() })?;
+                let list = lists
+                    .iter()
+                    .find(|list| list.path == *from)
+                    .ok_or(SourceError::FileNotFound)?;
+
+                if !drops_comments && has_comments(list) {
+                    return Err(SourceError::LossyConversion { path: from.clone() });
+                }
+
+                let entries = list
+                    .source_entries()
+                    .map(|entry| restyled(entry, format))
+                    .collect::<SourceResult<Vec<_>>>()?;
+
+                let entries = match format {
+                    SourceFormat::OneLine => SourceListType::SourceLine(SourceListLineStyle(
+                        entries.into_iter().map(SourceLine::Entry).collect(),
+                    )),
+                    SourceFormat::Deb822 => SourceListType::Deb822(SourceListDeb822 { entries }),
+                };
+
+                lists.remove_file(from)?;
+                lists.add_file(SourcesList {
+                    path: to.clone(),
+                    entries,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a file has comments or empty lines, which are not kept by conversions.
+fn has_comments(list: &SourcesList) -> bool {
+    match &list.entries {
+        SourceListType::SourceLine(lines) => lines
+            .0
+            .iter()
+            .any(|line| !matches!(line, SourceLine::Entry(_))),
+        SourceListType::Deb822(_) => false,
+    }
+}
+
+fn empty_entries(format: SourceFormat) -> SourceListType {
+    match format {
+        SourceFormat::OneLine => SourceListType::SourceLine(SourceListLineStyle(Vec::new())),
+        SourceFormat::Deb822 => SourceListType::Deb822(SourceListDeb822 {
+            entries: Vec::new(),
+        }),
+    }
+}
+
+/// Converts an entry to the style of a file.
+pub(crate) fn restyled(entry: &SourceEntry, format: SourceFormat) -> SourceResult<SourceEntry> {
+    if format == SourceFormat::OneLine && matches!(entry.signed_by, Some(Signature::KeyBlock(_))) {
+        return Err(SourceError::InvalidValue {
+            field: "signed-by",
+            value: "an embedded key cannot be written in the one-line format".into(),
+        });
+    }
+
+    if format == SourceFormat::OneLine && entry.unsupported_type {
+        return Err(SourceError::InvalidValue {
+            field: "Types",
+            value: "a stanza without a supported type cannot be written in the one-line format"
+                .into(),
+        });
+    }
+
+    let mut entry = entry.clone();
+    entry.set_style(format == SourceFormat::Deb822);
+    Ok(entry)
+}
+
+/// Fetches the file of a location, marking it as modified.
+fn list_mut<'a>(
+    lists: &'a mut SourcesLists,
+    location: &EntryLocation,
+) -> SourceResult<&'a mut SourcesList> {
+    let &mut SourcesLists {
+        ref mut files,
+        ref mut modified,
+        ..
+    } = lists;
+
+    let list = files
+        .iter_mut()
+        .find(|list| list.path == location.path)
+        .ok_or(SourceError::FileNotFound)?;
+
+    add_modified(modified, &location.path);
+    Ok(list)
+}
+
+fn entry_mut<'a>(
+    lists: &'a mut SourcesLists,
+    location: &EntryLocation,
+) -> SourceResult<&'a mut SourceEntry> {
+    list_mut(lists, location)?
+        .entry_mut(location.index)
+        .ok_or(SourceError::EntryNotFound)
+}
+
+impl Display for Change {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let location = |fmt: &mut Formatter, location: &EntryLocation| {
+            writeln!(fmt, "Path: {}", location.path.display())?;
+            writeln!(fmt, "Index: {}", location.index)
+        };
+
+        let entry = |fmt: &mut Formatter, entry: &SourceEntry| {
+            let mut line = entry.clone();
+            let key = match line.signed_by.take() {
+                Some(signature @ Signature::KeyBlock(_)) => Some(signature),
+                signature => {
+                    line.signed_by = signature;
+                    None
+                }
+            };
+
+            line.set_style(false);
+            line.enabled = true;
+            writeln!(fmt, "Entry: {}", line)?;
+            if !entry.enabled {
+                writeln!(fmt, "Enabled: no")?;
+            }
+            if let Some(key) = key {
+                writeln!(fmt, "Signed-By:{}", key)?;
+            }
+
+            Ok(())
+        };
+
+        match self {
+            Change::Enable { location: l } => {
+                writeln!(fmt, "Change: enable")?;
+                location(fmt, l)
+            }
+            Change::Disable { location: l } => {
+                writeln!(fmt, "Change: disable")?;
+                location(fmt, l)
+            }
+            Change::SetSuite {
+                location: l,
+                from,
+                to,
+            } => {
+                writeln!(fmt, "Change: set-suite")?;
+                location(fmt, l)?;
+                writeln!(fmt, "From: {}", from)?;
+                writeln!(fmt, "To: {}", to)
+            }
+            Change::SetUri {
+                location: l,
+                from,
+                to,
+            } => {
+                writeln!(fmt, "Change: set-uri")?;
+                location(fmt, l)?;
+                writeln!(fmt, "From: {}", from)?;
+                writeln!(fmt, "To: {}", to)
+            }
+            Change::AddComponent {
+                location: l,
+                component,
+            }
+            | Change::RemoveComponent {
+                location: l,
+                component,
+            } => {
+                let name = match self {
+                    Change::AddComponent { .. } => "add-component",
+                    _ => "remove-component",
+                };
+                writeln!(fmt, "Change: {}", name)?;
+                location(fmt, l)?;
+                writeln!(fmt, "Component: {}", component)
+            }
+            Change::AddArchitecture {
+                location: l,
+                architecture,
+            }
+            | Change::RemoveArchitecture {
+                location: l,
+                architecture,
+            } => {
+                let name = match self {
+                    Change::AddArchitecture { .. } => "add-architecture",
+                    _ => "remove-architecture",
+                };
+                writeln!(fmt, "Change: {}", name)?;
+                location(fmt, l)?;
+                writeln!(fmt, "Architecture: {}", architecture)
+            }
+            Change::Insert {
+                location: l,
+                entry: e,
+            }
+            | Change::Remove {
+                location: l,
+                entry: e,
+            } => {
+                let name = match self {
+                    Change::Insert { .. } => "insert",
+                    _ => "remove",
+                };
+                writeln!(fmt, "Change: {}", name)?;
+                location(fmt, l)?;
+                entry(fmt, e)
+            }
+            Change::CreateFile { path } | Change::RemoveFile { path } => {
+                let name = match self {
+                    Change::CreateFile { .. } => "create-file",
+                    _ => "remove-file",
+                };
+                writeln!(fmt, "Change: {}", name)?;
+                writeln!(fmt, "Path: {}", path.display())
+            }
+            Change::ConvertFile {
+                from,
+                to,
+                drops_comments,
+            } => {
+                writeln!(fmt, "Change: convert-file")?;
+                writeln!(fmt, "From: {}", from.display())?;
+                writeln!(fmt, "To: {}", to.display())?;
+                if *drops_comments {
+                    writeln!(fmt, "Drops-Comments: yes")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An ordered list of changes, which is applied as a whole or not at all.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ChangeSet {
+    pub changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, change: Change) -> &mut Self {
+        self.changes.push(change);
+        self
+    }
+
+    /// The change set which undoes this one.
+    ///
+    /// Fails if a change cannot be inverted, as described by `Change::invert`.
+    pub fn invert(&self) -> SourceResult<Self> {
+        Ok(ChangeSet {
+            changes: self
+                .changes
+                .iter()
+                .rev()
+                .map(Change::invert)
+                .collect::<SourceResult<_>>()?,
+        })
+    }
+
+    /// Applies every change in order. If any change cannot be applied, the lists are left
+    /// untouched.
+    ///
+    /// Changes are only applied in-memory. Use `SourcesLists::write_sync` to write them.
+    pub fn apply(&self, lists: &mut SourcesLists) -> SourceResult<()> {
+        let mut staged = lists.clone();
+        for change in &self.changes {
+            change.apply(&mut staged)?;
+        }
+
+        *lists = staged;
+        Ok(())
+    }
+
+    /// Describes what writing the lists would do once the changes are applied, without
+    /// modifying them.
+    pub fn preview(&self, lists: &SourcesLists) -> SourceResult<Vec<FilePreview>> {
+        let mut staged = lists.clone();
+        self.apply(&mut staged)?;
+        Ok(staged.preview())
+    }
+}
+
+impl Display for ChangeSet {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        for (pos, change) in self.changes.iter().enumerate() {
+            if pos != 0 {
+                writeln!(fmt)?;
+            }
+
+            write!(fmt, "{}", change)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ChangeSet {
+    type Err = ChangeSetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let deb822: Deb822 = s
+            .parse()
+            .map_err(|e: ParseError| ChangeSetError::Syntax { why: e.to_string() })?;
+
+        let mut changes = Vec::new();
+        for p in deb822.paragraphs() {
+            let field = |field: &'static str| {
+                p.get(field)
+                    .map(|v| v.trim().to_owned())
+                    .ok_or(ChangeSetError::MissingField { field })
+            };
+            let invalid =
+                |field: &'static str, value: String| ChangeSetError::InvalidValue { field, value };
+
+            let location = || -> Result<EntryLocation, ChangeSetError> {
+                let index = field("Index")?;
+                Ok(EntryLocation {
+                    path: field("Path")?.into(),
+                    index: index.parse().map_err(|_| invalid("Index", index))?,
+                })
+            };
+
+            let entry = || -> Result<SourceEntry, ChangeSetError> {
+                let line = field("Entry")?;
+                let mut entry = line
+                    .parse::<SourceEntry>()
+                    .map_err(|_| invalid("Entry", line))?;
+
+                match p.get("Enabled").as_deref().map(str::trim) {
+                    None | Some("yes") => (),
+                    Some("no") => entry.enabled = false,
+                    Some(value) => return Err(invalid("Enabled", value.to_owned())),
+                }
+
+                if let Some(key) = p.get("Signed-By") {
+                    entry.signed_by = Some(
+                        key.parse::<Signature>()
+                            .map_err(|_| invalid("Signed-By", key))?,
+                    );
+                }
+
+                Ok(entry)
+            };
+
+            let change = match field("Change")?.as_str() {
+                "enable" => Change::Enable {
+                    location: location()?,
+                },
+                "disable" => Change::Disable {
+                    location: location()?,
+                },
+                "set-suite" => Change::SetSuite {
+                    location: location()?,
+                    from: field("From")?,
+                    to: field("To")?,
+                },
+                "set-uri" => Change::SetUri {
+                    location: location()?,
+                    from: field("From")?,
+                    to: field("To")?,
+                },
+                "add-component" => Change::AddComponent {
+                    location: location()?,
+                    component: field("Component")?,
+                },
+                "remove-component" => Change::RemoveComponent {
+                    location: location()?,
+                    component: field("Component")?,
+                },
+                "add-architecture" => Change::AddArchitecture {
+                    location: location()?,
+                    architecture: field("Architecture")?,
+                },
+                "remove-architecture" => Change::RemoveArchitecture {
+                    location: location()?,
+                    architecture: field("Architecture")?,
+                },
+                "insert" => Change::Insert {
+                    location: location()?,
+                    entry: entry()?,
+                },
+                "remove" => Change::Remove {
+                    location: location()?,
+                    entry: entry()?,
+                },
+                "create-file" => Change::CreateFile {
+                    path: field("Path")?.into(),
+                },
+                "remove-file" => Change::RemoveFile {
+                    path: field("Path")?.into(),
+                },
+                "convert-file" => Change::ConvertFile {
+                    from: field("From")?.into(),
+                    to: field("To")?.into(),
+                    drops_comments: match p.get("Drops-Comments").as_deref().map(str::trim) {
+                        None | Some("no") => false,
+                        Some("yes") => true,
+                        Some(value) => return Err(invalid("Drops-Comments", value.to_owned())),
+                    },
+                },
+                other => return Err(invalid("Change", other.to_owned())),
+            };
+
+            changes.push(change);
+        }
+
+        Ok(ChangeSet { changes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, location};
+    use std::path::Path;
+
+    const LIST: &str = "\
+deb http://deb.debian.org/debian bookworm main
+deb-src http://deb.debian.org/debian bookworm main
+";
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[("debian.list", LIST)])
+    }
+
+    fn change_set(lists: &SourcesLists) -> ChangeSet {
+        let mut changes = ChangeSet::new();
+        changes
+            .push(Change::set_suite(lists, location("debian.list", 0), "trixie").unwrap())
+            .push(Change::AddComponent {
+                location: location("debian.list", 0),
+                component: "contrib".into(),
+            })
+            .push(Change::Disable {
+                location: location("debian.list", 1),
+            })
+            .push(Change::Insert {
+                location: location("debian.list", 2),
+                entry: "deb http://deb.debian.org/debian-security trixie-security main"
+                    .parse()
+                    .unwrap(),
+            })
+            .push(
+                Change::convert_file(lists, "debian.list".into(), "debian.sources".into()).unwrap(),
+            );
+        changes
+    }
+
+    #[test]
+    fn apply_and_invert() {
+        let original = lists();
+        let mut lists = lists();
+        let changes = change_set(&lists);
+
+        assert!(matches!(
+            Change::remove(&lists, location("debian.list", 2)),
+            Err(SourceError::EntryNotFound)
+        ));
+
+        // A change made against other contents is refused.
+        let mut stale = changes.clone();
+        stale.changes[0] = Change::SetSuite {
+            location: location("debian.list", 0),
+            from: "bullseye".into(),
+            to: "trixie".into(),
+        };
+        assert!(matches!(
+            stale.apply(&mut lists),
+            Err(SourceError::StaleEntry { .. })
+        ));
+        assert!(lists.modified.is_empty());
+
+        changes.apply(&mut lists).unwrap();
+
+        assert_eq!(lists.files.len(), 1);
+        assert_eq!(
+            lists.files[0].to_string(),
+            "Types: deb\n\
+             URIs: http://deb.debian.org/debian\n\
+             Suites: trixie \n\
+             Components: main contrib\n\
+             \n\
+             Enabled: no\n\
+             Types: deb-src\n\
+             URIs: http://deb.debian.org/debian\n\
+             Suites: bookworm \n\
+             Components: main\n\
+             \n\
+             Types: deb\n\
+             URIs: http://deb.debian.org/debian-security\n\
+             Suites: trixie-security \n\
+             Components: main\n"
+        );
+
+        // Applying again fails, and leaves the lists untouched.
+        let applied = lists.clone();
+        assert!(matches!(
+            changes.apply(&mut lists),
+            Err(SourceError::FileNotFound)
+        ));
+        assert_eq!(lists.files[0].entries, applied.files[0].entries);
+
+        changes.invert().unwrap().apply(&mut lists).unwrap();
+        assert_eq!(lists.files.len(), 1);
+        assert_eq!(lists.files[0].path, original.files[0].path);
+        assert_eq!(
+            lists.files[0].source_entries().collect::<Vec<_>>(),
+            original.files[0].source_entries().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let lists = lists();
+        let mut changes = change_set(&lists);
+        changes.push(Change::AddArchitecture {
+            location: location("debian.list", 1),
+            architecture: "i386".into(),
+        });
+        changes.push(Change::Remove {
+            location: location("example.sources", 0),
+            entry: {
+                let mut entry: SourceEntry = "deb https://example.org/repo stable main"
+                    .parse()
+                    .unwrap();
+                entry.enabled = false;
+                entry.signed_by = Some(Signature::KeyBlock(
+                    "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmDMEZ\n-----END PGP PUBLIC KEY BLOCK-----"
+                        .into(),
+                ));
+                entry
+            },
+        });
+
+        let text = changes.to_string();
+        assert!(text.starts_with(
+            "Change: set-suite\n\
+             Path: debian.list\n\
+             Index: 0\n\
+             From: bookworm\n\
+             To: trixie\n\
+             \n"
+        ));
+        assert!(text.ends_with(
+            "Entry: deb https://example.org/repo stable main\n\
+             Enabled: no\n\
+             Signed-By:\n \
+             -----BEGIN PGP PUBLIC KEY BLOCK-----\n \
+             .\n \
+             mDMEZ\n \
+             -----END PGP PUBLIC KEY BLOCK-----\n"
+        ));
+        assert_eq!(text.parse::<ChangeSet>().unwrap(), changes);
+        assert!(matches!(
+            "Change: rename\n".parse::<ChangeSet>(),
+            Err(ChangeSetError::InvalidValue {
+                field: "Change",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn preview() {
+        let lists = lists();
+        let previews = change_set(&lists).preview(&lists).unwrap();
+
+        // The removal of debian.list is not shown, as it is not on the disk.
+        assert_eq!(previews.len(), 1);
+        assert_eq!(previews[0].path, Path::new("debian.sources"));
+        assert_eq!(previews[0].original, None);
+        assert_eq!(previews[0].changes.len(), 3);
+        assert!(lists.modified.is_empty());
+    }
+
+    #[test]
+    fn files() {
+        let mut lists = lists();
+        let insert = Change::Insert {
+            location: location("extra.list", 0),
+            entry: "deb https://example.org/repo stable main".parse().unwrap(),
+        };
+
+        // Entries are only inserted in existing files, whose creation is a change of its own.
+        let mut changes = ChangeSet::new();
+        changes.push(insert.clone());
+        assert!(matches!(
+            changes.apply(&mut lists),
+            Err(SourceError::FileNotFound)
+        ));
+
+        let mut changes = ChangeSet::new();
+        changes
+            .push(Change::CreateFile {
+                path: "extra.list".into(),
+            })
+            .push(insert);
+        changes.apply(&mut lists).unwrap();
+        assert_eq!(lists.files.len(), 2);
+
+        let undo = changes.invert().unwrap();
+        assert_eq!(
+            undo.changes[1],
+            Change::RemoveFile {
+                path: "extra.list".into()
+            }
+        );
+        assert_eq!(undo.to_string().parse::<ChangeSet>().unwrap(), undo);
+        undo.apply(&mut lists).unwrap();
+        assert_eq!(lists.files.len(), 1);
+        assert_eq!(
+            lists.pending_operations(),
+            [crate::FileOperation::Remove {
+                path: "extra.list".into()
+            }]
+        );
+
+        // Only empty files are removed.
+        assert!(matches!(
+            Change::RemoveFile {
+                path: "debian.list".into()
+            }
+            .apply(&mut lists),
+            Err(SourceError::FileNotEmpty { .. })
+        ));
+    }
+
+    #[test]
+    fn lossy_conversion() {
+        let mut lists = tests::lists(&[("debian.list", &format!("# Debian\n{}", LIST))]);
+
+        // A conversion which does not expect to drop comments is refused.
+        let unaware = Change::ConvertFile {
+            from: "debian.list".into(),
+            to: "debian.sources".into(),
+            drops_comments: false,
+        };
+        assert!(matches!(
+            ChangeSet {
+                changes: vec![unaware]
+            }
+            .apply(&mut lists),
+            Err(SourceError::LossyConversion { .. })
+        ));
+
+        let convert =
+            Change::convert_file(&lists, "debian.list".into(), "debian.sources".into()).unwrap();
+        assert!(convert.to_string().ends_with("Drops-Comments: yes\n"));
+
+        let changes = ChangeSet {
+            changes: vec![convert],
+        };
+        assert_eq!(changes.to_string().parse::<ChangeSet>().unwrap(), changes);
+        changes.apply(&mut lists).unwrap();
+        assert!(matches!(
+            changes.invert(),
+            Err(SourceError::LossyConversion { .. })
+        ));
+    }
+
+    #[test]
+    fn architectures() {
+        let mut lists = lists();
+        let add = |index| Change::AddArchitecture {
+            location: location("debian.list", index),
+            architecture: "i386".into(),
+        };
+        let remove = |architecture: &str| Change::RemoveArchitecture {
+            location: location("debian.list", 0),
+            architecture: architecture.into(),
+        };
+
+        // Entries without architectures are not restricted to the added one.
+        assert!(matches!(
+            add(0).apply(&mut lists),
+            Err(SourceError::InvalidValue { field: "arch", .. })
+        ));
+
+        lists.files[0].entry_mut(0).unwrap().archs = Some(vec!["amd64".into()]);
+        add(0).apply(&mut lists).unwrap();
+        remove("amd64").apply(&mut lists).unwrap();
+        assert_eq!(
+            lists.entry_at(&location("debian.list", 0)).unwrap().archs,
+            Some(vec!["i386".into()])
+        );
+
+        // Nor are they left without any architecture.
+        assert!(matches!(
+            remove("i386").apply(&mut lists),
+            Err(SourceError::InvalidValue { field: "arch", .. })
+        ));
+    }
+}
@@ -15,6 +15,8 @@ pub enum RepositoryError {
     InvalidType,
     /// The `Signed-By` field is incorrect
     InvalidSignature,
+    /// An OpenPGP fingerprint is malformed
+    InvalidFingerprint,
     /// Errors in lossy serializer or deserializer
     Lossy(deb822_lossless::lossy::Error),
     /// Errors in lossless parser
@@ -37,6 +39,7 @@ impl std::fmt::Display for RepositoryError {
             Self::MissingUri => write!(f, "Missing repository URI"),
             Self::InvalidType => write!(f, "Invalid repository type"),
             Self::InvalidSignature => write!(f, "The field `Signed-By` is incorrect"),
+            Self::InvalidFingerprint => write!(f, "Invalid OpenPGP fingerprint"),
             Self::Lossy(e) => write!(f, "Lossy parser error: {}", e),
             Self::Lossless(e) => write!(f, "Lossless parser error: {}", e),
             Self::Io(e) => write!(f, "IO error: {}", e),
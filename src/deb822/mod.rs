@@ -46,6 +46,7 @@ impl From<&RepositoryType> for String {
     }
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for RepositoryType {
     fn to_string(&self) -> String {
         self.into()
@@ -86,16 +87,19 @@ impl From<&YesNoForce> for String {
     }
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for &YesNoForce {
     fn to_string(&self) -> String {
         self.to_owned().into()
     }
 }
 
+/// Unknown types are skipped, so that they can be reported by `SourcesList::validate`.
 fn deserialize_types(text: &str) -> Result<HashSet<RepositoryType>, RepositoryError> {
-    text.split_whitespace()
-        .map(RepositoryType::from_str)
-        .collect::<Result<HashSet<RepositoryType>, RepositoryError>>()
+    Ok(text
+        .split_whitespace()
+        .filter_map(|t| RepositoryType::from_str(t).ok())
+        .collect())
 }
 
 fn serialize_types(files: &HashSet<RepositoryType>) -> String {
@@ -106,19 +110,17 @@ fn serialize_types(files: &HashSet<RepositoryType>) -> String {
         .join("\n")
 }
 
-fn deserialize_uris(text: &str) -> Result<Vec<Url>, String> {
-    // TODO: bad error type
-    text.split_whitespace()
-        .map(Url::from_str)
-        .collect::<Result<Vec<Url>, _>>()
-        .map_err(|e| e.to_string()) // TODO: bad error type
+/// URIs which are not absolute are kept as they are, so that they can be reported by
+/// `SourcesList::validate`.
+fn deserialize_uris(text: &str) -> Result<Vec<String>, String> {
+    Ok(text
+        .split_whitespace()
+        .map(|uri| Url::from_str(uri).map_or_else(|_| uri.to_owned(), String::from))
+        .collect())
 }
 
-fn serialize_uris(uris: &[Url]) -> String {
-    uris.iter()
-        .map(|u| u.as_str())
-        .collect::<Vec<&str>>()
-        .join(" ")
+fn serialize_uris(uris: &[String]) -> String {
+    uris.join(" ")
 }
 
 fn deserialize_string_chain(text: &str) -> Result<Vec<String>, String> {
@@ -194,7 +196,7 @@ pub struct Repository {
     pub types: HashSet<RepositoryType>, // consider alternative, closed set
     /// The address of the repository
     #[deb822(field = "URIs", deserialize_with = deserialize_uris, serialize_with = serialize_uris)]
-    pub uris: Vec<Url>, // according to Debian that's URI, but this type is more advanced than URI from `http` crate
+    pub uris: Vec<String>, // according to Debian that's URI, and may not be absolute
     /// The distribution name as codename or suite type (like `stable` or `testing`)
     #[deb822(field = "Suites", deserialize_with = deserialize_string_chain, serialize_with = serialize_string_chain)]
     pub suites: Vec<String>,
@@ -212,22 +214,25 @@ pub struct Repository {
     #[deb822(field = "Targets", deserialize_with = deserialize_string_chain, serialize_with = serialize_string_chain)]
     pub targets: Option<Vec<String>>,
     /// (Optional) Controls if APT should try PDiffs instead of downloading indexes entirely; if not set defaults to configuration option `Acquire::PDiffs`
-    #[deb822(field = "PDiffs", deserialize_with = deserialize_yesno)]
-    pub pdiffs: Option<bool>,
+    ///
+    /// This and the following yes/no fields are kept as written, so that invalid values are
+    /// reported by `SourcesList::validate` rather than rejected when parsing.
+    #[deb822(field = "PDiffs")]
+    pub pdiffs: Option<String>,
     /// (Optional) Controls if APT should try to acquire indexes via a URI constructed from a hashsum of the expected file
     #[deb822(field = "By-Hash")]
-    pub by_hash: Option<YesNoForce>,
+    pub by_hash: Option<String>,
     /// (Optional) If yes circumvents parts of `apt-secure`, don't thread lightly
     #[deb822(field = "Allow-Insecure")]
-    pub allow_insecure: Option<bool>, // TODO: redundant option, not present = default no
+    pub allow_insecure: Option<String>, // TODO: redundant option, not present = default no
     /// (Optional) If yes circumvents parts of `apt-secure`, don't thread lightly
     #[deb822(field = "Allow-Weak")]
-    pub allow_weak: Option<bool>, // TODO: redundant option, not present = default no
+    pub allow_weak: Option<String>, // TODO: redundant option, not present = default no
     /// (Optional) If yes circumvents parts of `apt-secure`, don't thread lightly
     #[deb822(field = "Allow-Downgrade-To-Insecure")]
-    pub allow_downgrade_to_insecure: Option<bool>, // TODO: redundant option, not present = default no
+    pub allow_downgrade_to_insecure: Option<String>, // TODO: redundant option, not present = default no
     /// (Optional) If set forces whether APT considers source as rusted or no (default not present is a third state)
-    #[deb822(field = "Trusted")]
+    #[deb822(field = "Trusted", deserialize_with = deserialize_yesno, serialize_with = serializer_yesno)]
     pub trusted: Option<bool>,
     /// (Optional) Contains either absolute path to GPG keyring or embedded GPG public key block, if not set APT uses all trusted keys;
     /// I can't find example of using with fingerprints
@@ -288,6 +293,7 @@ impl std::str::FromStr for Repositories {
     }
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Repositories {
     fn to_string(&self) -> String {
         self.0
@@ -311,10 +317,9 @@ impl Deref for Repositories {
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashSet, str::FromStr};
+    use std::collections::HashSet;
 
     use indoc::indoc;
-    use url::Url;
 
     use crate::deb822::{signature::Signature, Repositories, Repository, RepositoryType};
 
@@ -391,7 +396,7 @@ mod tests {
             enabled: Some(true), // TODO: looks odd, as only `Enabled: no` in meaningful
             types: HashSet::from([RepositoryType::Binary]),
             architectures: vec!["arm64".to_owned()].into(),
-            uris: vec![Url::from_str("https://deb.debian.org/debian").unwrap()],
+            uris: vec!["https://deb.debian.org/debian".to_owned()],
             suites: vec!["jammy".to_owned()],
             components: vec!["main".to_owned()].into(),
             signature: None,
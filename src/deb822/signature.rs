@@ -1,16 +1,138 @@
 //! A module implementing `Signature` type that holds info about variants of the signature key used by the repository
 
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use crate::deb822::error::RepositoryError;
 
 /// A type to store
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::schema::SignatureSchema",
+        try_from = "crate::schema::SignatureSchema"
+    )
+)]
 pub enum Signature {
     /// The PGP key is stored inside the `.sources` files
+    ///
+    /// The key is kept as plain armored text, with blank lines rather than the ` .` markers
+    /// of deb822 continuation lines.
     KeyBlock(String), // TODO: shall we validate PGP Public Key?
     /// The public key is store in a file of the given path
-    KeyPath(Vec<PathBuf>), // TODO: man page specifies fingerprints, but there's no example
+    KeyPath(Vec<PathBuf>),
+    /// A list of keys given by fingerprint, possibly mixed with key files
+    ///
+    /// Lists made only of paths are stored as `KeyPath` instead.
+    Keys(Vec<KeyReference>),
+}
+
+/// A fingerprint of an OpenPGP key, as accepted by `Signed-By`.
+///
+/// The fingerprint is stored in uppercase. A trailing `!` pins the exact (sub)key, rather than
+/// any subkey of the primary key with this fingerprint.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Fingerprint {
+    hex: String,
+    exact: bool,
+}
+
+impl Fingerprint {
+    /// The hexadecimal digits of the fingerprint, in uppercase.
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+
+    /// Whether the fingerprint ends with `!`, which only accepts this exact (sub)key.
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// Whether the fingerprint identifies the given key, given by its hexadecimal fingerprint.
+    pub fn matches(&self, fingerprint: &str) -> bool {
+        self.hex.eq_ignore_ascii_case(fingerprint)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = RepositoryError;
+
+    /// Parses a v4 fingerprint of 40 hexadecimal digits, or a v5/v6 fingerprint of 64 digits.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (hex, exact) = match text.strip_suffix('!') {
+            Some(hex) => (hex, true),
+            None => (text, false),
+        };
+
+        if !matches!(hex.len(), 40 | 64) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(RepositoryError::InvalidFingerprint);
+        }
+
+        Ok(Fingerprint {
+            hex: hex.to_ascii_uppercase(),
+            exact,
+        })
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.hex)?;
+        if self.exact {
+            f.write_str("!")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single key of a `Signed-By` list.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum KeyReference {
+    /// A keyring file
+    Path(PathBuf),
+    /// A key to be found in the trusted keyrings
+    Fingerprint(Fingerprint),
+}
+
+impl KeyReference {
+    /// Values containing a slash are paths, as apt does. Other values are fingerprints if
+    /// they are well-formed, and otherwise kept as paths to be reported by
+    /// `SourcesList::validate`.
+    pub(crate) fn parse(text: &str) -> Self {
+        if !text.contains('/') {
+            if let Ok(fingerprint) = text.parse() {
+                return KeyReference::Fingerprint(fingerprint);
+            }
+        }
+
+        KeyReference::Path(PathBuf::from(text))
+    }
+}
+
+impl fmt::Display for KeyReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyReference::Path(path) => write!(f, "{}", path.to_string_lossy()),
+            KeyReference::Fingerprint(fingerprint) => write!(f, "{}", fingerprint),
+        }
+    }
+}
+
+/// How the keys of an entry are pinned.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum KeyPinning {
+    /// The key is embedded in the `.sources` file
+    Embedded,
+    /// Only keyring files are given
+    Files,
+    /// Only fingerprints are given
+    Fingerprints,
+    /// Both keyring files and fingerprints are given
+    Mixed,
 }
 
 impl std::str::FromStr for Signature {
@@ -22,13 +144,23 @@ impl std::str::FromStr for Signature {
         // It's quite confusing, but let it be... we have to deal with reality.
         if text.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
             // If text is multiline, we assume PGP Public Key block
-            Ok(Signature::KeyBlock(text.to_string()))
+            let armor = text
+                .trim()
+                .lines()
+                .map(|line| match line.trim() {
+                    "." => "",
+                    line => line,
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            Ok(Signature::KeyBlock(armor))
         } else {
-            let mut paths = vec![];
-            for i in text.trim().split_ascii_whitespace() {
-                paths.push(PathBuf::from(i));
-            }
-            Ok(Signature::KeyPath(paths))
+            Ok(Signature::from_keys(
+                text.trim()
+                    .split_ascii_whitespace()
+                    .map(KeyReference::parse),
+            ))
         }
 
         // if let Some((name, rest)) = text.split_once('\n') {
@@ -46,21 +178,109 @@ impl std::str::FromStr for Signature {
     }
 }
 
+impl Signature {
+    /// Builds a signature from a list of keys, using `KeyPath` when there are only paths.
+    pub fn from_keys<I: IntoIterator<Item = KeyReference>>(keys: I) -> Self {
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        if keys.iter().all(|k| matches!(k, KeyReference::Path(_))) {
+            Signature::KeyPath(
+                keys.into_iter()
+                    .filter_map(|k| match k {
+                        KeyReference::Path(path) => Some(path),
+                        KeyReference::Fingerprint(_) => None,
+                    })
+                    .collect(),
+            )
+        } else {
+            Signature::Keys(keys)
+        }
+    }
+
+    /// The keys listed by this signature, or `None` for an embedded key block.
+    pub fn keys(&self) -> Option<Vec<KeyReference>> {
+        match self {
+            Signature::KeyBlock(_) => None,
+            Signature::KeyPath(paths) => {
+                Some(paths.iter().cloned().map(KeyReference::Path).collect())
+            }
+            Signature::Keys(keys) => Some(keys.clone()),
+        }
+    }
+
+    /// The fingerprints listed by this signature.
+    pub fn fingerprints(&self) -> impl Iterator<Item = &Fingerprint> {
+        let keys = match self {
+            Signature::Keys(keys) => keys.as_slice(),
+            _ => &[],
+        };
+
+        keys.iter().filter_map(|k| match k {
+            KeyReference::Fingerprint(fingerprint) => Some(fingerprint),
+            KeyReference::Path(_) => None,
+        })
+    }
+
+    /// How the keys are pinned by this signature.
+    pub fn pinning(&self) -> KeyPinning {
+        match self {
+            Signature::KeyBlock(_) => KeyPinning::Embedded,
+            Signature::KeyPath(_) => KeyPinning::Files,
+            Signature::Keys(keys) => {
+                if keys
+                    .iter()
+                    .all(|k| matches!(k, KeyReference::Fingerprint(_)))
+                {
+                    KeyPinning::Fingerprints
+                } else {
+                    KeyPinning::Mixed
+                }
+            }
+        }
+    }
+
+    /// The value of the `signed-by` option in one-line style entries.
+    ///
+    /// Embedded key blocks cannot be written in the one-line style, so `None` is returned.
+    pub fn to_option_value(&self) -> Option<String> {
+        match self {
+            Signature::KeyBlock(_) => None,
+            Signature::KeyPath(paths) => Some(
+                paths
+                    .iter()
+                    .map(|p| p.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            Signature::Keys(keys) => Some(
+                keys.iter()
+                    .map(|k| k.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        }
+    }
+}
+
 impl std::fmt::Display for Signature {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Signature::KeyBlock(text) => write!(f, "\n{}", text),
-            Signature::KeyPath(paths) => {
-                if paths.len() == 1 {
-                    write!(f, " {}", paths[0].to_string_lossy())?;
+            // Every line is folded as a continuation line, blank ones being written as ` .`.
+            Signature::KeyBlock(armor) => {
+                for line in armor.lines() {
+                    match line.trim() {
+                        "" => write!(f, "\n .")?,
+                        line => write!(f, "\n {}", line)?,
+                    }
+                }
+                Ok(())
+            }
+            Signature::KeyPath(_) | Signature::Keys(_) => {
+                let keys = self.keys().unwrap_or_default();
+                if keys.len() == 1 {
+                    write!(f, " {}", keys[0])?;
                 } else {
-                    writeln!(f)?;
-                    for (idx, p) in paths.iter().enumerate() {
-                        if idx != paths.len() - 1 {
-                            writeln!(f, " {}", p.to_string_lossy())?;
-                        } else {
-                            write!(f, " {}", p.to_string_lossy())?;
-                        }
+                    for k in &keys {
+                        write!(f, "\n {}", k)?;
                     }
                 }
                 Ok(())
@@ -68,3 +288,67 @@ impl std::fmt::Display for Signature {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPR: &str = "A7236886F3CCCAAD148A27F80E98404D386FA1D9";
+
+    #[test]
+    fn fingerprint() {
+        let exact = format!("{}!", FPR.to_ascii_lowercase());
+        let fingerprint = exact.parse::<Fingerprint>().unwrap();
+        assert_eq!(fingerprint.hex(), FPR);
+        assert!(fingerprint.is_exact());
+        assert_eq!(fingerprint.to_string(), format!("{}!", FPR));
+
+        assert!("386FA1D9".parse::<Fingerprint>().is_err());
+        assert!(format!("{}X", &FPR[1..]).parse::<Fingerprint>().is_err());
+        assert!("0".repeat(64).parse::<Fingerprint>().is_ok());
+    }
+
+    #[test]
+    fn keys() {
+        let paths = "/usr/share/keyrings/debian-archive-keyring.gpg"
+            .parse::<Signature>()
+            .unwrap();
+        assert!(matches!(paths, Signature::KeyPath(_)));
+        assert_eq!(paths.pinning(), KeyPinning::Files);
+
+        let fingerprints = format!("{} {}!", FPR, FPR).parse::<Signature>().unwrap();
+        assert_eq!(fingerprints.pinning(), KeyPinning::Fingerprints);
+        assert_eq!(fingerprints.fingerprints().count(), 2);
+
+        let mixed = format!("/etc/apt/keyrings/example.gpg\n {}!", FPR)
+            .parse::<Signature>()
+            .unwrap();
+        assert_eq!(mixed.pinning(), KeyPinning::Mixed);
+        assert_eq!(
+            mixed.to_option_value().unwrap(),
+            format!("/etc/apt/keyrings/example.gpg,{}!", FPR)
+        );
+        assert_eq!(
+            mixed.to_string(),
+            format!("\n /etc/apt/keyrings/example.gpg\n {}!", FPR)
+        );
+    }
+
+    #[test]
+    fn key_block() {
+        // The key may start on the line of the field, and markers may carry extra spaces.
+        let value = "-----BEGIN PGP PUBLIC KEY BLOCK-----\n . \nmDMEY865\n-----END PGP PUBLIC KEY BLOCK-----";
+        let signature = value.parse::<Signature>().unwrap();
+        assert_eq!(
+            signature,
+            Signature::KeyBlock(
+                "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmDMEY865\n-----END PGP PUBLIC KEY BLOCK-----"
+                    .into()
+            )
+        );
+        assert_eq!(
+            signature.to_string(),
+            "\n -----BEGIN PGP PUBLIC KEY BLOCK-----\n .\n mDMEY865\n -----END PGP PUBLIC KEY BLOCK-----"
+        );
+    }
+}
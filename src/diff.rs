@@ -0,0 +1,435 @@
+//! Comparison of two versions of a sources file, and preview of the changes to write.
+
+use crate::duplicates::normalized_url;
+use crate::sources_list::origin;
+use crate::{FileOperation, SourceEntry, SourceFormat, SourcesList, SourcesLists};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A difference between two versions of a list of entries.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum EntryChange {
+    /// The entry only exists in the new version.
+    Added(SourceEntry),
+    /// The entry only exists in the old version.
+    Removed(SourceEntry),
+    /// The entry fetches the same target in both versions, but differs otherwise.
+    Modified { old: SourceEntry, new: SourceEntry },
+}
+
+/// Compares two versions of a list of entries.
+///
+/// Entries are matched by their type, URI and suite, so that an entry whose components or
+/// options were changed is reported as modified. The remaining entries are then matched by
+/// their type and URI, in order, so that an entry whose suite was changed is also reported
+/// as modified. Changes are listed in the order of the new entries, followed by the removed
+/// entries.
+pub fn diff_entries<'a, O, N>(old: O, new: N) -> Vec<EntryChange>
+where
+    O: IntoIterator<Item = &'a SourceEntry>,
+    N: IntoIterator<Item = &'a SourceEntry>,
+{
+    let mut old = old.into_iter().map(Some).collect::<Vec<_>>();
+    let new = new.into_iter().collect::<Vec<_>>();
+
+    let mut take = |entry: &SourceEntry, same: fn(&SourceEntry, &SourceEntry) -> bool| {
+        old.iter_mut()
+            .find(|o| o.is_some_and(|o| same(o, entry)))
+            .and_then(Option::take)
+    };
+
+    let mut matched = new
+        .iter()
+        .map(|entry| take(entry, SourceEntry::same_target))
+        .collect::<Vec<_>>();
+
+    for (entry, matched) in new.iter().zip(&mut matched) {
+        if matched.is_none() {
+            *matched = take(entry, |a, b| {
+                a.source == b.source && normalized_url(&a.url) == normalized_url(&b.url)
+            });
+        }
+    }
+
+    let mut changes = Vec::new();
+    for (entry, matched) in new.into_iter().zip(matched) {
+        match matched {
+            Some(o) if o == entry => (),
+            Some(o) => changes.push(EntryChange::Modified {
+                old: o.clone(),
+                new: entry.clone(),
+            }),
+            None => changes.push(EntryChange::Added(entry.clone())),
+        }
+    }
+
+    changes.extend(old.into_iter().flatten().cloned().map(EntryChange::Removed));
+    changes
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LineOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Pairs the lines of both texts, keeping the longest common subsequence of lines.
+fn line_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(LineOp, &'a str)> {
+    // The length of the common subsequence of the suffixes starting at each pair of lines.
+    let width = new.len() + 1;
+    let mut common = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i * width + j] = if old[i] == new[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((LineOp::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            ops.push((LineOp::Delete, old[i]));
+            i += 1;
+        } else {
+            ops.push((LineOp::Insert, new[j]));
+            j += 1;
+        }
+    }
+
+    ops
+}
+
+/// Formats the changes from one text to another as a unified diff, with the given number of
+/// lines of context around each change.
+///
+/// Returns an empty string if both texts are equal.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> String {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let ops = line_ops(&old_lines, &new_lines);
+
+    // Groups the changes which are close enough to share their context into hunks.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (pos, _) in ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != LineOp::Equal)
+    {
+        let start = pos.saturating_sub(context);
+        let end = (pos + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = String::new();
+    if hunks.is_empty() {
+        return out;
+    }
+
+    let _ = writeln!(out, "--- {}", old_name);
+    let _ = writeln!(out, "+++ {}", new_name);
+
+    let range = |start: usize, count: usize| match count {
+        0 => format!("{},0", start.saturating_sub(1)),
+        1 => start.to_string(),
+        _ => format!("{},{}", start, count),
+    };
+
+    let (mut old_line, mut new_line, mut pos) = (1, 1, 0);
+    for (start, end) in hunks {
+        for (op, _) in &ops[pos..start] {
+            match op {
+                LineOp::Equal => (old_line, new_line) = (old_line + 1, new_line + 1),
+                LineOp::Delete => old_line += 1,
+                LineOp::Insert => new_line += 1,
+            }
+        }
+
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|(op, _)| *op != LineOp::Insert).count();
+        let new_count = hunk.iter().filter(|(op, _)| *op != LineOp::Delete).count();
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_line, old_count),
+            range(new_line, new_count)
+        );
+
+        for (op, line) in hunk {
+            out.push(match op {
+                LineOp::Equal => ' ',
+                LineOp::Delete => '-',
+                LineOp::Insert => '+',
+            });
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+
+        old_line += old_count;
+        new_line += new_count;
+        pos = end;
+    }
+
+    out
+}
+
+/// The change that `SourcesLists::write_sync` will make to a file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FilePreview {
+    /// The path of the file once written, or its current path if it will be removed.
+    pub path: PathBuf,
+    /// The current path of the file, if it will be renamed.
+    pub renamed_from: Option<PathBuf>,
+    /// The current contents of the file, or `None` if it does not exist.
+    pub original: Option<String>,
+    /// The contents of the file once written, or `None` if it will be removed.
+    pub new: Option<String>,
+    /// The changes made to the entries of the file.
+    pub changes: Vec<EntryChange>,
+}
+
+impl FilePreview {
+    /// Formats the change as a unified diff, with three lines of context.
+    pub fn unified_diff(&self) -> String {
+        let name = |path: &Path| path.display().to_string();
+        let old_name = match &self.original {
+            Some(_) => name(self.renamed_from.as_deref().unwrap_or(&self.path)),
+            None => "/dev/null".to_owned(),
+        };
+        let new_name = match &self.new {
+            Some(_) => name(&self.path),
+            None => "/dev/null".to_owned(),
+        };
+
+        let (original, new) = (
+            self.original.as_deref().unwrap_or_default(),
+            self.new.as_deref().unwrap_or_default(),
+        );
+
+        let mut diff = unified_diff(original, new, &old_name, &new_name, 3);
+        if diff.is_empty() && self.renamed_from.is_some() {
+            diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+        }
+
+        diff
+    }
+}
+
+/// The entries of a version of a file, if it can be parsed.
+fn parsed_entries(
+    path: &Path,
+    text: Option<&str>,
+    format: Option<SourceFormat>,
+) -> Vec<SourceEntry> {
+    let (Some(text), Some(format)) = (text, format) else {
+        return Vec::new();
+    };
+
+    SourcesList::parse_as(path, text, format)
+        .map(|list| list.source_entries().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// The contents of a file as text, or a note of their size if they are binary, as keyrings
+/// may be.
+fn as_text(data: Vec<u8>) -> String {
+    String::from_utf8(data)
+        .unwrap_or_else(|e| format!("[{} bytes of binary data]\n", e.as_bytes().len()))
+}
+
+impl SourcesLists {
+    /// Describes what `SourcesLists::write_sync` will write, remove and rename, without
+    /// touching the disk.
+    ///
+    /// Files whose contents and path are unchanged are omitted.
+    pub fn preview(&self) -> Vec<FilePreview> {
+        let read = |path: &Path| fs::read(path).ok().map(as_text);
+        let mut previews = Vec::new();
+
+        let written = self
+            .modified
+            .iter()
+            .filter_map(|path| self.iter().find(|list| list.path == *path))
+            .chain(self.detached.iter().map(|(list, _)| list))
+            .collect::<Vec<_>>();
+
+        for list in &written {
+            let from = origin(&self.operations, &list.path);
+            let original = read(from);
+            let new = list.to_string();
+            if original.as_deref() == Some(new.as_str()) && from == list.path {
+                continue;
+            }
+
+            let changes = diff_entries(
+                &parsed_entries(from, original.as_deref(), Some(list.format())),
+                list.source_entries(),
+            );
+
+            previews.push(FilePreview {
+                path: list.path.clone(),
+                renamed_from: (from != list.path).then(|| from.to_path_buf()),
+                original,
+                new: Some(new),
+                changes,
+            });
+        }
+
+        for (pos, operation) in self.operations.iter().enumerate() {
+            let later = &self.operations[pos + 1..];
+            match operation {
+                FileOperation::Rename { from, to } => {
+                    // Only the final path of a file which is not rewritten is shown.
+                    let moved_again = later.iter().any(|op| match op {
+                        FileOperation::Rename { from, .. } => from == to,
+                        FileOperation::Remove { path } | FileOperation::Write { path, .. } => {
+                            path == to
+                        }
+                    });
+                    if moved_again || written.iter().any(|list| list.path == *to) {
+                        continue;
+                    }
+
+                    let from = origin(&self.operations[..pos], from);
+                    let Some(original) = read(from) else {
+                        continue;
+                    };
+
+                    previews.push(FilePreview {
+                        path: to.clone(),
+                        renamed_from: Some(from.to_path_buf()),
+                        new: Some(original.clone()),
+                        original: Some(original),
+                        changes: Vec::new(),
+                    });
+                }
+                FileOperation::Remove { path } => {
+                    if written.iter().any(|list| list.path == *path) {
+                        continue;
+                    }
+
+                    let from = origin(&self.operations[..pos], path);
+                    let Some(original) = read(from) else {
+                        continue;
+                    };
+
+                    let changes = parsed_entries(
+                        from,
+                        Some(&original),
+                        SourceFormat::from_path(path).or_else(|| SourceFormat::from_path(from)),
+                    )
+                    .into_iter()
+                    .map(EntryChange::Removed)
+                    .collect();
+
+                    previews.push(FilePreview {
+                        path: from.to_path_buf(),
+                        renamed_from: None,
+                        original: Some(original),
+                        new: None,
+                        changes,
+                    });
+                }
+                FileOperation::Write { path, contents } => {
+                    // Only the last contents written to a file are shown.
+                    let rewritten = later
+                        .iter()
+                        .any(|op| matches!(op, FileOperation::Write { path: p, .. } if p == path));
+                    if rewritten {
+                        continue;
+                    }
+
+                    previews.push(FilePreview {
+                        path: path.clone(),
+                        renamed_from: None,
+                        original: read(path),
+                        new: Some(as_text(contents.clone())),
+                        changes: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        previews
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk";
+
+        assert_eq!(
+            unified_diff(old, new, "old", "new", 1),
+            "--- old\n\
+             +++ new\n\
+             @@ -1,3 +1,3 @@\n\
+             \x20a\n\
+             -b\n\
+             +B\n\
+             \x20c\n\
+             @@ -10 +10,2 @@\n\
+             \x20j\n\
+             +k\n\
+             \\ No newline at end of file\n"
+        );
+        assert_eq!(
+            unified_diff("", "a\n", "/dev/null", "new", 3),
+            "--- /dev/null\n+++ new\n@@ -0,0 +1 @@\n+a\n"
+        );
+        assert!(unified_diff(old, old, "old", "new", 3).is_empty());
+    }
+
+    #[test]
+    fn entries() {
+        let entry = |line: &str| line.parse::<SourceEntry>().unwrap();
+        let old = [
+            entry("deb http://deb.debian.org/debian bookworm main"),
+            entry("deb http://deb.debian.org/debian bookworm-updates main"),
+            entry("deb http://example.org/repo stable main"),
+        ];
+        let new = [
+            entry("deb http://deb.debian.org/debian bookworm main contrib"),
+            entry("deb http://deb.debian.org/debian bookworm-updates main"),
+            entry("deb-src http://deb.debian.org/debian bookworm main"),
+        ];
+
+        assert_eq!(
+            diff_entries(&old, &new),
+            [
+                EntryChange::Modified {
+                    old: old[0].clone(),
+                    new: new[0].clone()
+                },
+                EntryChange::Added(new[2].clone()),
+                EntryChange::Removed(old[2].clone()),
+            ]
+        );
+    }
+}
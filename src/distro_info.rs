@@ -0,0 +1,552 @@
+//! Release knowledge loaded from `distro-info-data`.
+//!
+//! The `distro-info-data` package ships one CSV file per distribution in
+//! **/usr/share/distro-info**, listing every release together with its codename and support
+//! dates. A `ReleaseCatalogue` reads these files (or the snapshot embedded in this crate) so
+//! that suites such as `stable` can be resolved to a codename, the release following a given
+//! codename can be found, and entries pointing to releases which reached their end of life can
+//! be flagged.
+
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEBIAN_CSV: &str = include_str!("../data/distro-info/debian.csv");
+const UBUNTU_CSV: &str = include_str!("../data/distro-info/ubuntu.csv");
+
+/// The suite aliases that each distribution publishes, besides its series names.
+const ALIASES: &[(&str, &[&str])] = &[
+    (
+        "debian",
+        &["stable", "oldstable", "oldoldstable", "testing", "unstable"],
+    ),
+    ("ubuntu", &["devel"]),
+];
+
+/// A calendar date, as written in distro-info data (`YYYY-MM-DD`).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn new(year: u16, month: u8, day: u8) -> Self {
+        Date { year, month, day }
+    }
+
+    /// The current date in UTC, according to the system clock.
+    pub fn today() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        Self::from_unix_days((secs / 86400) as i64)
+    }
+
+    /// Converts a count of days since 1970-01-01 into a civil date.
+    pub(crate) fn from_unix_days(days: i64) -> Self {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as u16;
+
+        Date { year, month, day }
+    }
+}
+
+impl FromStr for Date {
+    type Err = DistroInfoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DistroInfoError::InvalidDate {
+            value: s.to_owned(),
+        };
+        let mut fields = s.trim().splitn(3, '-');
+        let mut next = || fields.next().ok_or_else(invalid);
+
+        let year = next()?.parse::<u16>().map_err(|_| invalid())?;
+        let month = next()?.parse::<u8>().map_err(|_| invalid())?;
+        let day = next()?.parse::<u8>().map_err(|_| invalid())?;
+
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+
+        Ok(Date { year, month, day })
+    }
+}
+
+/// The number of days of a month, accounting for leap years.
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// A release of a distribution, as described by a row of distro-info data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Release {
+    /// The distribution this release belongs to, such as `debian` or `ubuntu`.
+    pub distro: String,
+    /// The version number, such as `12` or `24.04 LTS`. Rolling suites have none.
+    pub version: Option<String>,
+    /// The human-readable codename, such as `Bookworm`.
+    pub codename: String,
+    /// The name used in apt sources, such as `bookworm`.
+    pub series: String,
+    pub created: Option<Date>,
+    pub release: Option<Date>,
+    /// The end of regular support.
+    pub eol: Option<Date>,
+    /// Additional support phases (`eol-lts`, `eol-esm`, ...) in file order.
+    pub extended: Vec<(String, Date)>,
+}
+
+impl Release {
+    /// Whether the release has been published on the given date.
+    pub fn is_released(&self, date: Date) -> bool {
+        self.release.is_some_and(|release| release <= date)
+    }
+
+    /// Whether the regular support of the release has ended on the given date.
+    pub fn is_eol(&self, date: Date) -> bool {
+        self.eol.is_some_and(|eol| eol <= date)
+    }
+
+    /// Whether the release is a rolling suite such as `sid`, without a version number.
+    pub fn is_rolling(&self) -> bool {
+        self.version.is_none()
+    }
+}
+
+/// A collection of releases for one or more distributions.
+#[derive(Clone, Debug, Default)]
+pub struct ReleaseCatalogue {
+    distros: BTreeMap<String, Vec<Release>>,
+}
+
+impl ReleaseCatalogue {
+    /// Creates a catalogue without any release information.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// A catalogue built from the Debian and Ubuntu data embedded in this crate.
+    pub fn builtin() -> Self {
+        let mut catalogue = Self::default();
+        catalogue
+            .add_csv("debian", DEBIAN_CSV)
+            .expect("embedded debian distro-info data is valid");
+        catalogue
+            .add_csv("ubuntu", UBUNTU_CSV)
+            .expect("embedded ubuntu distro-info data is valid");
+        catalogue
+    }
+
+    /// Reads every **/usr/share/distro-info/*.csv** file from the system.
+    pub fn scan() -> Result<Self, DistroInfoError> {
+        Self::scan_from_root("/")
+    }
+
+    /// Reads every **/usr/share/distro-info/*.csv** file below the root argument.
+    pub fn scan_from_root<P: AsRef<Path>>(root: P) -> Result<Self, DistroInfoError> {
+        let dir = root.as_ref().join("usr/share/distro-info");
+        let mut catalogue = Self::default();
+        if !dir.exists() {
+            return Ok(catalogue);
+        }
+
+        let read_err = |path: &Path, why| DistroInfoError::Read {
+            path: path.to_path_buf(),
+            why,
+        };
+
+        for entry in fs::read_dir(&dir).map_err(|why| read_err(&dir, why))? {
+            let path = entry.map_err(|why| read_err(&dir, why))?.path();
+            if path.extension().is_none_or(|e| e != "csv") {
+                continue;
+            }
+
+            let Some(distro) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            let data = fs::read_to_string(&path).map_err(|why| read_err(&path, why))?;
+            catalogue.add_csv(distro, &data)?;
+        }
+
+        Ok(catalogue)
+    }
+
+    /// Adds the releases described by distro-info CSV data for the given distribution.
+    ///
+    /// Releases already known under the same series are replaced.
+    pub fn add_csv(&mut self, distro: &str, data: &str) -> Result<(), DistroInfoError> {
+        let mut lines = data
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty());
+        let header = match lines.next() {
+            Some((_, header)) => header.split(',').map(str::trim).collect::<Vec<_>>(),
+            None => return Ok(()),
+        };
+
+        let column = |name: &'static str| {
+            header
+                .iter()
+                .position(|&h| h == name)
+                .ok_or(DistroInfoError::MissingColumn { column: name })
+        };
+
+        let version = column("version")?;
+        let codename = column("codename")?;
+        let series = column("series")?;
+        let created = column("created")?;
+        let release = column("release")?;
+        let eol = column("eol")?;
+
+        for (line_num, line) in lines {
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let field = |pos: usize| fields.get(pos).copied().filter(|f| !f.is_empty());
+            let date = |pos: usize| {
+                field(pos)
+                    .map(Date::from_str)
+                    .transpose()
+                    .map_err(|why| DistroInfoError::BadLine {
+                        line: line_num,
+                        why: Box::new(why),
+                    })
+            };
+
+            let series = field(series).ok_or_else(|| DistroInfoError::BadLine {
+                line: line_num,
+                why: Box::new(DistroInfoError::MissingColumn { column: "series" }),
+            })?;
+
+            let mut extended = Vec::new();
+            for (pos, name) in header.iter().enumerate().skip(eol + 1) {
+                if let Some(date) = date(pos)? {
+                    extended.push((name.to_string(), date));
+                }
+            }
+
+            self.insert(Release {
+                distro: distro.to_owned(),
+                version: field(version).map(String::from),
+                codename: field(codename).unwrap_or(series).to_owned(),
+                series: series.to_owned(),
+                created: date(created)?,
+                release: date(release)?,
+                eol: date(eol)?,
+                extended,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Adds a single release, replacing any release of the same distribution and series.
+    ///
+    /// Releases are kept in insertion order, which is expected to be chronological.
+    pub fn insert(&mut self, release: Release) {
+        let releases = self.distros.entry(release.distro.clone()).or_default();
+        match releases.iter_mut().find(|r| r.series == release.series) {
+            Some(existing) => *existing = release,
+            None => releases.push(release),
+        }
+    }
+
+    /// The distributions that are known to this catalogue.
+    pub fn distros(&self) -> impl Iterator<Item = &str> {
+        self.distros.keys().map(String::as_str)
+    }
+
+    /// The releases of a distribution, in chronological order.
+    pub fn releases(&self, distro: &str) -> &[Release] {
+        self.distros.get(distro).map_or(&[], Vec::as_slice)
+    }
+
+    /// Finds the release with the given series name in any distribution.
+    pub fn find(&self, series: &str) -> Option<&Release> {
+        self.distros
+            .values()
+            .flat_map(|releases| releases.iter())
+            .find(|r| r.series == series)
+    }
+
+    /// Resolves a suite or suite alias of a distribution into its release.
+    ///
+    /// Besides series names, the aliases of the distribution are understood, relative to the
+    /// given date: `stable`, `oldstable`, `oldoldstable`, `testing` and `unstable` for Debian,
+    /// and `devel` for Ubuntu. Other distributions have no aliases.
+    pub fn resolve_in(&self, distro: &str, suite: &str, date: Date) -> Option<&Release> {
+        let releases = self.releases(distro);
+        if let Some(release) = releases.iter().find(|r| r.series == suite) {
+            return Some(release);
+        }
+
+        let aliased = ALIASES
+            .iter()
+            .any(|&(name, aliases)| name == distro && aliases.contains(&suite));
+        if !aliased {
+            return None;
+        }
+
+        let mut released = releases
+            .iter()
+            .rev()
+            .filter(|r| !r.is_rolling() && r.is_released(date));
+
+        match suite {
+            "stable" => released.next(),
+            "oldstable" => released.nth(1),
+            "oldoldstable" => released.nth(2),
+            "unstable" => releases.iter().find(|r| r.series == "sid"),
+            "testing" | "devel" => releases.iter().find(|r| {
+                !r.is_rolling()
+                    && !r.is_released(date)
+                    && r.created.is_some_and(|created| created <= date)
+            }),
+            _ => None,
+        }
+    }
+
+    /// Resolves a suite or suite alias into its release in every known distribution.
+    ///
+    /// Series names are matched first, so that an alias is only considered when no
+    /// distribution has a release of that name. Each alias is resolved in the distributions
+    /// which publish it.
+    pub fn resolve_all(&self, suite: &str, date: Date) -> Vec<&Release> {
+        if let Some(release) = self.find(suite) {
+            return vec![release];
+        }
+
+        self.distros
+            .keys()
+            .filter_map(|distro| self.resolve_in(distro, suite, date))
+            .collect()
+    }
+
+    /// Resolves a suite or suite alias into its release, if only one distribution has it.
+    ///
+    /// Use `resolve_in` to resolve aliases such as `stable` within a given distribution.
+    pub fn resolve(&self, suite: &str, date: Date) -> Option<&Release> {
+        match self.resolve_all(suite, date).as_slice() {
+            [release] => Some(release),
+            _ => None,
+        }
+    }
+
+    /// Resolves a suite of the repository at the given URI.
+    ///
+    /// Aliases are only resolved within the distribution whose name appears in the URI, such
+    /// as `debian` in `http://deb.debian.org/debian`.
+    pub fn resolve_for(&self, suite: &str, url: &str, date: Date) -> Option<&Release> {
+        let mut releases = self.resolve_all(suite, date);
+        if self.find(suite).is_none() {
+            let url = url.to_ascii_lowercase();
+            releases.retain(|release| url.contains(&release.distro));
+        }
+
+        match releases.as_slice() {
+            [release] => Some(release),
+            _ => None,
+        }
+    }
+
+    /// The release that follows the given one within its distribution.
+    ///
+    /// Rolling suites such as `sid` are never considered to be successors.
+    pub fn successor(&self, release: &Release) -> Option<&Release> {
+        let releases = self.releases(&release.distro);
+        let pos = releases.iter().position(|r| r.series == release.series)?;
+        releases[pos + 1..].iter().find(|r| !r.is_rolling())
+    }
+
+    /// Resolves the release that an entry's suite points to, ignoring pockets such as
+    /// `-updates` or `/updates`.
+    pub fn release_of(&self, entry: &SourceEntry, date: Date) -> Option<&Release> {
+        self.resolve_for(entry.parsed_suite().codename()?, &entry.url, date)
+    }
+}
+
+impl SourcesLists {
+    /// Iterates on every entry which points to a release that reached its end of life on
+    /// the given date, along with the file it was found in and the matching release.
+    pub fn eol_entries<'a>(
+        &'a self,
+        catalogue: &'a ReleaseCatalogue,
+        date: Date,
+    ) -> impl Iterator<Item = (&'a SourcesList, &'a SourceEntry, &'a Release)> + 'a {
+        self.iter().flat_map(move |list| {
+            list.source_entries().filter_map(move |entry| {
+                catalogue
+                    .release_of(entry, date)
+                    .filter(|release| release.is_eol(date))
+                    .map(|release| (list, entry, release))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests;
+
+    fn date(s: &str) -> Date {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert_eq!(date("2024-02-29"), Date::new(2024, 2, 29));
+        assert_eq!(date("2000-02-29"), Date::new(2000, 2, 29));
+        for invalid in [
+            "2023-02-29",
+            "1900-02-29",
+            "2024-02-31",
+            "2024-04-31",
+            "2024-13-01",
+        ] {
+            assert!(invalid.parse::<Date>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn unix_days() {
+        assert_eq!(Date::from_unix_days(0), Date::new(1970, 1, 1));
+        assert_eq!(Date::from_unix_days(19_723), Date::new(2024, 1, 1));
+        assert_eq!(Date::from_unix_days(20_742), Date::new(2026, 10, 16));
+    }
+
+    #[test]
+    fn debian_aliases() {
+        let catalogue = ReleaseCatalogue::builtin();
+        let resolve = |suite, on| {
+            catalogue
+                .resolve_in("debian", suite, date(on))
+                .map(|r| r.series.as_str())
+        };
+
+        assert_eq!(resolve("stable", "2024-01-01"), Some("bookworm"));
+        assert_eq!(resolve("oldstable", "2024-01-01"), Some("bullseye"));
+        assert_eq!(resolve("oldoldstable", "2024-01-01"), Some("buster"));
+        assert_eq!(resolve("testing", "2024-01-01"), Some("trixie"));
+        assert_eq!(resolve("unstable", "2024-01-01"), Some("sid"));
+        assert_eq!(resolve("stable", "2025-09-01"), Some("trixie"));
+        assert_eq!(resolve("testing", "2025-09-01"), Some("forky"));
+        assert_eq!(resolve("nonexistent", "2025-09-01"), None);
+    }
+
+    #[test]
+    fn ambiguous_aliases() {
+        let catalogue = ReleaseCatalogue::builtin();
+        let on = date("2024-06-01");
+
+        // Aliases only resolve within the distributions which publish them.
+        assert_eq!(catalogue.resolve_all("stable", on).len(), 1);
+        assert_eq!(catalogue.resolve("stable", on).unwrap().series, "bookworm");
+        assert_eq!(catalogue.resolve("devel", on).unwrap().series, "oracular");
+        assert_eq!(catalogue.resolve("noble", on).unwrap().distro, "ubuntu");
+        assert_eq!(catalogue.resolve("unstable", on).unwrap().series, "sid");
+        assert_eq!(catalogue.resolve_in("ubuntu", "stable", on), None);
+        assert_eq!(catalogue.resolve_in("debian", "devel", on), None);
+
+        let series = |url| {
+            catalogue
+                .resolve_for("stable", url, on)
+                .map(|r| r.series.as_str())
+        };
+        assert_eq!(series("http://deb.debian.org/debian"), Some("bookworm"));
+        assert_eq!(series("http://archive.ubuntu.com/ubuntu"), None);
+        assert_eq!(series("https://example.org/repo"), None);
+    }
+
+    #[test]
+    fn successor_and_eol() {
+        let catalogue = ReleaseCatalogue::builtin();
+        let bookworm = catalogue.find("bookworm").unwrap();
+        assert_eq!(catalogue.successor(bookworm).unwrap().series, "trixie");
+        assert!(!bookworm.is_eol(date("2025-01-01")));
+        assert!(bookworm.is_eol(date("2026-07-01")));
+
+        let forky = catalogue.find("forky").unwrap();
+        assert_eq!(catalogue.successor(forky), None);
+
+        let jammy = catalogue.find("jammy").unwrap();
+        assert_eq!(catalogue.successor(jammy).unwrap().series, "kinetic");
+        assert_eq!(jammy.extended.len(), 2);
+    }
+
+    #[test]
+    fn custom_csv() {
+        let mut catalogue = ReleaseCatalogue::empty();
+        catalogue
+            .add_csv(
+                "aosc",
+                "version,codename,series,created,release,eol\n\
+                 ,Stable,stable,2015-01-01,2015-01-01\n\
+                 1,Old,old,2010-01-01,2010-02-01,2012-01-01\n",
+            )
+            .unwrap();
+
+        assert_eq!(
+            catalogue
+                .resolve("stable", date("2020-01-01"))
+                .unwrap()
+                .distro,
+            "aosc"
+        );
+        assert!(catalogue.find("old").unwrap().is_eol(date("2020-01-01")));
+        assert!(catalogue
+            .add_csv(
+                "broken",
+                "version,codename,series,created,release,eol\n1,A,a,soon,,\n"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn flag_eol_entries() {
+        let lists = tests::lists(&[(
+            "sources.list",
+            "deb http://deb.debian.org/debian buster main\n\
+             deb http://security.debian.org/debian-security buster/updates main\n\
+             deb http://deb.debian.org/debian bookworm main\n",
+        )]);
+
+        let catalogue = ReleaseCatalogue::builtin();
+        let eol = lists
+            .eol_entries(&catalogue, date("2025-01-01"))
+            .map(|(_, entry, release)| (entry.suite.as_str(), release.series.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            eol,
+            vec![("buster", "buster"), ("buster/updates", "buster")]
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! Detection of entries which apt would read more than once, or with conflicting options.
+//!
+//! apt warns when the same target is configured multiple times, and refuses to load the
+//! sources when options which apply to a whole release, such as `signed-by`, are given
+//! different values for the same URI and suite.
+
+use crate::source_deb822::same_stanza_but_type;
+use crate::sources_list::add_modified;
+use crate::{
+    EntryLocation, Severity, SourceEntry, SourceLine, SourceListType, SourcesList, SourcesLists,
+};
+use std::fmt::{self, Display, Formatter};
+use url::Url;
+
+/// Options besides `signed-by` which apply to a whole release, and thus must not conflict.
+const RELEASE_OPTIONS: &[&str] = &[
+    "check-valid-until",
+    "valid-until-min",
+    "valid-until-max",
+    "check-date",
+    "date-max-future",
+];
+
+/// How two entries overlap.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ConflictKind {
+    /// Both entries configure the same components, which apt fetches only once.
+    ///
+    /// The components are empty for flat repositories.
+    Duplicate { components: Vec<String> },
+    /// An option of the release is set to different values by each entry.
+    ConflictingOption {
+        name: String,
+        first: String,
+        second: String,
+    },
+}
+
+impl ConflictKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ConflictKind::Duplicate { .. } => Severity::Warning,
+            ConflictKind::ConflictingOption { .. } => Severity::Error,
+        }
+    }
+}
+
+/// Two entries which refer to the same release.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct EntryConflict {
+    /// The entry which is read first by apt.
+    pub first: EntryLocation,
+    pub second: EntryLocation,
+    pub kind: ConflictKind,
+}
+
+impl EntryConflict {
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl Display for EntryConflict {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match &self.kind {
+            ConflictKind::Duplicate { components } if components.is_empty() => write!(
+                fmt,
+                "{} is configured multiple times, in {}",
+                self.first, self.second
+            ),
+            ConflictKind::Duplicate { components } => write!(
+                fmt,
+                "components {} of {} are configured multiple times, in {}",
+                components.join(" "),
+                self.first,
+                self.second
+            ),
+            ConflictKind::ConflictingOption {
+                name,
+                first,
+                second,
+            } => write!(
+                fmt,
+                "conflicting values set for option {} in {} and {}: {} != {}",
+                name, self.first, self.second, first, second
+            ),
+        }
+    }
+}
+
+/// Which copy of a duplicated entry is kept by `SourcesLists::dedup_entries`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicatePreference {
+    /// Keep the copy which apt reads first.
+    #[default]
+    First,
+    /// Keep the copy which apt reads last.
+    Last,
+    /// Keep the copy from a deb822 file, or else the first copy.
+    Deb822,
+}
+
+/// The URI of an entry, with its scheme and host in lowercase and without a trailing slash.
+pub(crate) fn normalized_url(url: &str) -> String {
+    let url = match Url::parse(url) {
+        Ok(url) => url.to_string(),
+        Err(_) => url.to_owned(),
+    };
+
+    url.trim_end_matches('/').to_owned()
+}
+
+/// Whether two entries refer to the same release of the same repository.
+fn same_release(a: &SourceEntry, b: &SourceEntry) -> bool {
+    a.suite == b.suite && normalized_url(&a.url) == normalized_url(&b.url)
+}
+
+/// Whether two entries fetch some of the same targets, and which components they share.
+fn shared_components(a: &SourceEntry, b: &SourceEntry) -> Option<Vec<String>> {
+    if a.source != b.source || !same_release(a, b) {
+        return None;
+    }
+
+    let archs_overlap = match (&a.archs, &b.archs) {
+        (Some(a), Some(b)) => a.iter().any(|arch| b.contains(arch)),
+        _ => true,
+    };
+
+    let components = a
+        .components
+        .iter()
+        .filter(|c| b.components.contains(c))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let flat = a.components.is_empty() && b.components.is_empty();
+    (archs_overlap && (flat || !components.is_empty())).then_some(components)
+}
+
+/// The options of the release which the entries set to different values.
+fn conflicting_options(a: &SourceEntry, b: &SourceEntry) -> Vec<ConflictKind> {
+    let mut conflicts = Vec::new();
+    let describe = |entry: &SourceEntry| {
+        entry
+            .signed_by
+            .as_ref()
+            .map_or_else(|| "(none)".to_owned(), |s| s.to_string().trim().to_owned())
+    };
+
+    // apt only accepts a missing `signed-by` if the same release sets none.
+    if a.signed_by != b.signed_by {
+        conflicts.push(ConflictKind::ConflictingOption {
+            name: "signed-by".into(),
+            first: describe(a),
+            second: describe(b),
+        });
+    }
+
+    for name in RELEASE_OPTIONS {
+        if let (Some(first), Some(second)) = (a.option(name), b.option(name)) {
+            if first != second {
+                conflicts.push(ConflictKind::ConflictingOption {
+                    name: (*name).to_owned(),
+                    first: first.join(","),
+                    second: second.join(","),
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+impl SourcesList {
+    /// Removes the entry at the given index, returning it.
+    pub(crate) fn remove_at(&mut self, index: usize) -> Option<SourceEntry> {
+        match &mut self.entries {
+            SourceListType::SourceLine(lines) => match lines.0.get(index) {
+                Some(SourceLine::Entry(_)) => match lines.0.remove(index) {
+                    SourceLine::Entry(entry) => Some(entry),
+                    _ => None,
+                },
+                _ => None,
+            },
+            SourceListType::Deb822(e) => (index < e.entries.len()).then(|| e.entries.remove(index)),
+        }
+    }
+}
+
+impl SourcesLists {
+    /// Finds the enabled entries which refer to the same release, in the order that apt reads
+    /// them.
+    ///
+    /// URIs are compared after normalization, and deb822 stanzas are compared after their
+    /// expansion into one entry per type, URI and suite.
+    pub fn find_conflicts(&self) -> Vec<EntryConflict> {
+        let entries = self
+            .located_entries()
+            .filter(|(_, entry)| entry.enabled)
+            .collect::<Vec<_>>();
+
+        let mut conflicts = Vec::new();
+        for (pos, (first, a)) in entries.iter().enumerate() {
+            for (second, b) in &entries[pos + 1..] {
+                if !same_release(a, b) {
+                    continue;
+                }
+
+                let mut push = |kind| {
+                    conflicts.push(EntryConflict {
+                        first: first.clone(),
+                        second: second.clone(),
+                        kind,
+                    })
+                };
+
+                if let Some(components) = shared_components(a, b) {
+                    push(ConflictKind::Duplicate { components });
+                }
+
+                // Expanded entries of a stanza share their options.
+                if first.path != second.path || !a.is_deb822 {
+                    conflicting_options(a, b).into_iter().for_each(&mut push);
+                }
+            }
+        }
+
+        conflicts
+    }
+
+    /// Removes the targets which are configured multiple times, keeping the preferred copy.
+    ///
+    /// Shared components are removed from the other copy, which is itself removed if it has
+    /// no component left. Conflicting options are left for the caller to resolve.
+    ///
+    /// The entries of a deb822 stanza only lose the components which every entry of the stanza
+    /// duplicates, so that the stanza is not split in two. Duplicates found in only some of its
+    /// suites are left in place, and still reported by `find_conflicts`.
+    ///
+    /// Returns the original locations of the entries which were modified or removed.
+    pub fn dedup_entries(&mut self, preference: DuplicatePreference) -> Vec<EntryLocation> {
+        // The components to drop from each entry, and the entries left without a component.
+        let mut drops: Vec<(EntryLocation, Vec<String>)> = Vec::new();
+        let mut removed: Vec<EntryLocation> = Vec::new();
+
+        for conflict in self.find_conflicts() {
+            let ConflictKind::Duplicate { components } = conflict.kind else {
+                continue;
+            };
+
+            let keep_second = match preference {
+                DuplicatePreference::First => false,
+                DuplicatePreference::Last => true,
+                DuplicatePreference::Deb822 => {
+                    let is_deb822 = |location| self.entry_at(location).is_some_and(|e| e.is_deb822);
+                    !is_deb822(&conflict.first) && is_deb822(&conflict.second)
+                }
+            };
+
+            let (kept, dropped) = if keep_second {
+                (conflict.second, conflict.first)
+            } else {
+                (conflict.first, conflict.second)
+            };
+
+            if removed.contains(&kept) || removed.contains(&dropped) {
+                continue;
+            }
+
+            let Some(entry) = self.entry_at(&dropped) else {
+                continue;
+            };
+
+            let index = match drops.iter().position(|(location, _)| *location == dropped) {
+                Some(index) => index,
+                None => {
+                    drops.push((dropped.clone(), Vec::new()));
+                    drops.len() - 1
+                }
+            };
+
+            let dropping = &mut drops[index].1;
+            for component in components {
+                if !dropping.contains(&component) {
+                    dropping.push(component);
+                }
+            }
+
+            if entry.components.iter().all(|c| dropping.contains(c)) {
+                removed.push(dropped);
+            }
+        }
+
+        // The entries of a stanza only lose what all of them lose.
+        let mut changed: Vec<EntryLocation> = Vec::new();
+        let mut reductions: Vec<(EntryLocation, Vec<String>)> = Vec::new();
+        let mut removals: Vec<EntryLocation> = Vec::new();
+        for (location, dropping) in &drops {
+            let Some(entry) = self.entry_at(location) else {
+                continue;
+            };
+
+            let stanza = if entry.is_deb822 {
+                self.located_entries()
+                    .filter(|(l, e)| l.path == location.path && same_stanza_but_type(e, entry))
+                    .map(|(l, _)| l)
+                    .collect::<Vec<_>>()
+            } else {
+                vec![location.clone()]
+            };
+
+            if stanza.iter().all(|l| removed.contains(l)) {
+                removals.push(location.clone());
+                changed.push(location.clone());
+                continue;
+            }
+
+            let shared = dropping
+                .iter()
+                .filter(|c| {
+                    stanza
+                        .iter()
+                        .all(|l| drops.iter().any(|(d, cs)| d == l && cs.contains(c)))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !shared.is_empty() {
+                reductions.push((location.clone(), shared));
+                changed.push(location.clone());
+            }
+        }
+
+        for (location, shared) in reductions {
+            let entry = self
+                .files
+                .iter_mut()
+                .find(|list| list.path == location.path)
+                .and_then(|list| list.entry_mut(location.index));
+            if let Some(entry) = entry {
+                entry.components.retain(|c| !shared.contains(c));
+                add_modified(&mut self.modified, &location.path);
+            }
+        }
+
+        // Entries are removed last, and from the end of each file, so that locations remain
+        // valid while deduplicating.
+        removals.sort_by_key(|location| std::cmp::Reverse(location.index));
+        for location in removals {
+            if let Some(list) = self.files.iter_mut().find(|l| l.path == location.path) {
+                list.remove_at(location.index);
+                add_modified(&mut self.modified, &location.path);
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, location};
+
+    const LIST: &str = "\
+deb http://deb.debian.org/debian bookworm main contrib
+deb http://DEB.debian.org/debian/ bookworm main
+deb [arch=i386] http://deb.debian.org/debian bookworm non-free
+deb [check-valid-until=no] http://example.org/repo ./
+";
+
+    const DEB822: &str = "\
+Types: deb deb-src
+URIs: http://deb.debian.org/debian
+Suites: bookworm
+Components: contrib non-free
+Signed-By: /usr/share/keyrings/debian-archive-keyring.gpg
+
+Types: deb
+URIs: http://example.org/repo
+Suites: ./
+Check-Valid-Until: yes
+";
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[("sources.list", LIST), ("debian.sources", DEB822)])
+    }
+
+    fn duplicate(components: &[&str]) -> ConflictKind {
+        ConflictKind::Duplicate {
+            components: components.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn conflicts() {
+        let signed_by = |second: &str| ConflictKind::ConflictingOption {
+            name: "signed-by".into(),
+            first: "(none)".into(),
+            second: second.into(),
+        };
+        let keyring = "/usr/share/keyrings/debian-archive-keyring.gpg";
+
+        let found = lists()
+            .find_conflicts()
+            .into_iter()
+            .map(|c| (c.first, c.second, c.kind))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            [
+                (
+                    location("sources.list", 0),
+                    location("sources.list", 1),
+                    duplicate(&["main"])
+                ),
+                (
+                    location("sources.list", 0),
+                    location("debian.sources", 0),
+                    duplicate(&["contrib"])
+                ),
+                (
+                    location("sources.list", 0),
+                    location("debian.sources", 0),
+                    signed_by(keyring)
+                ),
+                (
+                    location("sources.list", 0),
+                    location("debian.sources", 1),
+                    signed_by(keyring)
+                ),
+                (
+                    location("sources.list", 1),
+                    location("debian.sources", 0),
+                    signed_by(keyring)
+                ),
+                (
+                    location("sources.list", 1),
+                    location("debian.sources", 1),
+                    signed_by(keyring)
+                ),
+                (
+                    location("sources.list", 2),
+                    location("debian.sources", 0),
+                    duplicate(&["non-free"])
+                ),
+                (
+                    location("sources.list", 2),
+                    location("debian.sources", 0),
+                    signed_by(keyring)
+                ),
+                (
+                    location("sources.list", 2),
+                    location("debian.sources", 1),
+                    signed_by(keyring)
+                ),
+                (
+                    location("sources.list", 3),
+                    location("debian.sources", 2),
+                    duplicate(&[])
+                ),
+                (
+                    location("sources.list", 3),
+                    location("debian.sources", 2),
+                    ConflictKind::ConflictingOption {
+                        name: "check-valid-until".into(),
+                        first: "no".into(),
+                        second: "yes".into(),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn dedup() {
+        let mut lists = lists();
+        let changed = lists.dedup_entries(DuplicatePreference::Deb822);
+        assert_eq!(
+            changed,
+            [
+                location("sources.list", 1),
+                location("sources.list", 0),
+                location("sources.list", 2),
+                location("sources.list", 3),
+            ]
+        );
+
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb http://deb.debian.org/debian bookworm main\n"
+        );
+        assert_eq!(lists.modified, [lists.files[0].path.clone()]);
+        assert!(lists
+            .find_conflicts()
+            .iter()
+            .all(|c| !matches!(c.kind, ConflictKind::Duplicate { .. })));
+    }
+
+    #[test]
+    fn dedup_stanza() {
+        let stanza = "\
+Types: deb
+URIs: http://deb.debian.org/debian
+Suites: bookworm bookworm-updates
+Components: main contrib
+";
+        let lists =
+            |list: &str| tests::lists(&[("sources.list", list), ("debian.sources", stanza)]);
+
+        // Dropping main from one suite would split the stanza, so it is kept.
+        let mut partial = lists("deb http://deb.debian.org/debian bookworm main\n");
+        let original = partial.files[1].to_string();
+        assert!(partial.dedup_entries(DuplicatePreference::First).is_empty());
+        assert_eq!(partial.files[1].to_string(), original);
+        assert_eq!(partial.find_conflicts().len(), 1);
+
+        // Once every suite duplicates it, main is dropped from the whole stanza.
+        let mut whole = lists(
+            "deb http://deb.debian.org/debian bookworm main\n\
+             deb http://deb.debian.org/debian bookworm-updates main\n",
+        );
+        assert_eq!(
+            whole.dedup_entries(DuplicatePreference::First),
+            [location("debian.sources", 0), location("debian.sources", 1)]
+        );
+        assert_eq!(
+            whole.files[1].to_string(),
+            original.replace("main contrib", "contrib")
+        );
+
+        // And the stanza is removed once it has no component left.
+        let mut removed = lists(
+            "deb http://deb.debian.org/debian bookworm main contrib\n\
+             deb http://deb.debian.org/debian bookworm-updates main contrib\n",
+        );
+        removed.dedup_entries(DuplicatePreference::First);
+        assert_eq!(removed.files[1].to_string(), "");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{EntryLocation, SourceFormat};
 use std::io;
 use std::path::PathBuf;
 
@@ -18,8 +19,20 @@ pub enum SourceError {
     EntryWrite { path: PathBuf, why: io::Error },
     #[error("source file was not found")]
     FileNotFound,
+    #[error("source file {} already exists", path.display())]
+    FileExists { path: PathBuf },
+    #[error("source file {} is not empty", path.display())]
+    FileNotEmpty { path: PathBuf },
+    #[error("converting {} drops its comments, which cannot be restored", path.display())]
+    LossyConversion { path: PathBuf },
     #[error("Syntax Error: {}", why)]
     SyntaxError { why: String },
+    #[error("entry at {location} has changed since the plan was made")]
+    StaleEntry { location: EntryLocation },
+    #[error("cannot tell the format of source file {}", path.display())]
+    UnknownFormat { path: PathBuf },
+    #[error("source file {} is not in the {} format", path.display(), format)]
+    FormatMismatch { path: PathBuf, format: SourceFormat },
 }
 
 #[derive(Debug, Error)]
@@ -36,5 +49,91 @@ pub enum SourcesListError {
     Io(#[from] std::io::Error),
 }
 
+/// An error that may occur when loading distro-info release data.
+#[derive(Debug, Error)]
+pub enum DistroInfoError {
+    #[error("Failed to read distro-info data at {}: {}", path.display(), why)]
+    Read { path: PathBuf, why: io::Error },
+    #[error("distro-info data is missing the '{column}' column")]
+    MissingColumn { column: &'static str },
+    #[error("Parsing error on line {}: {}", line, why)]
+    BadLine {
+        line: usize,
+        why: Box<DistroInfoError>,
+    },
+    #[error("invalid date in distro-info data: '{value}'")]
+    InvalidDate { value: String },
+}
+
+/// An error that may occur when loading migration rules.
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("Failed to read migration rules at {}: {}", path.display(), why)]
+    Read { path: PathBuf, why: io::Error },
+    #[error("Syntax Error: {}", why)]
+    Syntax { why: String },
+    #[error("missing field in migration rule: '{field}'")]
+    MissingField { field: &'static str },
+    #[error("invalid field in migration rule '{rule}': '{value}' is invalid for '{field}'")]
+    InvalidValue {
+        rule: String,
+        field: &'static str,
+        value: String,
+    },
+}
+
+/// An error that may occur when parsing a change set.
+#[derive(Debug, Error)]
+pub enum ChangeSetError {
+    #[error("Syntax Error: {}", why)]
+    Syntax { why: String },
+    #[error("missing field in change set: '{field}'")]
+    MissingField { field: &'static str },
+    #[error("invalid field in change set: '{value}' is invalid for '{field}'")]
+    InvalidValue { field: &'static str, value: String },
+}
+
+/// An error that may occur when adding a repository.
+#[derive(Debug, Error)]
+pub enum AddRepositoryError {
+    #[error("'{input}' is neither a one-line entry, a deb822 stanza nor a PPA")]
+    Unrecognized { input: String },
+    #[error("a codename is required to add {ppa}")]
+    MissingCodename { ppa: String },
+    #[error("repository is already configured at {location}")]
+    Exists { location: EntryLocation },
+    #[error(transparent)]
+    Source(#[from] SourceError),
+    #[error(transparent)]
+    Key(#[from] KeyError),
+}
+
+/// An error that may occur when reading OpenPGP keys.
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("Failed to read keyring at {}: {}", path.display(), why)]
+    Read { path: PathBuf, why: io::Error },
+    #[error("invalid ASCII armor: {why}")]
+    Armor { why: String },
+    #[error("malformed OpenPGP data: {why}")]
+    Malformed { why: String },
+    #[error("unsupported OpenPGP data: {what}")]
+    Unsupported { what: String },
+    #[error("entry at {location} has no embedded key")]
+    NoEmbeddedKey { location: EntryLocation },
+    #[error("keyring already exists at {}", path.display())]
+    KeyringExists { path: PathBuf },
+    #[error("keyring at {} does not have the .{} extension of its format", path.display(), extension)]
+    KeyringExtension {
+        path: PathBuf,
+        extension: &'static str,
+    },
+    #[error("key {fingerprint} may still be needed by the entry at {location}")]
+    KeyInUse {
+        fingerprint: String,
+        location: EntryLocation,
+    },
+}
+
 /// Equivalent to `Result<T, SourceError>`.
 pub type SourceResult<T> = Result<T, SourceError>;
@@ -0,0 +1,279 @@
+//! Discovery and restoration of the sources files that apt ignores.
+//!
+//! Distribution upgrades, package managers and users leave copies of sources files with a
+//! suffix that apt does not read, such as `sources.list.distUpgrade` or
+//! `debian.sources.dpkg-old`. These are parsed as inactive lists, which can be compared with
+//! the active file and restored in its place.
+
+use crate::diff::{diff_entries, EntryChange};
+use crate::sources_list::add_modified;
+use crate::{
+    FileOperation, SourceError, SourceFormat, SourceResult, SourcesList, SourcesListError,
+    SourcesLists,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Why a sources file is ignored by apt.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum InactiveKind {
+    /// `.disabled`, set by `SourcesLists::disable_file` and by some tools.
+    Disabled,
+    /// `.save`, a copy made by `apt-add-repository` and the Ubuntu release upgrader.
+    Save,
+    /// `.distUpgrade`, the original kept by the Ubuntu release upgrader.
+    DistUpgrade,
+    /// `.bak`, a manual backup.
+    Backup,
+    /// `.dpkg-old`, the previous version of a conffile replaced by dpkg.
+    DpkgOld,
+    /// `.dpkg-dist`, the new version of a conffile which dpkg did not install.
+    DpkgDist,
+}
+
+impl InactiveKind {
+    const ALL: [InactiveKind; 6] = [
+        InactiveKind::Disabled,
+        InactiveKind::Save,
+        InactiveKind::DistUpgrade,
+        InactiveKind::Backup,
+        InactiveKind::DpkgOld,
+        InactiveKind::DpkgDist,
+    ];
+
+    /// The suffix appended to the name of the active file.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            InactiveKind::Disabled => ".disabled",
+            InactiveKind::Save => ".save",
+            InactiveKind::DistUpgrade => ".distUpgrade",
+            InactiveKind::Backup => ".bak",
+            InactiveKind::DpkgOld => ".dpkg-old",
+            InactiveKind::DpkgDist => ".dpkg-dist",
+        }
+    }
+
+    /// Splits the path of an inactive file into its kind and the path of the active file.
+    pub fn from_path(path: &Path) -> Option<(Self, PathBuf)> {
+        let name = path.file_name()?.to_str()?;
+        Self::ALL.into_iter().find_map(|kind| {
+            let active = name.strip_suffix(kind.suffix())?;
+            SourceFormat::from_path(active).map(|_| (kind, path.with_file_name(active)))
+        })
+    }
+}
+
+/// A sources file which apt ignores, and the active file that it is a variant of.
+#[derive(Clone, Debug)]
+pub struct InactiveSourcesList {
+    pub kind: InactiveKind,
+    /// The path of the active file, which may not exist.
+    pub active_path: PathBuf,
+    /// The entries of the inactive file, at its own path.
+    pub list: SourcesList,
+}
+
+impl InactiveSourcesList {
+    /// Lists the changes that restoring this file would make to the active file.
+    pub fn diff(&self, lists: &SourcesLists) -> Vec<EntryChange> {
+        let active = lists.iter().find(|list| list.path == self.active_path);
+        diff_entries(
+            active.into_iter().flat_map(SourcesList::source_entries),
+            self.list.source_entries(),
+        )
+    }
+}
+
+/// Lists the inactive variants of **etc/apt/sources.list** and of the files in
+/// **etc/apt/sources.list.d**, below the directory.
+pub(crate) fn inactive_sources_list<P: AsRef<Path>>(
+    dir: P,
+) -> Result<Vec<PathBuf>, SourcesListError> {
+    let dir = dir.as_ref();
+    let mut paths = Vec::new();
+
+    for sub in ["etc/apt", "etc/apt/sources.list.d"] {
+        let Ok(entries) = fs::read_dir(dir.join(sub)) else {
+            continue;
+        };
+
+        let mut found = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let Some((_, active)) = InactiveKind::from_path(&path) else {
+                continue;
+            };
+
+            // Only sources.list itself is read from etc/apt.
+            if sub == "etc/apt" && active.file_name().is_some_and(|n| n != "sources.list") {
+                continue;
+            }
+
+            found.push(path);
+        }
+
+        found.sort();
+        paths.extend(found);
+    }
+
+    Ok(paths)
+}
+
+impl SourcesLists {
+    /// Scans **/etc/apt** and **/etc/apt/sources.list.d** for the sources files which apt
+    /// ignores, such as disabled files and backups.
+    ///
+    /// Files which cannot be parsed are skipped.
+    pub fn scan_inactive() -> Result<Vec<InactiveSourcesList>, SourcesListError> {
+        Self::scan_inactive_from_root("/")
+    }
+
+    /// Scans **/etc/apt** and **/etc/apt/sources.list.d** for the sources files which apt
+    /// ignores, such as disabled files and backups. (from root argument)
+    ///
+    /// Files which cannot be parsed are skipped.
+    pub fn scan_inactive_from_root<P: AsRef<Path>>(
+        root: P,
+    ) -> Result<Vec<InactiveSourcesList>, SourcesListError> {
+        let inactive = inactive_sources_list(root)?
+            .into_iter()
+            .filter_map(|path| {
+                let (kind, active_path) = InactiveKind::from_path(&path)?;
+                let format = SourceFormat::from_path(&active_path)?;
+                let list = SourcesList::new_as(&path, format).ok()?;
+                Some(InactiveSourcesList {
+                    kind,
+                    active_path,
+                    list,
+                })
+            })
+            .collect();
+
+        Ok(inactive)
+    }
+
+    /// Replaces the active file with the contents of an inactive file, creating it if needed.
+    ///
+    /// A disabled file is removed once restored, whereas backups are kept. The changes are
+    /// written by `SourcesLists::write_sync`.
+    pub fn restore_file(&mut self, inactive: &InactiveSourcesList) -> SourceResult<()> {
+        let path = &inactive.active_path;
+        let entries = inactive.list.entries.clone();
+
+        match self.files.iter_mut().find(|list| list.path == *path) {
+            Some(list) if list.format() != inactive.list.format() => {
+                return Err(SourceError::FormatMismatch {
+                    path: path.clone(),
+                    format: inactive.list.format(),
+                })
+            }
+            Some(list) => list.entries = entries,
+            None => self.files.push(SourcesList {
+                path: path.clone(),
+                entries,
+            }),
+        }
+
+        add_modified(&mut self.modified, path);
+        if inactive.kind == InactiveKind::Disabled {
+            self.operations.push(FileOperation::Remove {
+                path: inactive.list.path.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempRoot;
+    use crate::EntryChange;
+
+    #[test]
+    fn kinds() {
+        assert_eq!(
+            InactiveKind::from_path(Path::new("/etc/apt/sources.list.distUpgrade")),
+            Some((InactiveKind::DistUpgrade, "/etc/apt/sources.list".into()))
+        );
+        assert_eq!(
+            InactiveKind::from_path(Path::new("debian.sources.dpkg-old")),
+            Some((InactiveKind::DpkgOld, "debian.sources".into()))
+        );
+        assert_eq!(InactiveKind::from_path(Path::new("notes.txt.bak")), None);
+        assert_eq!(InactiveKind::from_path(Path::new("debian.sources")), None);
+    }
+
+    #[test]
+    fn scan_and_restore() {
+        let root = TempRoot::new("inactive");
+        let dir = root.join("etc/apt/sources.list.d");
+        fs::create_dir_all(&dir).unwrap();
+
+        let bookworm = "deb http://deb.debian.org/debian bookworm main\n";
+        let trixie = "deb http://deb.debian.org/debian trixie main\n";
+        fs::write(root.join("etc/apt/sources.list"), trixie).unwrap();
+        fs::write(root.join("etc/apt/sources.list.distUpgrade"), bookworm).unwrap();
+        fs::write(root.join("etc/apt/trusted.gpg.bak"), "").unwrap();
+        fs::write(dir.join("extra.list.disabled"), bookworm).unwrap();
+        fs::write(dir.join("broken.sources.save"), "Types: rpm\n").unwrap();
+
+        let mut lists = SourcesLists::scan_from_root(&root).unwrap();
+        let inactive = SourcesLists::scan_inactive_from_root(&root).unwrap();
+        let found = inactive
+            .iter()
+            .map(|i| {
+                (
+                    i.kind,
+                    i.active_path.strip_prefix(&root).unwrap().to_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (InactiveKind::DistUpgrade, "etc/apt/sources.list".into()),
+                (
+                    InactiveKind::Disabled,
+                    "etc/apt/sources.list.d/extra.list".into()
+                ),
+            ]
+        );
+
+        let entry = |line: &str| line.parse().unwrap();
+        assert_eq!(
+            inactive[0].diff(&lists),
+            [EntryChange::Modified {
+                old: entry(trixie.trim()),
+                new: entry(bookworm.trim()),
+            }]
+        );
+        assert_eq!(
+            inactive[1].diff(&lists),
+            [EntryChange::Added(entry(bookworm.trim()))]
+        );
+
+        lists.restore_file(&inactive[0]).unwrap();
+        lists.restore_file(&inactive[1]).unwrap();
+        lists.write_sync().unwrap();
+
+        let read = |path: &Path| fs::read_to_string(path).ok();
+        let contents = (
+            read(&root.join("etc/apt/sources.list")),
+            read(&root.join("etc/apt/sources.list.distUpgrade")),
+            read(&dir.join("extra.list")),
+            read(&dir.join("extra.list.disabled")),
+        );
+
+        assert_eq!(
+            contents,
+            (
+                Some(bookworm.to_owned()),
+                Some(bookworm.to_owned()),
+                Some(bookworm.to_owned()),
+                None
+            )
+        );
+    }
+}
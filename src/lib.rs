@@ -6,18 +6,54 @@
 //! Active source entries will be parsed into `SourceEntry`'s, which can be handled or serialized
 //! back into text. Formatting of these lines are not preserved.
 
+mod add_repository;
+mod audit;
+mod changes;
 mod deb822;
+mod diff;
+mod distro_info;
+mod duplicates;
 mod errors;
+mod inactive;
+mod migration;
+mod mirror;
+mod openpgp;
+mod reconcile;
+mod repositories;
+#[cfg(feature = "serde")]
+mod schema;
 pub mod source_deb822;
 mod source_entry;
 mod source_line;
 mod sources_list;
+mod suite;
+mod trust;
+mod upgrade;
+mod validate;
+mod verify;
 
 #[cfg(test)]
 mod tests;
 
+pub use self::add_repository::*;
+pub use self::audit::*;
+pub use self::changes::*;
+pub use self::diff::*;
+pub use self::distro_info::*;
+pub use self::duplicates::*;
 pub use self::errors::*;
+pub use self::inactive::*;
+pub use self::migration::*;
+pub use self::mirror::*;
+pub use self::openpgp::*;
+pub use self::reconcile::*;
+pub use self::repositories::*;
 pub use self::source_entry::*;
 pub use self::source_line::*;
 pub use self::sources_list::*;
-pub use deb822::signature::Signature;
+pub use self::suite::*;
+pub use self::trust::*;
+pub use self::upgrade::*;
+pub use self::validate::*;
+pub use self::verify::*;
+pub use deb822::signature::{Fingerprint, KeyPinning, KeyReference, Signature};
@@ -0,0 +1,555 @@
+//! Release-specific migrations of source entries.
+//!
+//! Upgrading a system or keeping an old one working needs more than renaming suites:
+//! repositories move, suites get new names and components get split. These changes are
+//! described by `MigrationRule`s, which are read from deb822 files such as:
+//!
+//! ```text
+//! Name: debian-security-suite
+//! Match-URIs: *://security.debian.org/*
+//! Match-Suites: {codename}/updates
+//! Since-Codename: bullseye
+//! Set-Suite: {codename}-security
+//! ```
+//!
+//! The following fields are understood:
+//!
+//! * `Name` identifies the rule, and is mandatory
+//! * `Description` is a free-form text
+//! * `Match-URIs` lists patterns that the URI must match, where `*` matches anything
+//! * `Match-Suites` lists suite templates, where `{codename}` stands for a codename
+//! * `Match-Codenames` lists the codenames the rule applies to
+//! * `Match-Distro` is the distribution the codename must belong to
+//! * `Since-Codename` applies the rule to this codename and every later release
+//! * `If-Components` lists components that must all be enabled
+//! * `If-EOL` is `yes`, or the name of a support phase of the distro-info data, such as
+//!   `eol-lts`, that must have ended
+//! * `Set-URI` replaces the URI
+//! * `Set-Suite` replaces the suite, where `{codename}` is substituted
+//! * `Add-Components` and `Remove-Components` edit the components
+//! * `Set-Options` lists `name=value` options to set
+//! * `Disable` is a yes/no field that disables the entry
+//!
+//! Rules are applied in order, so that a rule sees the changes made by the rules before it.
+
+use super::*;
+use deb822_lossless::{Deb822, ParseError};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const DEBIAN_RULES: &str = include_str!("../data/migrations/debian.rules");
+const UBUNTU_RULES: &str = include_str!("../data/migrations/ubuntu.rules");
+
+/// The support phases of the distro-info data, which `If-EOL` may name.
+const SUPPORT_PHASES: &[&str] = &["eol", "eol-lts", "eol-elts", "eol-server", "eol-esm"];
+
+/// A change made by a migration rule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MigrationAction {
+    SetUri(String),
+    /// A suite template, where `{codename}` is replaced by the matched codename.
+    SetSuite(String),
+    AddComponents(Vec<String>),
+    RemoveComponents(Vec<String>),
+    SetOptions(Vec<(String, Vec<String>)>),
+    Disable,
+}
+
+/// A declarative description of how matching entries must be migrated.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MigrationRule {
+    pub name: String,
+    pub description: Option<String>,
+    pub uris: Vec<String>,
+    pub suites: Vec<String>,
+    pub codenames: Vec<String>,
+    pub distro: Option<String>,
+    pub since_codename: Option<String>,
+    pub if_components: Vec<String>,
+    pub if_eol: Option<String>,
+    pub actions: Vec<MigrationAction>,
+}
+
+impl MigrationRule {
+    /// Checks whether the rule applies to an entry, returning the matched codename.
+    fn matches(
+        &self,
+        entry: &SourceEntry,
+        catalogue: &ReleaseCatalogue,
+        date: Date,
+    ) -> Option<String> {
+        let url = entry.url();
+        if !self.uris.is_empty()
+            && !self
+                .uris
+                .iter()
+                .any(|pattern| glob_match(pattern.trim_end_matches('/'), url))
+        {
+            return None;
+        }
+
+        let codename = if self.suites.is_empty() {
+            entry.parsed_suite().codename().map(String::from)
+        } else {
+            self.suites
+                .iter()
+                .find_map(|template| match_template(template, &entry.suite))
+        }?;
+
+        if !self.codenames.is_empty() && !self.codenames.contains(&codename) {
+            return None;
+        }
+
+        if !self
+            .if_components
+            .iter()
+            .all(|c| entry.components.contains(c))
+        {
+            return None;
+        }
+
+        if self.distro.is_some() || self.since_codename.is_some() || self.if_eol.is_some() {
+            let release = match &self.distro {
+                Some(distro) => catalogue.resolve_in(distro, &codename, date)?,
+                None => catalogue.resolve_for(&codename, &entry.url, date)?,
+            };
+            if self.distro.as_ref().is_some_and(|d| *d != release.distro) {
+                return None;
+            }
+
+            if let Some(since) = &self.since_codename {
+                let releases = catalogue.releases(&release.distro);
+                let since = releases.iter().position(|r| r.series == *since)?;
+                let current = releases.iter().position(|r| r.series == release.series)?;
+                if current < since {
+                    return None;
+                }
+            }
+
+            if let Some(phase) = &self.if_eol {
+                let end = match phase.as_str() {
+                    "yes" | "eol" => release.eol,
+                    phase => release
+                        .extended
+                        .iter()
+                        .find(|(name, _)| name == phase)
+                        .map(|(_, date)| *date)
+                        .or(release.eol),
+                };
+
+                if end.is_none_or(|end| end > date) {
+                    return None;
+                }
+            }
+        }
+
+        Some(codename)
+    }
+
+    /// Applies the actions of the rule, returning `true` if the entry was changed.
+    fn apply(&self, entry: &mut SourceEntry, codename: &str) -> bool {
+        let original = entry.clone();
+        for action in &self.actions {
+            match action {
+                MigrationAction::SetUri(uri) => entry.url.clone_from(uri),
+                MigrationAction::SetSuite(template) => {
+                    entry.suite = template.replace("{codename}", codename)
+                }
+                MigrationAction::AddComponents(components) => {
+                    for component in components {
+                        if !entry.components.contains(component) {
+                            entry.components.push(component.clone());
+                        }
+                    }
+                }
+                MigrationAction::RemoveComponents(components) => {
+                    entry.components.retain(|c| !components.contains(c))
+                }
+                MigrationAction::SetOptions(options) => {
+                    for (name, values) in options {
+                        entry.set_option(name, values.clone());
+                    }
+                }
+                MigrationAction::Disable => entry.enabled = false,
+            }
+        }
+
+        *entry != original
+    }
+}
+
+/// Matches a suite against a template containing `{codename}`, returning the codename.
+fn match_template(template: &str, suite: &str) -> Option<String> {
+    let Some((prefix, suffix)) = template.split_once("{codename}") else {
+        return (template == suite)
+            .then(|| Suite::from(suite).codename().map(String::from))
+            .flatten();
+    };
+
+    let codename = suite.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    if codename.is_empty() || codename.contains(['-', '/']) {
+        return None;
+    }
+
+    Some(codename.to_owned())
+}
+
+/// Matches text against a pattern where `*` matches any sequence of characters.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// An ordered set of migration rules.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MigrationRules {
+    pub rules: Vec<MigrationRule>,
+}
+
+/// A migration that was applied to an entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AppliedMigration {
+    pub location: EntryLocation,
+    pub rule: String,
+}
+
+impl MigrationRules {
+    /// The rules for Debian and Ubuntu shipped with this crate.
+    pub fn builtin() -> Self {
+        let mut rules = DEBIAN_RULES
+            .parse::<Self>()
+            .expect("embedded debian migration rules are valid");
+        rules.extend(
+            UBUNTU_RULES
+                .parse::<Self>()
+                .expect("embedded ubuntu migration rules are valid"),
+        );
+        rules
+    }
+
+    /// Reads the rules from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MigrationError> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(|why| MigrationError::Read {
+                path: path.to_path_buf(),
+                why,
+            })?
+            .parse()
+    }
+
+    /// Reads the rules from every `*.rules` file of a directory, in file name order.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Self, MigrationError> {
+        let dir = dir.as_ref();
+        let read_err = |why| MigrationError::Read {
+            path: dir.to_path_buf(),
+            why,
+        };
+
+        let mut paths = fs::read_dir(dir)
+            .map_err(read_err)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_err)?;
+        paths.retain(|p| p.extension().is_some_and(|e| e == "rules"));
+        paths.sort();
+
+        let mut rules = Self::default();
+        for path in paths {
+            rules.extend(Self::load(path)?);
+        }
+
+        Ok(rules)
+    }
+
+    /// Appends rules after the existing ones.
+    pub fn extend(&mut self, other: Self) {
+        self.rules.extend(other.rules);
+    }
+}
+
+impl FromStr for MigrationRules {
+    type Err = MigrationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let deb822: Deb822 = s
+            .parse()
+            .map_err(|e: ParseError| MigrationError::Syntax { why: e.to_string() })?;
+
+        let mut rules = Vec::new();
+        for p in deb822.paragraphs() {
+            let list = |field: &str| {
+                p.get(field)
+                    .map(|v| v.split_whitespace().map(String::from).collect::<Vec<_>>())
+                    .unwrap_or_default()
+            };
+
+            let name = p
+                .get("Name")
+                .ok_or(MigrationError::MissingField { field: "Name" })?;
+
+            let mut actions = Vec::new();
+            if let Some(uri) = p.get("Set-URI") {
+                actions.push(MigrationAction::SetUri(uri.trim().to_owned()));
+            }
+
+            if let Some(suite) = p.get("Set-Suite") {
+                actions.push(MigrationAction::SetSuite(suite.trim().to_owned()));
+            }
+
+            let components = list("Add-Components");
+            if !components.is_empty() {
+                actions.push(MigrationAction::AddComponents(components));
+            }
+
+            let components = list("Remove-Components");
+            if !components.is_empty() {
+                actions.push(MigrationAction::RemoveComponents(components));
+            }
+
+            let options = list("Set-Options")
+                .into_iter()
+                .map(|option| match option.split_once('=') {
+                    Some((k, v)) => Ok((k.to_owned(), v.split(',').map(String::from).collect())),
+                    None => Err(MigrationError::InvalidValue {
+                        rule: name.clone(),
+                        field: "Set-Options",
+                        value: option,
+                    }),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if !options.is_empty() {
+                actions.push(MigrationAction::SetOptions(options));
+            }
+
+            match p.get("Disable").as_deref().map(str::trim) {
+                Some("yes") => actions.push(MigrationAction::Disable),
+                Some("no") | None => (),
+                Some(value) => {
+                    return Err(MigrationError::InvalidValue {
+                        rule: name,
+                        field: "Disable",
+                        value: value.to_owned(),
+                    })
+                }
+            }
+
+            let if_eol = p.get("If-EOL").map(|d| d.trim().to_owned());
+            if let Some(phase) = if_eol
+                .as_ref()
+                .filter(|phase| *phase != "yes" && !SUPPORT_PHASES.contains(&phase.as_str()))
+            {
+                return Err(MigrationError::InvalidValue {
+                    rule: name,
+                    field: "If-EOL",
+                    value: phase.clone(),
+                });
+            }
+
+            rules.push(MigrationRule {
+                description: p.get("Description").map(|d| d.trim().to_owned()),
+                uris: list("Match-URIs"),
+                suites: list("Match-Suites"),
+                codenames: list("Match-Codenames"),
+                distro: p.get("Match-Distro").map(|d| d.trim().to_owned()),
+                since_codename: p.get("Since-Codename").map(|d| d.trim().to_owned()),
+                if_components: list("If-Components"),
+                if_eol,
+                actions,
+                name,
+            });
+        }
+
+        Ok(MigrationRules { rules })
+    }
+}
+
+impl SourcesLists {
+    /// Applies migration rules to every entry.
+    ///
+    /// The catalogue is used to look up the releases that entries point to, for rules which
+    /// depend on the distribution or the support status of a release. Changes are only applied
+    /// in-memory. Use `SourcesLists::write_sync` to write all changes to the disk.
+    pub fn apply_migrations(
+        &mut self,
+        rules: &MigrationRules,
+        catalogue: &ReleaseCatalogue,
+        date: Date,
+    ) -> Vec<AppliedMigration> {
+        let mut applied = Vec::new();
+        let &mut Self {
+            ref mut modified,
+            ref mut files,
+            ..
+        } = self;
+
+        for list in files.iter_mut() {
+            let path = list.path.clone();
+            let indexes = list.indexed_entries().map(|(i, _)| i).collect::<Vec<_>>();
+            for index in indexes {
+                let Some(entry) = list.entry_mut(index) else {
+                    continue;
+                };
+
+                for rule in &rules.rules {
+                    let Some(codename) = rule.matches(entry, catalogue, date) else {
+                        continue;
+                    };
+
+                    if rule.apply(entry, &codename) {
+                        add_modified(modified, &path);
+                        applied.push(AppliedMigration {
+                            location: EntryLocation {
+                                path: path.clone(),
+                                index,
+                            },
+                            rule: rule.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests;
+
+    fn lists(text: &str) -> SourcesLists {
+        tests::lists(&[("sources.list", text)])
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match(
+            "*://deb.debian.org/debian",
+            "https://deb.debian.org/debian"
+        ));
+        assert!(glob_match(
+            "*://*.archive.ubuntu.com/ubuntu",
+            "http://cn.archive.ubuntu.com/ubuntu"
+        ));
+        assert!(!glob_match(
+            "*://deb.debian.org/debian",
+            "http://deb.debian.org/debian-security"
+        ));
+        assert!(glob_match("abc", "abc"));
+        assert!(!glob_match("abc", "abcd"));
+    }
+
+    #[test]
+    fn bullseye_upgrade() {
+        let mut lists = lists(
+            "deb http://deb.debian.org/debian bullseye main non-free\n\
+             deb http://security.debian.org/debian-security bullseye/updates main\n\
+             deb http://security.debian.org/debian-security buster/updates main\n",
+        );
+
+        let applied = lists.apply_migrations(
+            &MigrationRules::builtin(),
+            &ReleaseCatalogue::builtin(),
+            Date::new(2023, 1, 1),
+        );
+
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].rule, "debian-security-suite");
+        let suites = lists
+            .entries()
+            .map(|e| e.suite.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(suites, ["bullseye", "bullseye-security", "buster/updates"]);
+    }
+
+    #[test]
+    fn archived_releases() {
+        let mut lists = lists(
+            "deb http://deb.debian.org/debian buster main non-free\n\
+             deb http://deb.debian.org/debian buster-updates main\n\
+             deb http://deb.debian.org/debian bookworm main non-free\n\
+             deb http://cn.archive.ubuntu.com/ubuntu/ lunar main\n\
+             deb http://cn.archive.ubuntu.com/ubuntu/ noble main\n",
+        );
+
+        lists.apply_migrations(
+            &MigrationRules::builtin(),
+            &ReleaseCatalogue::builtin(),
+            Date::new(2025, 1, 1),
+        );
+
+        let entries = lists.entries().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                "deb [check-valid-until=no] http://archive.debian.org/debian buster main non-free",
+                "# deb [check-valid-until=no] http://archive.debian.org/debian buster-updates main",
+                "deb http://deb.debian.org/debian bookworm main non-free non-free-firmware",
+                "deb http://old-releases.ubuntu.com/ubuntu lunar main",
+                "deb http://cn.archive.ubuntu.com/ubuntu/ noble main",
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_rules() {
+        let rules = "Name: aosc-mirror\n\
+                     Match-URIs: https://repo.aosc.io/debs\n\
+                     Match-Suites: stable\n\
+                     Set-URI: https://mirrors.example.org/anthon/debs\n\
+                     Set-Options: check-valid-until=no by-hash=yes\n"
+            .parse::<MigrationRules>()
+            .unwrap();
+
+        let mut lists = tests::lists(&[(
+            "aosc.sources",
+            "Types: deb\nURIs: https://repo.aosc.io/debs\nSuites: stable\nComponents: main\n",
+        )]);
+
+        let applied = lists.apply_migrations(&rules, &ReleaseCatalogue::empty(), Date::today());
+        assert_eq!(applied.len(), 1);
+        assert_eq!(lists.modified, [lists.files[0].path.clone()]);
+        assert_eq!(
+            lists.files[0].to_string(),
+            "Types: deb\n\
+             URIs: https://mirrors.example.org/anthon/debs\n\
+             Suites: stable \n\
+             Components: main\n\
+             Check-Valid-Until: no\n\
+             By-Hash: yes\n"
+        );
+
+        assert!(matches!(
+            "Description: no name\n".parse::<MigrationRules>(),
+            Err(MigrationError::MissingField { field: "Name" })
+        ));
+        assert!(matches!(
+            "Name: typo\nMatch-Codenames: buster\nIf-EOL: eol-lst\nDisable: yes\n"
+                .parse::<MigrationRules>(),
+            Err(MigrationError::InvalidValue {
+                field: "If-EOL",
+                ..
+            })
+        ));
+    }
+}
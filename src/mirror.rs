@@ -0,0 +1,469 @@
+//! Switching of archives to another mirror.
+//!
+//! A `MirrorSwitch` recognizes the entries of an archive by the prefix of their URI, or by the
+//! `Origin` and `Label` of the releases cached by apt, and moves them to a new mirror. The
+//! path below a matched prefix is kept, as are suites and components, so that
+//! `http://deb.debian.org/debian-security` follows `http://deb.debian.org/debian` to the same
+//! mirror. Entries matched by their release only lose their scheme and host. Security pockets
+//! are left on their archive by default, since mirrors often lag behind or do not carry them.
+//!
+//! The resulting `MirrorSwitchPlan` can be previewed before being applied with
+//! `SourcesLists::apply_mirror_switch`, and gives the plan which moves the entries back.
+
+use crate::duplicates::normalized_url;
+use crate::openpgp::split_clearsigned;
+use crate::{
+    Change, ChangeSet, EntryLocation, FilePreview, Pocket, SourceEntry, SourceResult, SourcesLists,
+};
+use std::fs;
+use std::path::Path;
+
+/// Moves the entries of an archive to a mirror.
+#[derive(Clone, Debug)]
+pub struct MirrorSwitch {
+    to: String,
+    prefixes: Vec<String>,
+    origins: Vec<String>,
+    labels: Vec<String>,
+    exceptions: Vec<String>,
+    keep_security: bool,
+}
+
+/// A base URI which was replaced by a mirror.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MirrorMapping {
+    pub from: String,
+    pub to: String,
+}
+
+/// An entry which a plan moves, and the URI that it had.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MovedEntry {
+    /// The location of the entry when it was moved.
+    pub location: EntryLocation,
+    /// The URI of the entry before it was moved.
+    pub from: String,
+    /// The entry, once moved.
+    pub entry: SourceEntry,
+}
+
+/// The changes which move entries to a mirror.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MirrorSwitchPlan {
+    /// A `set-uri` change for every entry which moves.
+    pub changes: ChangeSet,
+    /// The base URIs which were replaced, in order of appearance.
+    pub mappings: Vec<MirrorMapping>,
+    /// The entries which move, in the order of the changes.
+    pub moved: Vec<MovedEntry>,
+}
+
+impl MirrorSwitchPlan {
+    /// Shows how the files would change once the plan is applied and written.
+    pub fn preview(&self, lists: &SourcesLists) -> SourceResult<Vec<FilePreview>> {
+        self.changes.preview(lists)
+    }
+
+    /// The plan which moves the entries back to the URIs they had, once this one is applied.
+    ///
+    /// Each entry gets its own URI back, even when several archives were moved to the same
+    /// mirror, and entries which did not move are left alone. Unlike `ChangeSet::invert`, this
+    /// does not depend on the locations of the entries: those which have moved since are found
+    /// by their type, URI and suite. Entries which cannot be found are skipped.
+    pub fn reverse(&self, lists: &SourcesLists) -> MirrorSwitchPlan {
+        let mut plan = MirrorSwitchPlan {
+            mappings: self
+                .mappings
+                .iter()
+                .map(|mapping| MirrorMapping {
+                    from: mapping.to.clone(),
+                    to: mapping.from.clone(),
+                })
+                .collect(),
+            ..MirrorSwitchPlan::default()
+        };
+
+        for moved in &self.moved {
+            let matches = |location: &EntryLocation, entry: &SourceEntry| {
+                entry.same_target(&moved.entry)
+                    && entry.url == moved.entry.url
+                    && !plan.moved.iter().any(|m| m.location == *location)
+            };
+
+            let location = match lists.entry_at(&moved.location) {
+                Some(entry) if matches(&moved.location, entry) => Some(moved.location.clone()),
+                _ => lists
+                    .located_entries()
+                    .find(|(location, entry)| matches(location, entry))
+                    .map(|(location, _)| location),
+            };
+            let Some(location) = location else {
+                continue;
+            };
+
+            let mut entry = moved.entry.clone();
+            entry.url.clone_from(&moved.from);
+            plan.changes.push(Change::SetUri {
+                location: location.clone(),
+                from: moved.entry.url.clone(),
+                to: moved.from.clone(),
+            });
+            plan.moved.push(MovedEntry {
+                location,
+                from: moved.entry.url.clone(),
+                entry,
+            });
+        }
+
+        plan
+    }
+}
+
+impl MirrorSwitch {
+    /// Switches to the mirror at the given URI.
+    pub fn new(to: &str) -> Self {
+        MirrorSwitch {
+            to: to.trim_end_matches('/').to_owned(),
+            prefixes: Vec::new(),
+            origins: Vec::new(),
+            labels: Vec::new(),
+            exceptions: Vec::new(),
+            keep_security: true,
+        }
+    }
+
+    /// Matches entries whose URI starts with the prefix, which is replaced by the mirror.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefixes.push(normalized_url(prefix));
+        self
+    }
+
+    /// Matches entries whose cached release has the given `Origin`, such as `Debian`.
+    ///
+    /// The scheme and host of these entries are replaced by the mirror, and their path is kept.
+    pub fn origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_owned());
+        self
+    }
+
+    /// Matches entries whose cached release has the given `Label`.
+    ///
+    /// The scheme and host of these entries are replaced by the mirror, and their path is kept.
+    pub fn label(mut self, label: &str) -> Self {
+        self.labels.push(label.to_owned());
+        self
+    }
+
+    /// Leaves entries whose URI starts with the prefix where they are.
+    pub fn except(mut self, prefix: &str) -> Self {
+        self.exceptions.push(normalized_url(prefix));
+        self
+    }
+
+    /// Whether entries of `-security` and `/updates` suites are left where they are.
+    ///
+    /// This is the default.
+    pub fn keep_security(mut self, keep: bool) -> Self {
+        self.keep_security = keep;
+        self
+    }
+
+    /// Plans the switch of every matching entry of the lists.
+    ///
+    /// Releases are read from **var/lib/apt/lists**, below the root argument, when entries are
+    /// matched by origin or label.
+    pub fn plan<P: AsRef<Path>>(&self, lists: &SourcesLists, root: P) -> MirrorSwitchPlan {
+        let lists_dir = root.as_ref().join("var/lib/apt/lists");
+        let mut plan = MirrorSwitchPlan::default();
+
+        for (location, entry) in lists.located_entries() {
+            let url = normalized_url(&entry.url);
+            if self.exceptions.iter().any(|e| under(&url, e)) {
+                continue;
+            }
+
+            if self.keep_security
+                && matches!(
+                    entry.parsed_suite().pocket(),
+                    Some(Pocket::Security | Pocket::LegacySecurity)
+                )
+            {
+                continue;
+            }
+
+            let base = match self.prefixes.iter().find(|p| under(&url, p)) {
+                Some(prefix) => prefix.clone(),
+                None if self.release_matches(&lists_dir, entry) => site(&url).to_owned(),
+                None => continue,
+            };
+
+            let mut to = [self.to.as_str(), &url[base.len()..]].concat();
+            if entry.url.ends_with('/') {
+                to.push('/');
+            }
+
+            if to == entry.url {
+                continue;
+            }
+
+            let mapping = MirrorMapping {
+                from: base,
+                to: self.to.clone(),
+            };
+            if !plan.mappings.contains(&mapping) {
+                plan.mappings.push(mapping);
+            }
+
+            let mut moved = entry.clone();
+            moved.url.clone_from(&to);
+            plan.moved.push(MovedEntry {
+                location: location.clone(),
+                from: entry.url.clone(),
+                entry: moved,
+            });
+            plan.changes.push(Change::SetUri {
+                location,
+                from: entry.url.clone(),
+                to,
+            });
+        }
+
+        plan
+    }
+
+    /// Whether the release cached for the entry has a matching origin or label.
+    fn release_matches(&self, lists_dir: &Path, entry: &SourceEntry) -> bool {
+        if self.origins.is_empty() && self.labels.is_empty() {
+            return false;
+        }
+
+        let Some(text) = ["InRelease", "Release"]
+            .iter()
+            .find_map(|file| fs::read_to_string(lists_dir.join(entry.lists_filename(file))).ok())
+        else {
+            return false;
+        };
+
+        let text = match split_clearsigned(&text) {
+            Some((message, _)) => String::from_utf8_lossy(&message).into_owned(),
+            None => text,
+        };
+
+        let field = |name: &str| {
+            text.lines()
+                .take_while(|line| !line.trim().is_empty())
+                .find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    key.eq_ignore_ascii_case(name)
+                        .then(|| value.trim().to_owned())
+                })
+        };
+
+        field("Origin").is_some_and(|origin| self.origins.contains(&origin))
+            || field("Label").is_some_and(|label| self.labels.contains(&label))
+    }
+}
+
+/// The scheme and host of a normalized URI, without its path.
+fn site(url: &str) -> &str {
+    let start = url.find("://").map_or(0, |pos| pos + 3);
+    match url[start..].find('/') {
+        Some(pos) => &url[..start + pos],
+        None => url,
+    }
+}
+
+/// Whether a normalized URI is the prefix, or below it.
+fn under(url: &str, prefix: &str) -> bool {
+    url.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl SourcesLists {
+    /// Moves the entries of the plan to their mirror.
+    ///
+    /// Fails, leaving the lists untouched, if entries have changed since the plan was made.
+    pub fn apply_mirror_switch(&mut self, plan: &MirrorSwitchPlan) -> SourceResult<()> {
+        plan.changes.apply(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, TempRoot};
+    use crate::SourceListType;
+
+    const LIST: &str = "\
+deb http://deb.debian.org/debian/ bookworm main
+deb http://deb.debian.org/debian bookworm-updates main
+deb http://deb.debian.org/debian-security bookworm-security main
+deb http://ftp.example.org/debian bookworm-backports main
+deb https://example.org/repo stable main
+";
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[("sources.list", LIST)])
+    }
+
+    #[test]
+    fn prefix() {
+        let mut lists = lists();
+        let plan = MirrorSwitch::new("http://mirror.local/")
+            .prefix("http://deb.debian.org")
+            .plan(&lists, "/nonexistent");
+
+        assert_eq!(plan.changes.changes.len(), 2);
+        assert_eq!(
+            plan.mappings,
+            [MirrorMapping {
+                from: "http://deb.debian.org".into(),
+                to: "http://mirror.local".into(),
+            }]
+        );
+
+        lists.apply_mirror_switch(&plan).unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb http://mirror.local/debian/ bookworm main\n\
+             deb http://mirror.local/debian bookworm-updates main\n\
+             deb http://deb.debian.org/debian-security bookworm-security main\n\
+             deb http://ftp.example.org/debian bookworm-backports main\n\
+             deb https://example.org/repo stable main\n"
+        );
+
+        // Applying the plan again is refused, and switching back restores the lists.
+        assert!(lists.apply_mirror_switch(&plan).is_err());
+        lists.apply_mirror_switch(&plan.reverse(&lists)).unwrap();
+        assert_eq!(lists.files[0].to_string(), LIST);
+    }
+
+    #[test]
+    fn release() {
+        let root = TempRoot::new("mirror");
+        let lists_dir = root.join("var/lib/apt/lists");
+        fs::create_dir_all(&lists_dir).unwrap();
+
+        let entry: SourceEntry = "deb http://ftp.example.org/debian bookworm-backports main"
+            .parse()
+            .unwrap();
+        fs::write(
+            lists_dir.join(entry.lists_filename("InRelease")),
+            "-----BEGIN PGP SIGNED MESSAGE-----\n\
+             Hash: SHA512\n\
+             \n\
+             Origin: Debian Backports\n\
+             Label: Debian Backports\n\
+             Suite: stable-backports\n\
+             -----BEGIN PGP SIGNATURE-----\n\
+             \n\
+             iQIzBAEBCgAdFiEE\n\
+             -----END PGP SIGNATURE-----\n",
+        )
+        .unwrap();
+
+        let mut lists = lists();
+        let plan = MirrorSwitch::new("http://mirror.local")
+            .origin("Debian Backports")
+            .plan(&lists, &root);
+        lists.apply_mirror_switch(&plan).unwrap();
+
+        assert_eq!(
+            lists.files[0].entry(3).unwrap().url,
+            "http://mirror.local/debian"
+        );
+        assert_eq!(plan.reverse(&lists).changes, plan.changes.invert().unwrap());
+
+        // Security entries move when asked to, unless excepted.
+        let plan = MirrorSwitch::new("http://mirror.local")
+            .prefix("http://deb.debian.org/")
+            .except("http://deb.debian.org/debian/")
+            .keep_security(false)
+            .plan(&lists, &root);
+        assert_eq!(
+            plan.changes.changes,
+            [Change::SetUri {
+                location: crate::EntryLocation {
+                    path: "sources.list".into(),
+                    index: 2,
+                },
+                from: "http://deb.debian.org/debian-security".into(),
+                to: "http://mirror.local/debian-security".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn shared_origin() {
+        let root = TempRoot::new("mirror-origin");
+        let lists_dir = root.join("var/lib/apt/lists");
+        fs::create_dir_all(&lists_dir).unwrap();
+
+        // The archive and its security pocket are released by the same origin.
+        let mut lists = lists();
+        for index in [0, 2] {
+            let entry = lists.files[0].entry(index).unwrap();
+            fs::write(
+                lists_dir.join(entry.lists_filename("Release")),
+                "Origin: Debian\nLabel: Debian\n",
+            )
+            .unwrap();
+        }
+
+        let plan = MirrorSwitch::new("http://mirror.local")
+            .origin("Debian")
+            .keep_security(false)
+            .plan(&lists, &root);
+        assert_eq!(
+            plan.mappings,
+            [MirrorMapping {
+                from: "http://deb.debian.org".into(),
+                to: "http://mirror.local".into(),
+            }]
+        );
+
+        lists.apply_mirror_switch(&plan).unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb http://mirror.local/debian/ bookworm main\n\
+             deb http://deb.debian.org/debian bookworm-updates main\n\
+             deb http://mirror.local/debian-security bookworm-security main\n\
+             deb http://ftp.example.org/debian bookworm-backports main\n\
+             deb https://example.org/repo stable main\n"
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        let list = "\
+deb http://deb.debian.org/debian bookworm main
+deb http://mirror.local/debian bookworm-updates main
+deb http://deb.debian.org/debian-security bookworm-security main
+deb http://ftp.example.org/debian bookworm-backports main
+";
+        let mut lists = tests::lists(&[("sources.list", list)]);
+
+        // Two archives move to the same mirror, which already hosts an entry.
+        let plan = MirrorSwitch::new("http://mirror.local")
+            .prefix("http://deb.debian.org")
+            .prefix("http://ftp.example.org")
+            .plan(&lists, "/nonexistent");
+        assert_eq!(plan.mappings.len(), 2);
+        assert_eq!(plan.moved.len(), 2);
+        lists.apply_mirror_switch(&plan).unwrap();
+
+        // The entries are found again once another entry is inserted before them.
+        if let SourceListType::SourceLine(lines) = &mut lists.files[0].entries {
+            let entry = "deb https://example.org/repo stable main".parse().unwrap();
+            lines.0.insert(0, crate::SourceLine::Entry(entry));
+        }
+
+        let reverse = plan.reverse(&lists);
+        assert_eq!(reverse.moved.len(), 2);
+        lists.apply_mirror_switch(&reverse).unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            format!("deb https://example.org/repo stable main\n{}", list)
+        );
+    }
+}
@@ -0,0 +1,1581 @@
+//! Offline inspection of the OpenPGP keys which sign repositories.
+//!
+//! Only the parts of RFC 4880 and RFC 9580 needed to describe public keys are implemented:
+//! ASCII armor, packet framing, public key and signature packets. Self-signatures only set the
+//! expiry or revocation of a key once verified, which is limited to the RSA and Ed25519
+//! signatures supported by the `verify` module; the others are ignored.
+
+use crate::deb822::signature::{Fingerprint, KeyReference};
+use crate::verify::verify_data;
+use crate::FileOperation;
+use crate::{Date, EntryLocation, KeyError, Signature, SourcesLists};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The public key algorithm of a key.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PublicKeyAlgorithm {
+    Rsa,
+    Dsa,
+    ElGamal,
+    Ecdh,
+    Ecdsa,
+    /// EdDSA as defined before RFC 9580, with the curve given by an OID.
+    EdDsaLegacy,
+    X25519,
+    X448,
+    Ed25519,
+    Ed448,
+    Unknown(u8),
+}
+
+impl From<u8> for PublicKeyAlgorithm {
+    fn from(id: u8) -> Self {
+        match id {
+            1..=3 => PublicKeyAlgorithm::Rsa,
+            16 | 20 => PublicKeyAlgorithm::ElGamal,
+            17 => PublicKeyAlgorithm::Dsa,
+            18 => PublicKeyAlgorithm::Ecdh,
+            19 => PublicKeyAlgorithm::Ecdsa,
+            22 => PublicKeyAlgorithm::EdDsaLegacy,
+            25 => PublicKeyAlgorithm::X25519,
+            26 => PublicKeyAlgorithm::X448,
+            27 => PublicKeyAlgorithm::Ed25519,
+            28 => PublicKeyAlgorithm::Ed448,
+            id => PublicKeyAlgorithm::Unknown(id),
+        }
+    }
+}
+
+/// The elliptic curve of an ECDH, ECDSA or legacy EdDSA key.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Curve {
+    NistP256,
+    NistP384,
+    NistP521,
+    BrainpoolP256,
+    BrainpoolP384,
+    BrainpoolP512,
+    Secp256k1,
+    Ed25519,
+    Cv25519,
+    /// A curve which is not known, given by the hexadecimal form of its OID.
+    Unknown(String),
+}
+
+impl Curve {
+    fn from_oid(oid: &[u8]) -> Self {
+        match oid {
+            [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07] => Curve::NistP256,
+            [0x2B, 0x81, 0x04, 0x00, 0x22] => Curve::NistP384,
+            [0x2B, 0x81, 0x04, 0x00, 0x23] => Curve::NistP521,
+            [0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x07] => Curve::BrainpoolP256,
+            [0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0B] => Curve::BrainpoolP384,
+            [0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0D] => Curve::BrainpoolP512,
+            [0x2B, 0x81, 0x04, 0x00, 0x0A] => Curve::Secp256k1,
+            [0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01] => Curve::Ed25519,
+            [0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01] => Curve::Cv25519,
+            oid => Curve::Unknown(hex(oid)),
+        }
+    }
+
+    fn bits(&self) -> Option<u32> {
+        match self {
+            Curve::NistP256 | Curve::BrainpoolP256 | Curve::Secp256k1 => Some(256),
+            Curve::NistP384 | Curve::BrainpoolP384 => Some(384),
+            Curve::NistP521 => Some(521),
+            Curve::BrainpoolP512 => Some(512),
+            Curve::Ed25519 | Curve::Cv25519 => Some(255),
+            Curve::Unknown(_) => None,
+        }
+    }
+}
+
+/// A primary key or a subkey.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PublicKey {
+    version: u8,
+    algorithm: PublicKeyAlgorithm,
+    curve: Option<Curve>,
+    bits: Option<u32>,
+    fingerprint: String,
+    revoked: bool,
+    created_at: u32,
+    /// Seconds since the epoch at which the key expires, as set by its latest self-signature.
+    pub(crate) expires_at: Option<u64>,
+    /// Creation time of the self-signature which `expires_at` was read from.
+    bound_at: Option<u32>,
+    /// Whether the primary key allowed a subkey to sign, according to its latest binding.
+    pub(crate) signing: SigningBinding,
+    /// The algorithm-specific public key material.
+    pub(crate) material: Vec<u8>,
+    /// The key packet, as hashed by the fingerprint and by signatures over the key.
+    hashed: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Parses the body of a public key or public subkey packet.
+    ///
+    /// Returns `None` for key versions which have no fingerprint of their own (v2 and v3) or
+    /// are not known, so that such keys can be skipped like gpg does.
+    fn parse(body: &[u8]) -> Result<Option<Self>, KeyError> {
+        let mut reader = Reader::new(body);
+        let version = reader.u8()?;
+        let created_at = reader.u32()?;
+        let algorithm_id = reader.u8()?;
+
+        let material = match version {
+            4 => reader.rest(),
+            5 | 6 => {
+                let len = reader.u32()? as usize;
+                reader.take(len)?
+            }
+            _ => return Ok(None),
+        };
+
+        let mut hashed = match version {
+            4 => {
+                let len = u16::try_from(body.len())
+                    .map_err(|_| malformed("version 4 key packet is too long"))?;
+                [&[0x99][..], &len.to_be_bytes()].concat()
+            }
+            _ => {
+                let tag = if version == 5 { 0x9A } else { 0x9B };
+                [&[tag][..], &(body.len() as u32).to_be_bytes()].concat()
+            }
+        };
+        hashed.extend_from_slice(body);
+
+        let fingerprint = match version {
+            4 => hex(&Sha1::digest(&hashed)),
+            _ => hex(&Sha256::digest(&hashed)),
+        };
+
+        let algorithm = PublicKeyAlgorithm::from(algorithm_id);
+        let mut key_reader = Reader::new(material);
+        let (curve, bits) = match algorithm {
+            PublicKeyAlgorithm::Rsa | PublicKeyAlgorithm::Dsa | PublicKeyAlgorithm::ElGamal => {
+                (None, Some(key_reader.mpi()?.0))
+            }
+            PublicKeyAlgorithm::Ecdh
+            | PublicKeyAlgorithm::Ecdsa
+            | PublicKeyAlgorithm::EdDsaLegacy => {
+                let len = key_reader.u8()? as usize;
+                let curve = Curve::from_oid(key_reader.take(len)?);
+                let bits = curve.bits();
+                (Some(curve), bits)
+            }
+            PublicKeyAlgorithm::X25519 | PublicKeyAlgorithm::Ed25519 => (None, Some(255)),
+            PublicKeyAlgorithm::X448 | PublicKeyAlgorithm::Ed448 => (None, Some(448)),
+            PublicKeyAlgorithm::Unknown(_) => (None, None),
+        };
+
+        Ok(Some(PublicKey {
+            version,
+            algorithm,
+            curve,
+            bits,
+            fingerprint,
+            revoked: false,
+            created_at,
+            expires_at: None,
+            bound_at: None,
+            signing: SigningBinding::None,
+            material: material.to_vec(),
+            hashed,
+        }))
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn algorithm(&self) -> PublicKeyAlgorithm {
+        self.algorithm
+    }
+
+    /// The curve of ECDH, ECDSA and legacy EdDSA keys.
+    pub fn curve(&self) -> Option<&Curve> {
+        self.curve.as_ref()
+    }
+
+    /// The size of the key, in bits, if known.
+    pub fn bits(&self) -> Option<u32> {
+        self.bits
+    }
+
+    /// The fingerprint of the key, in uppercase hexadecimal.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// The long key ID, which is the end of a v4 fingerprint or the start of a newer one.
+    pub fn key_id(&self) -> &str {
+        match self.version {
+            4 => &self.fingerprint[24..],
+            _ => &self.fingerprint[..16],
+        }
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked
+    }
+
+    pub fn created(&self) -> Date {
+        Date::from_unix_days(i64::from(self.created_at) / 86400)
+    }
+
+    /// The date at which the key expires, or `None` if it does not expire.
+    pub fn expires(&self) -> Option<Date> {
+        self.expires_at
+            .map(|secs| Date::from_unix_days(secs as i64 / 86400))
+    }
+
+    /// Whether the key has expired at the given date.
+    pub fn is_expired(&self, date: Date) -> bool {
+        self.expires().is_some_and(|expires| expires <= date)
+    }
+
+    /// Whether the algorithm or size of the key is rejected by apt.
+    ///
+    /// This follows the public key algorithms that apt asserts when verifying repositories:
+    /// RSA of at least 2048 bits, Ed25519, Ed448, and the NIST and Brainpool curves.
+    pub fn is_weak(&self) -> bool {
+        match self.algorithm {
+            PublicKeyAlgorithm::Rsa | PublicKeyAlgorithm::ElGamal => {
+                self.bits.is_none_or(|bits| bits < 2048)
+            }
+            PublicKeyAlgorithm::Ecdh
+            | PublicKeyAlgorithm::Ecdsa
+            | PublicKeyAlgorithm::EdDsaLegacy => !matches!(
+                self.curve,
+                Some(
+                    Curve::NistP256
+                        | Curve::NistP384
+                        | Curve::NistP521
+                        | Curve::BrainpoolP256
+                        | Curve::BrainpoolP384
+                        | Curve::BrainpoolP512
+                        | Curve::Ed25519
+                        | Curve::Cv25519
+                )
+            ),
+            PublicKeyAlgorithm::X25519
+            | PublicKeyAlgorithm::X448
+            | PublicKeyAlgorithm::Ed25519
+            | PublicKeyAlgorithm::Ed448 => false,
+            PublicKeyAlgorithm::Dsa | PublicKeyAlgorithm::Unknown(_) => true,
+        }
+    }
+
+    /// Records the key expiration time of a self-signature, if it is newer than the previous one.
+    ///
+    /// Returns whether the signature is the latest one.
+    fn bind(&mut self, signature: &SignaturePacket) -> bool {
+        let created = signature.created.unwrap_or(0);
+        if self.bound_at.is_some_and(|bound| bound > created) {
+            return false;
+        }
+
+        self.bound_at = Some(created);
+        self.expires_at = signature
+            .key_expiry
+            .filter(|&secs| secs != 0)
+            .map(|secs| u64::from(self.created_at) + u64::from(secs));
+        true
+    }
+}
+
+/// How the primary key allowed a subkey to sign.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SigningBinding {
+    /// No verified binding allows the subkey to sign. This is the case of primary keys.
+    None,
+    /// The binding of the subkey could not be verified, such as with an unsupported algorithm.
+    Unverified(String),
+    /// The latest binding has the signing flag and a valid back-signature by the subkey.
+    Signing,
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.algorithm, &self.curve, self.bits) {
+            (_, Some(curve), _) => write!(fmt, "{:?}/{:?}", self.algorithm, curve)?,
+            (PublicKeyAlgorithm::Rsa, _, Some(bits)) => write!(fmt, "rsa{}", bits)?,
+            (algorithm, _, _) => write!(fmt, "{:?}", algorithm)?,
+        }
+
+        write!(fmt, " {}", self.fingerprint)
+    }
+}
+
+/// A signature packet, as needed to read self-signatures and to verify releases.
+#[derive(Clone, Debug)]
+pub(crate) struct SignaturePacket {
+    pub version: u8,
+    pub sig_type: u8,
+    pub algorithm: PublicKeyAlgorithm,
+    pub hash_algorithm: u8,
+    /// The part of the packet which is hashed after the signed data.
+    pub hashed: Vec<u8>,
+    pub digest_prefix: [u8; 2],
+    /// The salt hashed before the signed data by v6 signatures.
+    pub salt: Vec<u8>,
+    /// The algorithm-specific signature material.
+    pub material: Vec<u8>,
+    pub created: Option<u32>,
+    pub key_expiry: Option<u32>,
+    /// The first octet of the key flags, such as 0x02 for keys which may sign data.
+    pub key_flags: Option<u8>,
+    /// The signature embedded in a subkey binding, by which the subkey binds itself back.
+    pub embedded: Option<Box<SignaturePacket>>,
+    pub issuer: Option<String>,
+    pub issuer_fingerprint: Option<String>,
+}
+
+impl SignaturePacket {
+    pub fn parse(body: &[u8]) -> Result<Self, KeyError> {
+        let mut reader = Reader::new(body);
+        let version = reader.u8()?;
+        if !matches!(version, 4..=6) {
+            return Err(KeyError::Unsupported {
+                what: format!("version {} signature", version),
+            });
+        }
+
+        let sig_type = reader.u8()?;
+        let algorithm = PublicKeyAlgorithm::from(reader.u8()?);
+        let hash_algorithm = reader.u8()?;
+        let wide = |reader: &mut Reader| -> Result<usize, KeyError> {
+            Ok(if version == 4 {
+                reader.u16()? as usize
+            } else {
+                reader.u32()? as usize
+            })
+        };
+
+        let hashed_len = wide(&mut reader)?;
+        let hashed_subpackets = reader.take(hashed_len)?;
+        let hashed = body[..reader.pos].to_vec();
+        let unhashed_len = wide(&mut reader)?;
+        let unhashed_subpackets = reader.take(unhashed_len)?;
+        let prefix = reader.take(2)?;
+        let salt = if version == 6 {
+            let len = reader.u8()? as usize;
+            reader.take(len)?.to_vec()
+        } else {
+            Vec::new()
+        };
+
+        let mut signature = SignaturePacket {
+            version,
+            sig_type,
+            algorithm,
+            hash_algorithm,
+            hashed,
+            digest_prefix: [prefix[0], prefix[1]],
+            salt,
+            material: reader.rest().to_vec(),
+            created: None,
+            key_expiry: None,
+            key_flags: None,
+            embedded: None,
+            issuer: None,
+            issuer_fingerprint: None,
+        };
+
+        signature.read_subpackets(hashed_subpackets, true)?;
+        signature.read_subpackets(unhashed_subpackets, false)?;
+        Ok(signature)
+    }
+
+    fn read_subpackets(&mut self, data: &[u8], hashed: bool) -> Result<(), KeyError> {
+        let mut reader = Reader::new(data);
+        while !reader.is_empty() {
+            let first = reader.u8()? as usize;
+            let len = match first {
+                0..=191 => first,
+                192..=254 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+                _ => reader.u32()? as usize,
+            };
+
+            let subpacket = reader.take(len)?;
+            let Some((&kind, value)) = subpacket.split_first() else {
+                continue;
+            };
+
+            match kind & 0x7F {
+                2 if hashed && value.len() == 4 => self.created = Some(be_u32(value)),
+                9 if hashed && value.len() == 4 => self.key_expiry = Some(be_u32(value)),
+                16 if value.len() == 8 => self.issuer = Some(hex(value)),
+                27 if hashed && !value.is_empty() => self.key_flags = Some(value[0]),
+                // The embedded signature is verified on its own, so it may be unhashed.
+                32 => self.embedded = SignaturePacket::parse(value).ok().map(Box::new),
+                33 if value.len() > 1 => self.issuer_fingerprint = Some(hex(&value[1..])),
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the signature may have been made by the given key, according to its issuer.
+    ///
+    /// Signatures which do not name their issuer are assumed to match.
+    pub fn may_be_issued_by(&self, key: &PublicKey) -> bool {
+        if let Some(fingerprint) = &self.issuer_fingerprint {
+            return fingerprint == key.fingerprint();
+        }
+
+        self.issuer.as_ref().is_none_or(|id| id == key.key_id())
+    }
+}
+
+/// Whether a verified binding allows the subkey to sign data.
+///
+/// Signing subkeys must bind themselves back to the primary key with an embedded signature,
+/// so that another certificate cannot claim a subkey and its signatures.
+fn signing_binding(
+    primary: &PublicKey,
+    subkey: &PublicKey,
+    binding: &SignaturePacket,
+) -> SigningBinding {
+    let Some(back) = &binding.embedded else {
+        return SigningBinding::None;
+    };
+
+    if binding.key_flags.is_none_or(|flags| flags & 0x02 == 0)
+        || back.sig_type != 0x19
+        || !back.may_be_issued_by(subkey)
+    {
+        return SigningBinding::None;
+    }
+
+    match verify_data(
+        subkey,
+        back,
+        &[&primary.hashed[..], &subkey.hashed].concat(),
+    ) {
+        Ok(true) => SigningBinding::Signing,
+        Ok(false) => SigningBinding::None,
+        Err(what) => SigningBinding::Unverified(what),
+    }
+}
+
+/// Splits a clearsigned message into the text which was signed, in the canonical form that
+/// is hashed, and the armored signature.
+pub(crate) fn split_clearsigned(text: &str) -> Option<(Vec<u8>, &str)> {
+    let start = text.find("-----BEGIN PGP SIGNED MESSAGE-----")?;
+    let end = start + text[start..].find("\n-----BEGIN PGP SIGNATURE-----")?;
+
+    // The armor headers end with the first blank line, and the line break before the
+    // signature is not part of the message.
+    let mut lines = text[start..end].split('\n').skip(1);
+    for line in lines.by_ref() {
+        if line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let message = lines
+        .map(|line| {
+            let line = line.strip_prefix("- ").unwrap_or(line);
+            line.trim_end_matches([' ', '\t', '\r'])
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n");
+
+    Some((message.into_bytes(), &text[end + 1..]))
+}
+
+/// Reads the signatures of a clearsigned `InRelease` file, or of a detached `Release.gpg`
+/// signature, which may be armored or binary.
+pub(crate) fn read_signatures(data: &[u8]) -> Result<Vec<SignaturePacket>, KeyError> {
+    let binary;
+    let data = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP") => {
+            // Only the signature block of a clearsigned message is armored.
+            let start =
+                text.find("-----BEGIN PGP SIGNATURE-----")
+                    .ok_or_else(|| KeyError::Armor {
+                        why: "no signature block".into(),
+                    })?;
+            binary = dearmor(&text[start..])?;
+            &binary
+        }
+        _ => data,
+    };
+
+    packets(data)?
+        .into_iter()
+        .filter(|packet| packet.tag == 2)
+        .map(|packet| SignaturePacket::parse(packet.body))
+        .collect()
+}
+
+/// A primary key, along with its user IDs and subkeys.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Certificate {
+    pub primary: PublicKey,
+    pub user_ids: Vec<String>,
+    pub subkeys: Vec<PublicKey>,
+    /// The packets of the certificate, as read.
+    pub(crate) raw: Vec<u8>,
+}
+
+impl Certificate {
+    /// The fingerprint of the primary key.
+    pub fn fingerprint(&self) -> &str {
+        self.primary.fingerprint()
+    }
+
+    /// Whether the primary key has expired at the given date.
+    pub fn is_expired(&self, date: Date) -> bool {
+        self.primary.is_expired(date)
+    }
+
+    /// Whether the primary key uses an algorithm or size which apt rejects.
+    pub fn is_weak(&self) -> bool {
+        self.primary.is_weak()
+    }
+
+    /// Iterates on the primary key and the subkeys.
+    pub fn keys(&self) -> impl Iterator<Item = &PublicKey> {
+        std::iter::once(&self.primary).chain(&self.subkeys)
+    }
+
+    /// The primary key or subkey which may have made the signature, according to its issuer.
+    pub(crate) fn issuer_of(&self, signature: &SignaturePacket) -> Option<&PublicKey> {
+        if signature.issuer.is_none() && signature.issuer_fingerprint.is_none() {
+            return None;
+        }
+
+        self.keys().find(|key| signature.may_be_issued_by(key))
+    }
+
+    /// Whether the primary key or one of the subkeys is selected by the fingerprint.
+    ///
+    /// An exact fingerprint, ending with `!`, only selects the key that it names. Otherwise,
+    /// a fingerprint of the primary key selects every subkey as well.
+    pub fn matches(&self, fingerprint: &Fingerprint) -> bool {
+        if fingerprint.is_exact() {
+            self.keys().any(|k| fingerprint.matches(k.fingerprint()))
+        } else {
+            fingerprint.matches(self.fingerprint())
+                || self
+                    .subkeys
+                    .iter()
+                    .any(|k| fingerprint.matches(k.fingerprint()))
+        }
+    }
+}
+
+/// A set of certificates, read from an armored or binary keyring.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Keyring {
+    pub certificates: Vec<Certificate>,
+}
+
+impl Keyring {
+    /// Parses a keyring, which may be in the binary format of `.gpg` files, or armored as in
+    /// `.asc` files and embedded `Signed-By` keys.
+    pub fn parse(data: &[u8]) -> Result<Self, KeyError> {
+        let is_armored = data
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .is_some_and(|start| data[start..].starts_with(b"-----BEGIN PGP"));
+
+        if is_armored {
+            let text = std::str::from_utf8(data).map_err(|_| KeyError::Armor {
+                why: "armored data is not valid UTF-8".into(),
+            })?;
+            Self::from_packets(&dearmor(text)?)
+        } else {
+            Self::from_packets(data)
+        }
+    }
+
+    /// Reads a keyring file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, KeyError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|why| KeyError::Read {
+            path: path.to_path_buf(),
+            why,
+        })?;
+
+        Self::parse(&data)
+    }
+
+    fn from_packets(data: &[u8]) -> Result<Self, KeyError> {
+        enum Component<'a> {
+            Primary,
+            UserId(&'a [u8]),
+            Subkey,
+            Other,
+        }
+
+        let mut certificates: Vec<Certificate> = Vec::new();
+        // Whether the packets belong to a certificate that is skipped.
+        let mut skipping = false;
+        let mut component = Component::Other;
+
+        for packet in packets(data)? {
+            if packet.tag == 6 {
+                skipping = true;
+                component = Component::Primary;
+                if let Some(primary) = PublicKey::parse(packet.body)? {
+                    skipping = false;
+                    certificates.push(Certificate {
+                        primary,
+                        user_ids: Vec::new(),
+                        subkeys: Vec::new(),
+                        raw: Vec::new(),
+                    });
+                }
+            }
+
+            let certificate = match certificates.last_mut() {
+                Some(certificate) if !skipping => certificate,
+                _ => continue,
+            };
+
+            // Trust packets are private to the keyring of gpg, and are not exported.
+            if packet.tag != 12 {
+                certificate.raw.extend_from_slice(packet.raw);
+            }
+
+            match packet.tag {
+                13 => {
+                    component = Component::UserId(packet.body);
+                    certificate
+                        .user_ids
+                        .push(String::from_utf8_lossy(packet.body).into_owned());
+                }
+                14 => match PublicKey::parse(packet.body)? {
+                    Some(subkey) => {
+                        component = Component::Subkey;
+                        certificate.subkeys.push(subkey);
+                    }
+                    None => component = Component::Other,
+                },
+                17 => component = Component::Other,
+                2 => {
+                    // Signatures which cannot be read are ignored, as gpg does.
+                    let Ok(signature) = SignaturePacket::parse(packet.body) else {
+                        continue;
+                    };
+
+                    if !signature.may_be_issued_by(&certificate.primary) {
+                        continue;
+                    }
+
+                    // Self-signatures only take effect once verified with the primary key, so
+                    // that a forged packet cannot change the expiry or revocation of a key.
+                    let primary = &certificate.primary;
+                    let signed = match (signature.sig_type, &component) {
+                        (0x10..=0x13, Component::UserId(user_id)) => {
+                            let len = (user_id.len() as u32).to_be_bytes();
+                            Some([&primary.hashed, &[0xB4][..], &len, user_id].concat())
+                        }
+                        (0x1F | 0x20, _) => Some(primary.hashed.clone()),
+                        (0x18 | 0x28, Component::Subkey) => certificate
+                            .subkeys
+                            .last()
+                            .map(|subkey| [&primary.hashed[..], &subkey.hashed].concat()),
+                        _ => None,
+                    };
+
+                    let verified = signed.map(|signed| verify_data(primary, &signature, &signed));
+                    let (primary, subkey) =
+                        (&mut certificate.primary, certificate.subkeys.last_mut());
+                    match verified {
+                        Some(Ok(true)) => (),
+                        // A subkey whose binding cannot be checked may not be trusted to sign,
+                        // but it is not known to be unbound either.
+                        Some(Err(what)) => {
+                            if let (0x18, Component::Subkey, Some(subkey)) =
+                                (signature.sig_type, &component, subkey)
+                            {
+                                if subkey.bound_at.is_none() {
+                                    subkey.signing = SigningBinding::Unverified(what);
+                                }
+                            }
+                            continue;
+                        }
+                        _ => continue,
+                    }
+
+                    match (signature.sig_type, &component, subkey) {
+                        (0x10..=0x13, Component::UserId(_), _) | (0x1F, _, _) => {
+                            primary.bind(&signature);
+                        }
+                        (0x20, _, _) => primary.revoked = true,
+                        (0x18, Component::Subkey, Some(subkey)) => {
+                            let latest = subkey.bind(&signature);
+                            if latest {
+                                subkey.signing = signing_binding(primary, subkey, &signature);
+                            }
+                        }
+                        (0x28, Component::Subkey, Some(subkey)) => subkey.revoked = true,
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Keyring { certificates })
+    }
+
+    /// Finds the certificates which are selected by the fingerprint.
+    pub fn find<'a>(
+        &'a self,
+        fingerprint: &'a Fingerprint,
+    ) -> impl Iterator<Item = &'a Certificate> + 'a {
+        self.certificates.iter().filter(|c| c.matches(fingerprint))
+    }
+
+    /// The binary form of the keyring, as in `.gpg` files.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.certificates
+            .iter()
+            .flat_map(|c| c.raw.iter().copied())
+            .collect()
+    }
+
+    /// The armored form of the keyring, as in `.asc` files.
+    pub fn to_armored(&self) -> String {
+        armor(&self.to_bytes())
+    }
+}
+
+/// A packet, with its header in `raw`.
+pub(crate) struct Packet<'a> {
+    pub tag: u8,
+    pub body: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+/// Splits binary OpenPGP data into its packets.
+pub(crate) fn packets(data: &[u8]) -> Result<Vec<Packet<'_>>, KeyError> {
+    let mut reader = Reader::new(data);
+    let mut packets = Vec::new();
+
+    while !reader.is_empty() {
+        let start = reader.pos;
+        let header = reader.u8()?;
+        if header & 0x80 == 0 {
+            return Err(malformed("invalid packet header"));
+        }
+
+        let (tag, len) = if header & 0x40 != 0 {
+            let first = reader.u8()? as usize;
+            let len = match first {
+                0..=191 => first,
+                192..=223 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+                255 => reader.u32()? as usize,
+                _ => {
+                    return Err(KeyError::Unsupported {
+                        what: "partial body lengths".into(),
+                    })
+                }
+            };
+            (header & 0x3F, len)
+        } else {
+            let len = match header & 0x03 {
+                0 => reader.u8()? as usize,
+                1 => reader.u16()? as usize,
+                2 => reader.u32()? as usize,
+                _ => reader.remaining(),
+            };
+            ((header >> 2) & 0x0F, len)
+        };
+
+        let body = reader.take(len)?;
+        packets.push(Packet {
+            tag,
+            body,
+            raw: &data[start..reader.pos],
+        });
+    }
+
+    Ok(packets)
+}
+
+/// Decodes every ASCII armored block of the text, concatenating their data.
+pub fn dearmor(text: &str) -> Result<Vec<u8>, KeyError> {
+    let armor_err = |why: &str| KeyError::Armor { why: why.into() };
+    let mut data = Vec::new();
+    let mut found = false;
+    let mut lines = text.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        if !(line.starts_with("-----BEGIN PGP ") && line.ends_with("-----")) {
+            continue;
+        }
+
+        found = true;
+        let mut encoded = String::new();
+        let mut checksum = None;
+        let mut in_headers = true;
+        loop {
+            let line = lines.next().ok_or_else(|| armor_err("missing end line"))?;
+            if line.starts_with("-----END PGP ") {
+                break;
+            }
+
+            if in_headers {
+                in_headers = line.contains(": ");
+                if in_headers || line.is_empty() {
+                    continue;
+                }
+            }
+
+            match line.strip_prefix('=') {
+                Some(crc) => checksum = Some(crc.to_owned()),
+                None => encoded.push_str(line),
+            }
+        }
+
+        let block = STANDARD
+            .decode(&encoded)
+            .map_err(|why| armor_err(&why.to_string()))?;
+
+        if let Some(checksum) = checksum {
+            let expected = STANDARD
+                .decode(&checksum)
+                .map_err(|why| armor_err(&why.to_string()))?;
+            if expected != crc24(&block).to_be_bytes()[1..] {
+                return Err(armor_err("checksum mismatch"));
+            }
+        }
+
+        data.extend(block);
+    }
+
+    if !found {
+        return Err(armor_err("no armored block"));
+    }
+
+    Ok(data)
+}
+
+/// Encodes a binary keyring as an armored public key block.
+pub fn armor(data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+    let mut text = String::from("-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n");
+    for chunk in encoded.as_bytes().chunks(64) {
+        text.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        text.push('\n');
+    }
+
+    text.push('=');
+    text.push_str(&STANDARD.encode(&crc24(data).to_be_bytes()[1..]));
+    text.push_str("\n-----END PGP PUBLIC KEY BLOCK-----\n");
+    text
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xB7_04CE;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= 0x186_4CFB;
+            }
+        }
+    }
+
+    crc & 0xFF_FFFF
+}
+
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn malformed(why: &str) -> KeyError {
+    KeyError::Malformed { why: why.into() }
+}
+
+/// A cursor over binary OpenPGP data.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], KeyError> {
+        if len > self.remaining() {
+            return Err(malformed("unexpected end of data"));
+        }
+
+        let data = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(data)
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        let data = &self.data[self.pos..];
+        self.pos = self.data.len();
+        data
+    }
+
+    pub fn u8(&mut self) -> Result<u8, KeyError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, KeyError> {
+        let data = self.take(2)?;
+        Ok(u16::from_be_bytes([data[0], data[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, KeyError> {
+        Ok(be_u32(self.take(4)?))
+    }
+
+    /// Reads a multiprecision integer, returning its size in bits and its bytes.
+    pub fn mpi(&mut self) -> Result<(u32, &'a [u8]), KeyError> {
+        let bits = u32::from(self.u16()?);
+        let data = self.take(bits.div_ceil(8) as usize)?;
+        Ok((bits, data))
+    }
+}
+
+/// Where the keys of an entry come from.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum KeySource {
+    /// A key block embedded in a `.sources` file.
+    Embedded(String),
+    /// A keyring file.
+    File(PathBuf),
+    /// A fingerprint, looked up in the keyrings trusted by apt.
+    Fingerprint(Fingerprint),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeySource::Embedded(_) => fmt.write_str("embedded key"),
+            KeySource::File(path) => write!(fmt, "{}", path.display()),
+            KeySource::Fingerprint(fingerprint) => write!(fmt, "{}", fingerprint),
+        }
+    }
+}
+
+/// The keys of a key source, and the entries which depend on them.
+#[derive(Debug)]
+pub struct KeyReport {
+    pub source: KeySource,
+    pub certificates: Result<Vec<Certificate>, KeyError>,
+    pub entries: Vec<EntryLocation>,
+}
+
+impl KeyReport {
+    /// The certificates which have expired at the given date.
+    pub fn expired(&self, date: Date) -> impl Iterator<Item = &Certificate> {
+        self.certificates
+            .iter()
+            .flatten()
+            .filter(move |c| c.is_expired(date))
+    }
+
+    /// The certificates which use an algorithm or size that apt rejects.
+    pub fn weak(&self) -> impl Iterator<Item = &Certificate> {
+        self.certificates.iter().flatten().filter(|c| c.is_weak())
+    }
+}
+
+/// Resolves an absolute path of the system below another root directory.
+pub(crate) fn rooted(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Reads the keyrings that apt trusts for every repository: **etc/apt/trusted.gpg** and the
+/// `.gpg` and `.asc` files of **etc/apt/trusted.gpg.d**, below the root argument.
+pub fn trusted_keyrings<P: AsRef<Path>>(root: P) -> Vec<(PathBuf, Result<Keyring, KeyError>)> {
+    let root = root.as_ref();
+    let mut paths = vec![root.join("etc/apt/trusted.gpg")];
+
+    if let Ok(dir) = fs::read_dir(root.join("etc/apt/trusted.gpg.d")) {
+        let mut files = dir
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|x| x == "gpg" || x == "asc"))
+            .collect::<Vec<_>>();
+        files.sort();
+        paths.extend(files);
+    }
+
+    paths
+        .into_iter()
+        .filter(|path| path.exists())
+        .map(|path| {
+            let keyring = Keyring::open(&path);
+            (path, keyring)
+        })
+        .collect()
+}
+
+impl SourcesLists {
+    /// Reads the keys that every entry refers to, grouped by where they come from.
+    ///
+    /// Keyring paths and the trusted keyrings searched for fingerprints are resolved below the
+    /// root argument, so that the keys of a system image can be inspected. Entries without a
+    /// `signed-by` option are not reported.
+    pub fn inspect_keys<P: AsRef<Path>>(&self, root: P) -> Vec<KeyReport> {
+        let root = root.as_ref();
+        let mut reports: Vec<KeyReport> = Vec::new();
+        let mut trusted = None;
+
+        for (location, entry) in self.located_entries() {
+            let sources = match &entry.signed_by {
+                None => continue,
+                Some(Signature::KeyBlock(armor)) => vec![KeySource::Embedded(armor.clone())],
+                Some(signature) => signature
+                    .keys()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|key| match key {
+                        KeyReference::Path(path) => KeySource::File(path),
+                        KeyReference::Fingerprint(fingerprint) => {
+                            KeySource::Fingerprint(fingerprint)
+                        }
+                    })
+                    .collect(),
+            };
+
+            for source in sources {
+                if let Some(report) = reports.iter_mut().find(|r| r.source == source) {
+                    if !report.entries.contains(&location) {
+                        report.entries.push(location.clone());
+                    }
+                    continue;
+                }
+
+                let certificates = match &source {
+                    KeySource::Embedded(armor) => {
+                        Keyring::parse(armor.as_bytes()).map(|k| k.certificates)
+                    }
+                    KeySource::File(path) => {
+                        Keyring::open(rooted(root, path)).map(|k| k.certificates)
+                    }
+                    KeySource::Fingerprint(fingerprint) => {
+                        let keyrings = trusted.get_or_insert_with(|| trusted_keyrings(root));
+                        Ok(keyrings
+                            .iter()
+                            .filter_map(|(_, keyring)| keyring.as_ref().ok())
+                            .flat_map(|keyring| keyring.find(fingerprint).cloned())
+                            .collect())
+                    }
+                };
+
+                reports.push(KeyReport {
+                    source,
+                    certificates,
+                    entries: vec![location.clone()],
+                });
+            }
+        }
+
+        reports
+    }
+
+    /// Writes the key embedded in the entry at the given location to a keyring file, and makes
+    /// every entry which embeds the same keys refer to that file instead.
+    ///
+    /// The keyring is written below the root argument by `SourcesLists::write_sync`, along
+    /// with the entries, which refer to it by the absolute `keyring` path. An existing file is
+    /// never overwritten. Since apt reads a keyring in the format given by its extension, the
+    /// path must end with the extension of the format. Returns the number of entries which were
+    /// changed.
+    pub fn extract_key<P: AsRef<Path>, R: AsRef<Path>>(
+        &mut self,
+        location: &EntryLocation,
+        keyring: P,
+        format: KeyringFormat,
+        root: R,
+    ) -> Result<usize, KeyError> {
+        let keyring = keyring.as_ref();
+        if keyring.extension().is_none_or(|x| x != format.extension()) {
+            return Err(KeyError::KeyringExtension {
+                path: keyring.to_path_buf(),
+                extension: format.extension(),
+            });
+        }
+
+        let armor = match self.entry_at(location).and_then(|e| e.signed_by.as_ref()) {
+            Some(Signature::KeyBlock(armor)) => armor.clone(),
+            _ => {
+                return Err(KeyError::NoEmbeddedKey {
+                    location: location.clone(),
+                })
+            }
+        };
+
+        let path = rooted(root.as_ref(), keyring);
+        if path.exists() || self.writes_to(&path) {
+            return Err(KeyError::KeyringExists { path });
+        }
+
+        // Rejects embedded keys which cannot be read, rather than writing a broken keyring.
+        let fingerprints = key_fingerprints(&armor)?;
+        let contents = match format {
+            KeyringFormat::Armored => format!("{}\n", armor).into_bytes(),
+            KeyringFormat::Binary => dearmor(&armor)?,
+        };
+
+        let mut changed = 0;
+        self.entries_mut(|entry| {
+            // The same keys may be embedded with different armor headers or line lengths.
+            let same_keys = match &entry.signed_by {
+                Some(Signature::KeyBlock(other)) => {
+                    key_fingerprints(other).is_ok_and(|other| other == fingerprints)
+                }
+                _ => false,
+            };
+            if !same_keys {
+                return false;
+            }
+
+            entry.signed_by = Some(Signature::KeyPath(vec![keyring.to_path_buf()]));
+            changed += 1;
+            true
+        });
+
+        self.operations
+            .push(FileOperation::Write { path, contents });
+        Ok(changed)
+    }
+
+    /// Embeds a keyring file into every entry which refers to it alone.
+    ///
+    /// The keyring is read below the root argument, and matched against entries by its
+    /// absolute `keyring` path. Only deb822 entries can embed keys, so one-line style entries
+    /// are left unchanged, as are entries which refer to other keys as well. Returns the
+    /// number of entries which were changed.
+    pub fn inline_key<P: AsRef<Path>, R: AsRef<Path>>(
+        &mut self,
+        keyring: P,
+        root: R,
+    ) -> Result<usize, KeyError> {
+        let keyring = keyring.as_ref();
+        let path = rooted(root.as_ref(), keyring);
+        let data = fs::read(&path).map_err(|why| KeyError::Read {
+            path: path.clone(),
+            why,
+        })?;
+
+        let parsed = Keyring::parse(&data)?;
+        let armor = match std::str::from_utf8(&data) {
+            Ok(text) if text.trim_start().starts_with("-----BEGIN PGP") => text.trim().to_owned(),
+            _ => parsed.to_armored().trim().to_owned(),
+        };
+
+        let mut changed = 0;
+        self.entries_mut(|entry| {
+            let refers =
+                matches!(&entry.signed_by, Some(Signature::KeyPath(paths)) if paths == &[keyring]);
+            if !entry.is_deb822 || !refers {
+                return false;
+            }
+
+            entry.signed_by = Some(Signature::KeyBlock(armor.clone()));
+            changed += 1;
+            true
+        });
+
+        Ok(changed)
+    }
+}
+
+/// The fingerprints of the primary keys of an armored keyring.
+fn key_fingerprints(armor: &str) -> Result<Vec<String>, KeyError> {
+    let keyring = Keyring::parse(armor.as_bytes())?;
+    Ok(keyring
+        .certificates
+        .iter()
+        .map(|c| c.fingerprint().to_owned())
+        .collect())
+}
+
+/// The encoding of a keyring file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyringFormat {
+    /// ASCII armored, with the `.asc` extension.
+    Armored,
+    /// Binary, as produced by `gpg --dearmor`, with the `.gpg` extension.
+    Binary,
+}
+
+impl KeyringFormat {
+    /// The extension which apt expects of a keyring in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            KeyringFormat::Armored => "asc",
+            KeyringFormat::Binary => "gpg",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_deb822::SourceListDeb822;
+    use crate::tests::{self, TempRoot};
+    use crate::{SourceListType, SourcesList};
+    use std::path::PathBuf;
+
+    fn root() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/root")
+    }
+
+    #[test]
+    fn armored_keyring() {
+        let keyring = Keyring::open(rooted(
+            &root(),
+            Path::new("/etc/apt/trusted.gpg.d/debian-archive-bookworm-stable.asc"),
+        ))
+        .unwrap();
+
+        assert_eq!(keyring.certificates.len(), 1);
+        let certificate = &keyring.certificates[0];
+        assert_eq!(
+            certificate.fingerprint(),
+            "4D64FEC119C2029067D6E791F8D2585B8783D481"
+        );
+        assert_eq!(certificate.primary.key_id(), "F8D2585B8783D481");
+        assert_eq!(
+            certificate.user_ids,
+            ["Debian Stable Release Key (12/bookworm) <debian-release@lists.debian.org>"]
+        );
+        assert_eq!(
+            certificate.primary.algorithm(),
+            PublicKeyAlgorithm::EdDsaLegacy
+        );
+        assert_eq!(certificate.primary.curve(), Some(&Curve::Ed25519));
+        assert_eq!(certificate.primary.created(), Date::new(2023, 1, 23));
+        assert_eq!(certificate.primary.expires(), Some(Date::new(2031, 1, 21)));
+        assert!(!certificate.is_weak());
+        assert!(!certificate.is_expired(Date::new(2026, 10, 18)));
+        assert!(certificate.is_expired(Date::new(2031, 1, 21)));
+    }
+
+    #[test]
+    fn binary_keyring() {
+        let keyring = Keyring::open(root().join("usr/share/keyrings/legacy-rsa1024.gpg")).unwrap();
+        let certificate = &keyring.certificates[0];
+
+        assert_eq!(
+            certificate.fingerprint(),
+            "B92634F02D319DE1C0ED0AF1028C4910BBE90A9C"
+        );
+        assert_eq!(certificate.primary.algorithm(), PublicKeyAlgorithm::Rsa);
+        assert_eq!(certificate.primary.bits(), Some(1024));
+        assert_eq!(certificate.primary.expires(), Some(Date::new(2020, 1, 1)));
+        assert!(certificate.is_weak());
+        assert!(certificate.is_expired(Date::new(2026, 10, 18)));
+    }
+
+    #[test]
+    fn forged_self_signatures() {
+        // The self-signature is the last packet, and no longer verifies once changed.
+        let mut data = fs::read(root().join("usr/share/keyrings/legacy-rsa1024.gpg")).unwrap();
+        *data.last_mut().unwrap() ^= 0x01;
+        let keyring = Keyring::parse(&data).unwrap();
+        assert_eq!(keyring.certificates[0].primary.expires(), None);
+
+        // The length of a version 4 key is hashed in 16 bits.
+        let mut body = vec![4, 0, 0, 0, 0, 1];
+        body.resize(0x10000, 0);
+        let mut packet = vec![0x9A];
+        packet.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        packet.extend_from_slice(&body);
+        assert!(matches!(
+            Keyring::parse(&packet),
+            Err(KeyError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn armor_roundtrip() {
+        let keyring = Keyring::open(root().join("usr/share/keyrings/legacy-rsa1024.gpg")).unwrap();
+        let armored = keyring.to_armored();
+
+        assert!(armored.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n"));
+        assert_eq!(Keyring::parse(armored.as_bytes()).unwrap(), keyring);
+
+        let corrupted = armored.replacen('A', "B", 1);
+        assert!(matches!(
+            Keyring::parse(corrupted.as_bytes()),
+            Err(KeyError::Armor { .. })
+        ));
+    }
+
+    #[test]
+    fn inspect() {
+        let text = "\
+deb [signed-by=/usr/share/keyrings/example-archive.asc] https://example.org/debian stable main
+deb [signed-by=/usr/share/keyrings/example-archive.asc] https://example.org/debian stable-updates main
+deb [signed-by=4D64FEC119C2029067D6E791F8D2585B8783D481] http://deb.debian.org/debian bookworm main
+deb [signed-by=/usr/share/keyrings/legacy-rsa1024.gpg] https://legacy.example.com/apt ./
+deb [signed-by=/usr/share/keyrings/missing.gpg] https://missing.example.com/apt ./
+deb http://deb.debian.org/debian-security bookworm-security main
+";
+        let lists = tests::lists(&[("sources.list", text)]);
+
+        let reports = lists.inspect_keys(root());
+        assert_eq!(reports.len(), 4);
+
+        let example = &reports[0];
+        assert_eq!(
+            example.source,
+            KeySource::File("/usr/share/keyrings/example-archive.asc".into())
+        );
+        assert_eq!(
+            example.entries.iter().map(|l| l.index).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        let certificates = example.certificates.as_ref().unwrap();
+        assert_eq!(
+            certificates[0].user_ids,
+            ["Example Archive Automatic Signing Key <ftpmaster@example.org>"]
+        );
+        assert_eq!(
+            certificates[0].primary.expires(),
+            Some(Date::new(2034, 1, 1))
+        );
+
+        let debian = &reports[1];
+        assert!(matches!(debian.source, KeySource::Fingerprint(_)));
+        assert_eq!(debian.certificates.as_ref().unwrap().len(), 1);
+
+        let legacy = &reports[2];
+        let today = Date::new(2026, 10, 18);
+        assert_eq!(legacy.expired(today).count(), 1);
+        assert_eq!(legacy.weak().count(), 1);
+
+        assert!(matches!(
+            reports[3].certificates,
+            Err(KeyError::Read { .. })
+        ));
+    }
+
+    /// Formats a deb822 stanza which embeds the armored key.
+    fn key_stanza(suite: &str, armor: &str) -> String {
+        let key = armor
+            .trim()
+            .lines()
+            .map(|line| if line.is_empty() { " ." } else { line })
+            .collect::<Vec<_>>()
+            .join("\n ");
+        format!(
+            "Types: deb\nURIs: https://example.org/debian\nSuites: {} \nComponents: main\nSigned-By:\n {}\n",
+            suite, key
+        )
+    }
+
+    fn example_armor() -> String {
+        fs::read_to_string(root().join("usr/share/keyrings/example-archive.asc")).unwrap()
+    }
+
+    #[test]
+    fn extract_and_inline() {
+        let armor = example_armor();
+        let target = TempRoot::new("inline-key");
+        let sources = target.join("example.sources");
+        let text = format!(
+            "{}\n{}",
+            key_stanza("stable", &armor),
+            key_stanza("testing", &armor)
+        );
+
+        let mut lists = SourcesLists::from_files(vec![SourcesList {
+            path: sources.clone(),
+            entries: SourceListType::Deb822(text.parse::<SourceListDeb822>().unwrap()),
+        }]);
+
+        let keyring = Path::new("/etc/apt/keyrings/example.gpg");
+        let location = EntryLocation {
+            path: sources.clone(),
+            index: 1,
+        };
+
+        assert_eq!(
+            lists
+                .extract_key(&location, keyring, KeyringFormat::Binary, &target)
+                .unwrap(),
+            2
+        );
+        assert_eq!(lists.modified, [sources]);
+        assert_eq!(
+            lists.entries().next().unwrap().signed_by,
+            Some(Signature::KeyPath(vec![keyring.into()]))
+        );
+        assert!(matches!(
+            lists.extract_key(&location, keyring, KeyringFormat::Binary, &target),
+            Err(KeyError::NoEmbeddedKey { .. })
+        ));
+
+        // The keyring is only written along with the entries.
+        assert!(!rooted(&target, keyring).exists());
+        assert_eq!(
+            lists.pending_operations(),
+            [FileOperation::Write {
+                path: rooted(&target, keyring),
+                contents: dearmor(&armor).unwrap(),
+            }]
+        );
+
+        lists.write_sync().unwrap();
+        let written = fs::read(rooted(&target, keyring)).unwrap();
+        assert_eq!(written, dearmor(&armor).unwrap());
+
+        assert_eq!(lists.inline_key(keyring, &target).unwrap(), 2);
+
+        // The armor of the keyring file is regenerated, so compare the keys.
+        let embedded = match &lists.entries().next().unwrap().signed_by {
+            Some(Signature::KeyBlock(armor)) => armor.clone(),
+            other => panic!("expected an embedded key, got {:?}", other),
+        };
+        assert_eq!(dearmor(&embedded).unwrap(), written);
+    }
+
+    #[test]
+    fn extract_armored() {
+        let armor = example_armor();
+        let commented = armor.replacen(
+            "BLOCK-----\n\n",
+            "BLOCK-----\nComment: exported again\n\n",
+            1,
+        );
+
+        // Keys are matched by fingerprint, whatever their armor.
+        let text = format!(
+            "{}\n{}",
+            key_stanza("stable", &armor),
+            key_stanza("testing", &commented)
+        );
+        let mut lists = tests::lists(&[("example.sources", &text)]);
+
+        let target = TempRoot::new("extract-armored");
+        let keyring = Path::new("/etc/apt/keyrings/example.asc");
+        let location = tests::location("example.sources", 0);
+
+        assert_eq!(
+            lists
+                .extract_key(&location, keyring, KeyringFormat::Armored, &target)
+                .unwrap(),
+            2
+        );
+        let contents = match lists.pending_operations() {
+            [FileOperation::Write { contents, .. }] => String::from_utf8(contents.clone()).unwrap(),
+            other => panic!("expected a keyring write, got {:?}", other),
+        };
+        assert_eq!(contents.trim(), armor.trim());
+        assert!(contents.ends_with('\n'));
+    }
+
+    #[test]
+    fn extract_errors() {
+        let armor = example_armor();
+        let text = format!(
+            "{}\n{}",
+            key_stanza("stable", &armor),
+            key_stanza("testing", &armor)
+        );
+        let mut lists = tests::lists(&[("example.sources", &text)]);
+
+        let target = TempRoot::new("extract-errors");
+        let at = |index| tests::location("example.sources", index);
+
+        // A keyring whose extension apt would read in another format is refused.
+        for (path, format) in [
+            ("/etc/apt/keyrings/example.gpg", KeyringFormat::Armored),
+            ("/etc/apt/keyrings/example.asc", KeyringFormat::Binary),
+            ("/etc/apt/keyrings/example", KeyringFormat::Binary),
+        ] {
+            assert!(matches!(
+                lists.extract_key(&at(0), path, format, &target),
+                Err(KeyError::KeyringExtension { .. })
+            ));
+        }
+
+        // An existing keyring is never overwritten, nor one which is still to be written.
+        let existing = Path::new("/etc/apt/keyrings/existing.gpg");
+        fs::create_dir_all(rooted(&target, existing).parent().unwrap()).unwrap();
+        fs::write(rooted(&target, existing), b"").unwrap();
+        assert!(matches!(
+            lists.extract_key(&at(0), existing, KeyringFormat::Binary, &target),
+            Err(KeyError::KeyringExists { .. })
+        ));
+
+        let keyring = Path::new("/etc/apt/keyrings/example.gpg");
+        lists.files[0].entry_mut(1).unwrap().signed_by = Some(Signature::KeyBlock(
+            "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nAAAA\n-----END PGP PUBLIC KEY BLOCK-----"
+                .into(),
+        ));
+        assert_eq!(
+            lists
+                .extract_key(&at(0), keyring, KeyringFormat::Binary, &target)
+                .unwrap(),
+            1
+        );
+        assert!(matches!(
+            lists.extract_key(&at(1), keyring, KeyringFormat::Binary, &target),
+            Err(KeyError::KeyringExists { .. })
+        ));
+
+        // Embedded keys which cannot be read are not extracted.
+        let other = Path::new("/etc/apt/keyrings/other.gpg");
+        assert!(lists
+            .extract_key(&at(1), other, KeyringFormat::Binary, &target)
+            .is_err());
+        assert_eq!(lists.pending_operations().len(), 1);
+
+        assert!(matches!(
+            lists.inline_key(other, &target),
+            Err(KeyError::Read { .. })
+        ));
+    }
+
+    #[test]
+    fn inline_skips_one_line_entries() {
+        let target = TempRoot::new("inline-one-line");
+        let keyring = Path::new("/etc/apt/keyrings/example.asc");
+        fs::create_dir_all(rooted(&target, keyring).parent().unwrap()).unwrap();
+        fs::write(rooted(&target, keyring), example_armor()).unwrap();
+
+        let line = "deb [signed-by=/etc/apt/keyrings/example.asc] https://example.org/debian stable main\n";
+        let stanza = "Types: deb\nURIs: https://example.org/debian\nSuites: stable\nComponents: main\nSigned-By: /etc/apt/keyrings/example.asc\n";
+        let mut lists = tests::lists(&[("example.list", line), ("example.sources", stanza)]);
+
+        assert_eq!(lists.inline_key(keyring, &target).unwrap(), 1);
+        assert_eq!(lists.files[0].to_string(), line);
+        assert!(matches!(
+            lists.files[1].entry(0).unwrap().signed_by,
+            Some(Signature::KeyBlock(_))
+        ));
+        assert_eq!(lists.modified, [PathBuf::from("example.sources")]);
+    }
+}
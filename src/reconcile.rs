@@ -0,0 +1,384 @@
+//! Convergence of the sources files towards a desired set of repositories.
+//!
+//! Each desired repository names the file that it should be written to, and the format of that
+//! file follows its extension, as with `SourcesLists::insert_entry`. The files named by the
+//! desired repositories are owned: their entries are rewritten to match exactly. What happens to
+//! the entries of the other files depends on the `ReconcileMode`.
+
+use crate::changes::restyled;
+use crate::duplicates::normalized_url;
+use crate::{
+    Change, ChangeSet, EntryLocation, SourceEntry, SourceError, SourceFormat, SourceListType,
+    SourceResult, SourcesLists,
+};
+use std::path::{Path, PathBuf};
+
+/// A repository which should be configured, and the file which should hold it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DesiredRepository {
+    /// The file of the entry, whose extension selects the format.
+    pub path: PathBuf,
+    pub entry: SourceEntry,
+}
+
+impl DesiredRepository {
+    pub fn new<P: Into<PathBuf>>(path: P, entry: SourceEntry) -> Self {
+        DesiredRepository {
+            path: path.into(),
+            entry,
+        }
+    }
+}
+
+/// How the entries outside of the owned files are handled.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ReconcileMode {
+    /// Only the files named by the desired repositories are managed.
+    #[default]
+    Owned,
+    /// The desired repositories are the only enabled ones. Entries of the other files are
+    /// disabled, rather than removed, so that they can be restored.
+    Exact,
+}
+
+impl SourcesLists {
+    /// Computes the changes which make the lists match the desired repositories.
+    ///
+    /// Existing entries of an owned file are matched with the desired repositories by type, URI
+    /// and suite, then by type and URI, so that a change of suite or components is made in place.
+    /// Entries which differ in other ways are replaced, unmatched entries are removed, and the
+    /// remaining repositories are appended to their file, which is created if needed. The lists
+    /// are not modified: apply or preview the returned `ChangeSet`.
+    pub fn reconcile(
+        &self,
+        desired: &[DesiredRepository],
+        mode: ReconcileMode,
+    ) -> SourceResult<ChangeSet> {
+        let mut owned: Vec<(&Path, Vec<&SourceEntry>)> = Vec::new();
+        for repo in desired {
+            match owned.iter_mut().find(|(path, _)| *path == repo.path) {
+                Some((_, entries)) => entries.push(&repo.entry),
+                None => owned.push((&repo.path, vec![&repo.entry])),
+            }
+        }
+
+        let mut changes = ChangeSet::new();
+        for (path, entries) in owned {
+            self.reconcile_file(path, &entries, &mut changes)?;
+        }
+
+        if mode == ReconcileMode::Exact {
+            for list in self.iter() {
+                if desired.iter().any(|repo| repo.path == list.path) {
+                    continue;
+                }
+
+                for (index, entry) in list.indexed_entries() {
+                    if entry.enabled {
+                        changes.push(Change::Disable {
+                            location: EntryLocation {
+                                path: list.path.clone(),
+                                index,
+                            },
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn reconcile_file(
+        &self,
+        path: &Path,
+        desired: &[&SourceEntry],
+        changes: &mut ChangeSet,
+    ) -> SourceResult<()> {
+        let location = |index| EntryLocation {
+            path: path.to_path_buf(),
+            index,
+        };
+
+        let list = self.iter().find(|list| list.path == path);
+        let format = match list {
+            Some(list) => list.format(),
+            None => SourceFormat::from_path(path).ok_or_else(|| SourceError::UnknownFormat {
+                path: path.to_path_buf(),
+            })?,
+        };
+
+        let desired = desired
+            .iter()
+            .map(|entry| restyled(entry, format))
+            .collect::<SourceResult<Vec<_>>>()?;
+
+        if list.is_none() {
+            changes.push(Change::CreateFile {
+                path: path.to_path_buf(),
+            });
+        }
+
+        let current = list.map_or_else(Vec::new, |list| list.indexed_entries().collect());
+        let mut len = match list.map(|list| &list.entries) {
+            Some(SourceListType::SourceLine(lines)) => lines.0.len(),
+            Some(SourceListType::Deb822(e)) => e.entries.len(),
+            None => 0,
+        };
+
+        // Pairs each desired entry with an existing one, preferring those of the same suite.
+        let mut matched: Vec<Option<usize>> = vec![None; desired.len()];
+        let mut used = vec![false; current.len()];
+        let passes: [fn(&SourceEntry, &SourceEntry) -> bool; 2] =
+            [SourceEntry::same_target, |a, b| {
+                a.source == b.source && normalized_url(&a.url) == normalized_url(&b.url)
+            }];
+        for pass in passes {
+            for (want, slot) in desired.iter().zip(&mut matched) {
+                if slot.is_some() {
+                    continue;
+                }
+
+                let found =
+                    (0..current.len()).find(|&pos| !used[pos] && pass(current[pos].1, want));
+                if let Some(pos) = found {
+                    used[pos] = true;
+                    *slot = Some(pos);
+                }
+            }
+        }
+
+        // Changes in place first, as they keep the indices of the entries.
+        let mut replaced = Vec::new();
+        for (want, slot) in desired.iter().zip(&matched) {
+            let Some(pos) = *slot else { continue };
+            let (index, entry) = current[pos];
+
+            let mut edits = Vec::new();
+            let mut expected = entry.clone();
+            if entry.enabled != want.enabled {
+                expected.enabled = want.enabled;
+                edits.push(if want.enabled {
+                    Change::Enable {
+                        location: location(index),
+                    }
+                } else {
+                    Change::Disable {
+                        location: location(index),
+                    }
+                });
+            }
+
+            if entry.suite != want.suite {
+                expected.suite.clone_from(&want.suite);
+                edits.push(Change::SetSuite {
+                    location: location(index),
+                    from: entry.suite.clone(),
+                    to: want.suite.clone(),
+                });
+            }
+
+            for component in &entry.components {
+                if !want.components.contains(component) {
+                    edits.push(Change::RemoveComponent {
+                        location: location(index),
+                        component: component.clone(),
+                    });
+                }
+            }
+
+            for component in &want.components {
+                if !entry.components.contains(component) {
+                    edits.push(Change::AddComponent {
+                        location: location(index),
+                        component: component.clone(),
+                    });
+                }
+            }
+
+            // The edits leave the same components, whose order does not matter to apt.
+            expected.components.clone_from(&want.components);
+
+            if expected == *want {
+                changes.changes.extend(edits);
+            } else {
+                replaced.push((index, entry, want));
+            }
+        }
+
+        // Then removals and replacements, from the end so that earlier indices are kept.
+        let mut removals = current
+            .iter()
+            .enumerate()
+            .filter(|&(pos, _)| !used[pos])
+            .map(|(_, &(index, entry))| (index, entry, None))
+            .chain(
+                replaced
+                    .into_iter()
+                    .map(|(index, entry, want)| (index, entry, Some(want))),
+            )
+            .collect::<Vec<_>>();
+        removals.sort_by_key(|&(index, ..)| std::cmp::Reverse(index));
+
+        for (index, entry, want) in removals {
+            changes.push(Change::Remove {
+                location: location(index),
+                entry: entry.clone(),
+            });
+
+            match want {
+                Some(want) => {
+                    changes.push(Change::Insert {
+                        location: location(index),
+                        entry: want.clone(),
+                    });
+                }
+                None => len -= 1,
+            }
+        }
+
+        // Finally the new entries, at the end of the file.
+        for (want, slot) in desired.into_iter().zip(matched) {
+            if slot.is_none() {
+                changes.push(Change::Insert {
+                    location: location(len),
+                    entry: want,
+                });
+                len += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests;
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[
+            (
+                "debian.list",
+                "# Managed\n\
+                 deb http://deb.debian.org/debian bookworm main\n\
+                 deb http://deb.debian.org/debian bookworm-backports main\n\
+                 deb [arch=amd64] http://deb.debian.org/debian-security bookworm-security main\n",
+            ),
+            ("vendor.list", "deb https://example.org/repo stable main\n"),
+        ])
+    }
+
+    fn desired() -> Vec<DesiredRepository> {
+        let repo = |path: &str, line: &str| DesiredRepository::new(path, line.parse().unwrap());
+        vec![
+            repo(
+                "debian.list",
+                "deb http://deb.debian.org/debian trixie main contrib",
+            ),
+            repo(
+                "debian.list",
+                "deb http://deb.debian.org/debian-security trixie-security main",
+            ),
+            repo(
+                "debian.list",
+                "deb-src http://deb.debian.org/debian trixie main",
+            ),
+            repo(
+                "extra.sources",
+                "deb https://extra.example.org/apt stable main",
+            ),
+        ]
+    }
+
+    #[test]
+    fn owned() {
+        let mut lists = lists();
+        let changes = lists.reconcile(&desired(), ReconcileMode::Owned).unwrap();
+
+        let location = |path: &str, index| EntryLocation {
+            path: path.into(),
+            index,
+        };
+        let kinds = changes
+            .changes
+            .iter()
+            .map(|change| match change {
+                Change::SetSuite { location, .. } => ("set-suite", location.clone()),
+                Change::AddComponent { location, .. } => ("add-component", location.clone()),
+                Change::Remove { location, .. } => ("remove", location.clone()),
+                Change::Insert { location, .. } => ("insert", location.clone()),
+                Change::CreateFile { path } => ("create-file", location(path.to_str().unwrap(), 0)),
+                other => panic!("unexpected change {:?}", other),
+            })
+            .collect::<Vec<_>>();
+
+        // The suite of the first entry changes in place, the backports entry is removed, and the
+        // security entry, which loses its options, is replaced.
+        assert_eq!(
+            kinds,
+            [
+                ("set-suite", location("debian.list", 1)),
+                ("add-component", location("debian.list", 1)),
+                ("remove", location("debian.list", 3)),
+                ("insert", location("debian.list", 3)),
+                ("remove", location("debian.list", 2)),
+                ("insert", location("debian.list", 3)),
+                ("create-file", location("extra.sources", 0)),
+                ("insert", location("extra.sources", 0)),
+            ]
+        );
+
+        changes.apply(&mut lists).unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            "# Managed\n\
+             deb http://deb.debian.org/debian trixie main contrib\n\
+             deb http://deb.debian.org/debian-security trixie-security main\n\
+             deb-src http://deb.debian.org/debian trixie main\n"
+        );
+        assert_eq!(
+            lists.files[1].to_string(),
+            "deb https://example.org/repo stable main\n"
+        );
+        assert_eq!(lists.files[2].path, Path::new("extra.sources"));
+
+        // Once converged, there is nothing left to do.
+        let again = lists.reconcile(&desired(), ReconcileMode::Owned).unwrap();
+        assert_eq!(again, ChangeSet::new());
+    }
+
+    #[test]
+    fn exact() {
+        let mut lists = lists();
+        let changes = lists.reconcile(&desired(), ReconcileMode::Exact).unwrap();
+        assert_eq!(
+            changes.changes.last(),
+            Some(&Change::Disable {
+                location: EntryLocation {
+                    path: "vendor.list".into(),
+                    index: 0,
+                },
+            })
+        );
+
+        changes.apply(&mut lists).unwrap();
+        assert_eq!(
+            lists.files[1].to_string(),
+            "# deb https://example.org/repo stable main\n"
+        );
+        assert_eq!(
+            lists.reconcile(&desired(), ReconcileMode::Exact).unwrap(),
+            ChangeSet::new()
+        );
+
+        // An embedded key cannot be placed in a one-line file.
+        let mut repo = desired().remove(0);
+        repo.entry.signed_by = Some(crate::Signature::KeyBlock("key".into()));
+        assert!(matches!(
+            lists.reconcile(&[repo], ReconcileMode::Owned),
+            Err(SourceError::InvalidValue { .. })
+        ));
+    }
+}
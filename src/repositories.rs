@@ -0,0 +1,497 @@
+//! A view of the entries grouped into logical repositories.
+//!
+//! Entries are expanded per type, URI and suite, whereas users think of "the Debian archive"
+//! with its suites, components and source code. Entries which share a URI and options are
+//! folded into a `LogicalRepository`, across files and formats.
+
+use crate::duplicates::normalized_url;
+use crate::{Change, ChangeSet, EntryLocation, SourceEntry, SourceResult, SourcesLists};
+
+/// Entries which share a URI and options.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogicalRepository {
+    /// The URI of the first entry of the repository.
+    pub url: String,
+    /// The suites of the entries, in order of appearance.
+    pub suites: Vec<String>,
+    /// The components of the entries, in order of appearance.
+    pub components: Vec<String>,
+    /// Whether any binary (`deb`) entry is enabled.
+    pub binary: bool,
+    /// Whether any source code (`deb-src`) entry is enabled.
+    pub source: bool,
+    /// The entries of the repository.
+    pub entries: Vec<EntryLocation>,
+}
+
+impl LogicalRepository {
+    /// Whether any entry of the repository is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.binary || self.source
+    }
+}
+
+/// What entries of a repository have in common, in the one-line style.
+fn shared_fields(entry: &SourceEntry) -> SourceEntry {
+    let mut shared = entry.clone();
+    shared.set_style(false);
+    shared.enabled = true;
+    shared.source = false;
+    shared.url = normalized_url(&entry.url);
+    shared.suite.clear();
+    shared.components.clear();
+    shared
+}
+
+impl SourcesLists {
+    /// Groups the entries into logical repositories, in order of appearance.
+    pub fn repositories(&self) -> Vec<LogicalRepository> {
+        let mut repositories: Vec<(SourceEntry, LogicalRepository)> = Vec::new();
+
+        for (location, entry) in self.located_entries() {
+            let shared = shared_fields(entry);
+            let pos = match repositories.iter().position(|(s, _)| *s == shared) {
+                Some(pos) => pos,
+                None => {
+                    repositories.push((
+                        shared,
+                        LogicalRepository {
+                            url: entry.url.clone(),
+                            suites: Vec::new(),
+                            components: Vec::new(),
+                            binary: false,
+                            source: false,
+                            entries: Vec::new(),
+                        },
+                    ));
+                    repositories.len() - 1
+                }
+            };
+
+            let repo = &mut repositories[pos].1;
+            if !repo.suites.contains(&entry.suite) {
+                repo.suites.push(entry.suite.clone());
+            }
+
+            for component in &entry.components {
+                if !repo.components.contains(component) {
+                    repo.components.push(component.clone());
+                }
+            }
+
+            if entry.enabled {
+                if entry.source {
+                    repo.source = true;
+                } else {
+                    repo.binary = true;
+                }
+            }
+
+            repo.entries.push(location);
+        }
+
+        repositories.into_iter().map(|(_, repo)| repo).collect()
+    }
+
+    /// Enables or disables a repository.
+    ///
+    /// Enabling a repository enables its binary entries, while disabling it disables all of
+    /// its entries, including source code.
+    pub fn set_repository_enabled(
+        &mut self,
+        repo: &LogicalRepository,
+        enabled: bool,
+    ) -> SourceResult<()> {
+        let mut changes = ChangeSet::new();
+        for location in &repo.entries {
+            let Some(entry) = self.entry_at(location) else {
+                continue;
+            };
+
+            let location = location.clone();
+            if enabled && !entry.enabled && !entry.source {
+                changes.push(Change::Enable { location });
+            } else if !enabled && entry.enabled {
+                changes.push(Change::Disable { location });
+            }
+        }
+
+        changes.apply(self)
+    }
+
+    /// Enables or disables the source code (`deb-src`) of a repository.
+    ///
+    /// Enabling source code gives every enabled binary entry a `deb-src` counterpart in the
+    /// same file, enabling an existing one or adding one after the binary entry, so that a
+    /// deb822 stanza lists both types. Disabling source code disables the `deb-src` entries,
+    /// rather than removing them.
+    pub fn set_source_code(&mut self, repo: &LogicalRepository, enabled: bool) -> SourceResult<()> {
+        let mut changes = ChangeSet::new();
+
+        if !enabled {
+            for location in &repo.entries {
+                if self
+                    .entry_at(location)
+                    .is_some_and(|entry| entry.source && entry.enabled)
+                {
+                    changes.push(Change::Disable {
+                        location: location.clone(),
+                    });
+                }
+            }
+
+            return changes.apply(self);
+        }
+
+        let mut inserts = Vec::new();
+        for location in &repo.entries {
+            let Some(entry) = self.entry_at(location) else {
+                continue;
+            };
+
+            if entry.source || !entry.enabled {
+                continue;
+            }
+
+            let mut wanted = entry.clone();
+            wanted.source = true;
+
+            let counterpart = repo.entries.iter().find(|other| {
+                other.path == location.path
+                    && self.entry_at(other).is_some_and(|other| {
+                        let mut other = other.clone();
+                        other.enabled = true;
+                        other == wanted
+                    })
+            });
+
+            match counterpart {
+                Some(other) if self.entry_at(other).is_some_and(|e| e.enabled) => (),
+                Some(other) => {
+                    changes.push(Change::Enable {
+                        location: other.clone(),
+                    });
+                }
+                None => inserts.push((location.clone(), wanted)),
+            }
+        }
+
+        // Entries are inserted from the end, so that the other locations stay valid.
+        inserts.sort_by(|(a, _), (b, _)| b.path.cmp(&a.path).then(b.index.cmp(&a.index)));
+        for (location, entry) in inserts {
+            changes.push(Change::Insert {
+                location: EntryLocation {
+                    index: location.index + 1,
+                    path: location.path,
+                },
+                entry,
+            });
+        }
+
+        changes.apply(self)
+    }
+}
+
+/// An edit of an entry of a repository.
+enum Edit {
+    Change(Change),
+    /// The entry is removed, as it would be left without components or architectures.
+    Remove,
+}
+
+impl SourcesLists {
+    /// Adds a component to every entry of the repositories which lacks it.
+    ///
+    /// Entries of flat repositories, which have no components, are left untouched.
+    pub fn add_component(
+        &mut self,
+        repos: &[LogicalRepository],
+        component: &str,
+    ) -> SourceResult<()> {
+        self.edit_repositories(repos, |entry, location| {
+            if entry.components.is_empty() || entry.components.iter().any(|c| c == component) {
+                return None;
+            }
+
+            Some(Edit::Change(Change::AddComponent {
+                location,
+                component: component.to_owned(),
+            }))
+        })
+    }
+
+    /// Removes a component from the entries of the repositories.
+    ///
+    /// Entries whose only component it is are removed.
+    pub fn remove_component(
+        &mut self,
+        repos: &[LogicalRepository],
+        component: &str,
+    ) -> SourceResult<()> {
+        self.edit_repositories(repos, |entry, location| {
+            if !entry.components.iter().any(|c| c == component) {
+                None
+            } else if entry.components.len() == 1 {
+                Some(Edit::Remove)
+            } else {
+                Some(Edit::Change(Change::RemoveComponent {
+                    location,
+                    component: component.to_owned(),
+                }))
+            }
+        })
+    }
+
+    /// Adds an architecture to the entries of the repositories which are restricted to others.
+    ///
+    /// Entries without architectures already apply to every architecture of the system, and
+    /// are left untouched.
+    pub fn add_architecture(
+        &mut self,
+        repos: &[LogicalRepository],
+        architecture: &str,
+    ) -> SourceResult<()> {
+        self.edit_repositories(repos, |entry, location| match &entry.archs {
+            Some(archs) if !archs.iter().any(|a| a == architecture) => {
+                Some(Edit::Change(Change::AddArchitecture {
+                    location,
+                    architecture: architecture.to_owned(),
+                }))
+            }
+            _ => None,
+        })
+    }
+
+    /// Removes an architecture from the entries of the repositories which are restricted to it.
+    ///
+    /// Entries whose only architecture it is are removed, and entries without architectures
+    /// are left untouched.
+    pub fn remove_architecture(
+        &mut self,
+        repos: &[LogicalRepository],
+        architecture: &str,
+    ) -> SourceResult<()> {
+        self.edit_repositories(repos, |entry, location| match &entry.archs {
+            Some(archs) if archs.len() == 1 && archs[0] == architecture => Some(Edit::Remove),
+            Some(archs) if archs.iter().any(|a| a == architecture) => {
+                Some(Edit::Change(Change::RemoveArchitecture {
+                    location,
+                    architecture: architecture.to_owned(),
+                }))
+            }
+            _ => None,
+        })
+    }
+
+    /// Applies an edit to every entry of the repositories, at once.
+    ///
+    /// Since the entries of a deb822 stanza share their options, the entries of each of its
+    /// suites and types belong to the same repository and receive the same edits. A stanza with
+    /// several URIs holds a repository per URI, though: editing one of them moves its entries to
+    /// a stanza of their own.
+    fn edit_repositories(
+        &mut self,
+        repos: &[LogicalRepository],
+        edit: impl Fn(&SourceEntry, EntryLocation) -> Option<Edit>,
+    ) -> SourceResult<()> {
+        let mut changes = ChangeSet::new();
+        let mut removals = Vec::new();
+        for location in repos.iter().flat_map(|repo| &repo.entries) {
+            let Some(entry) = self.entry_at(location) else {
+                continue;
+            };
+
+            match edit(entry, location.clone()) {
+                Some(Edit::Change(change)) => {
+                    changes.push(change);
+                }
+                Some(Edit::Remove) => removals.push(location.clone()),
+                None => (),
+            }
+        }
+
+        // Entries are removed from the end, so that the other locations stay valid.
+        removals.sort_by(|a, b| b.path.cmp(&a.path).then(b.index.cmp(&a.index)));
+        removals.dedup();
+        for location in removals {
+            changes.push(Change::remove(self, location)?);
+        }
+
+        changes.apply(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests;
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[
+            (
+                "sources.list",
+                "deb http://archive.ubuntu.com/ubuntu noble main restricted\n\
+                 deb http://archive.ubuntu.com/ubuntu noble-updates main restricted\n\
+                 deb-src http://archive.ubuntu.com/ubuntu noble-updates main restricted\n\
+                 deb [arch=amd64] https://example.org/repo stable main\n",
+            ),
+            (
+                "ubuntu.sources",
+                "Types: deb\n\
+                 URIs: http://archive.ubuntu.com/ubuntu/\n\
+                 Suites: noble-backports\n\
+                 Components: main universe\n",
+            ),
+        ])
+    }
+
+    #[test]
+    fn grouping() {
+        let repos = lists().repositories();
+        assert_eq!(repos.len(), 2);
+
+        assert_eq!(repos[0].url, "http://archive.ubuntu.com/ubuntu");
+        assert_eq!(
+            repos[0].suites,
+            ["noble", "noble-updates", "noble-backports"]
+        );
+        assert_eq!(repos[0].components, ["main", "restricted", "universe"]);
+        assert!(repos[0].binary && repos[0].source);
+        assert_eq!(repos[0].entries.len(), 4);
+
+        assert_eq!(repos[1].url, "https://example.org/repo");
+        assert!(repos[1].binary && !repos[1].source);
+    }
+
+    #[test]
+    fn source_code() {
+        let mut lists = lists();
+        let repo = lists.repositories().remove(0);
+        lists.set_source_code(&repo, true).unwrap();
+
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb http://archive.ubuntu.com/ubuntu noble main restricted\n\
+             deb-src http://archive.ubuntu.com/ubuntu noble main restricted\n\
+             deb http://archive.ubuntu.com/ubuntu noble-updates main restricted\n\
+             deb-src http://archive.ubuntu.com/ubuntu noble-updates main restricted\n\
+             deb [arch=amd64] https://example.org/repo stable main\n"
+        );
+        assert_eq!(
+            lists.files[1].to_string(),
+            "Types: deb deb-src\n\
+             URIs: http://archive.ubuntu.com/ubuntu/\n\
+             Suites: noble-backports \n\
+             Components: main universe\n"
+        );
+
+        // Disabling keeps the entries, and enabling again does not duplicate them.
+        let repo = lists.repositories().remove(0);
+        lists.set_source_code(&repo, false).unwrap();
+        let repo = lists.repositories().remove(0);
+        assert!(repo.binary && !repo.source);
+        lists.set_source_code(&repo, true).unwrap();
+        assert_eq!(lists.repositories()[0].entries.len(), 6);
+        assert!(lists.repositories()[0].source);
+
+        let repo = lists.repositories().remove(0);
+        lists.set_repository_enabled(&repo, false).unwrap();
+        assert!(!lists.repositories()[0].is_enabled());
+        lists.set_repository_enabled(&repo, true).unwrap();
+        let repo = lists.repositories().remove(0);
+        assert!(repo.binary && !repo.source);
+    }
+
+    #[test]
+    fn components_and_architectures() {
+        let mut lists = tests::lists(&[
+            (
+                "sources.list",
+                "deb http://deb.debian.org/debian bookworm main\n\
+                 deb [arch=amd64] http://deb.debian.org/debian bookworm-backports main\n\
+                 deb https://example.org/flat ./\n",
+            ),
+            (
+                "debian.sources",
+                "Types: deb\n\
+                 URIs: http://deb.debian.org/debian/\n\
+                 Suites: trixie trixie-updates\n\
+                 Components: main contrib\n",
+            ),
+        ]);
+
+        let repos = lists.repositories();
+        assert_eq!(repos.len(), 3);
+        lists.add_component(&repos, "non-free-firmware").unwrap();
+        lists.add_architecture(&repos, "i386").unwrap();
+
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb http://deb.debian.org/debian bookworm main non-free-firmware\n\
+             deb [arch=amd64,i386] http://deb.debian.org/debian bookworm-backports main non-free-firmware\n\
+             deb https://example.org/flat ./\n"
+        );
+        assert_eq!(
+            lists.files[1].to_string(),
+            "Types: deb\n\
+             URIs: http://deb.debian.org/debian/\n\
+             Suites: trixie trixie-updates \n\
+             Components: main contrib non-free-firmware\n"
+        );
+
+        let repos = lists.repositories();
+        lists.remove_component(&repos, "main").unwrap();
+        lists.remove_architecture(&repos, "i386").unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb http://deb.debian.org/debian bookworm non-free-firmware\n\
+             deb [arch=amd64] http://deb.debian.org/debian bookworm-backports non-free-firmware\n\
+             deb https://example.org/flat ./\n"
+        );
+        assert_eq!(
+            lists.files[1].to_string(),
+            "Types: deb\n\
+             URIs: http://deb.debian.org/debian/\n\
+             Suites: trixie trixie-updates \n\
+             Components: contrib non-free-firmware\n"
+        );
+
+        // Removing the only component or architecture of an entry removes it.
+        let repos = lists.repositories();
+        lists.remove_architecture(&repos, "amd64").unwrap();
+        let repos = lists.repositories();
+        lists.remove_component(&repos, "non-free-firmware").unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            "deb https://example.org/flat ./\n"
+        );
+    }
+
+    #[test]
+    fn several_uris() {
+        let mut lists = tests::lists(&[(
+            "debian.sources",
+            "Types: deb\n\
+             URIs: http://deb.debian.org/debian http://mirror.example.org/debian\n\
+             Suites: bookworm\n\
+             Components: main\n",
+        )]);
+
+        // Each URI is a repository, whose entries leave the stanza once edited.
+        let repos = lists.repositories();
+        assert_eq!(repos.len(), 2);
+        lists.add_component(&repos[..1], "contrib").unwrap();
+        assert_eq!(
+            lists.files[0].to_string(),
+            "Types: deb\n\
+             URIs: http://deb.debian.org/debian\n\
+             Suites: bookworm \n\
+             Components: main contrib\n\
+             \n\
+             Types: deb\n\
+             URIs: http://mirror.example.org/debian\n\
+             Suites: bookworm \n\
+             Components: main\n"
+        );
+    }
+}
@@ -0,0 +1,523 @@
+//! The serde schema of entries and files, enabled by the `serde` feature.
+//!
+//! The schema does not follow the layout of the Rust types, so that it stays stable as they
+//! change. Field names are in kebab-case. An entry is written as:
+//!
+//! ```yaml
+//! enabled: true            # optional on import, true by default
+//! type: deb                # or deb-src, or unsupported for a deb822 stanza without a
+//!                          # supported type, whose types are in the `Types` option
+//! uri: https://deb.debian.org/debian
+//! suite: bookworm
+//! components: [main, contrib]
+//! architectures: [amd64]   # optional
+//! signed-by:               # optional
+//!   kind: keys             # a list of key files and fingerprints
+//!   keys: [/usr/share/keyrings/debian-archive-keyring.gpg]
+//! trusted: false           # optional, false by default
+//! options:                 # optional, in their one-line names
+//!   lang: [en]
+//!   check-valid-until: false
+//!   valid-until-max: 604800
+//! format: deb822           # optional on import, the style the entry was read in
+//! ```
+//!
+//! An embedded key is written as `{ kind: key-block, armor: "..." }`.
+//!
+//! Options are typed: `yes` and `no` are booleans, the values of `valid-until-min`,
+//! `valid-until-max` and `date-max-future` are integers, `lang` and `target` are always lists,
+//! and the other options are strings, or lists if they have several values.
+//!
+//! A file records its path and format, along with the comments of one-line files:
+//!
+//! ```yaml
+//! path: /etc/apt/sources.list
+//! format: one-line
+//! lines:
+//!   - comment: "# Debian"
+//!   - entry: { type: deb, uri: ..., suite: bookworm, components: [main] }
+//!   - empty
+//! ---
+//! path: /etc/apt/sources.list.d/debian.sources
+//! format: deb822
+//! entries:
+//!   - { type: deb, uri: ..., suite: bookworm, components: [main] }
+//! ```
+//!
+//! Imported files can be added to a `SourcesLists` with `SourcesLists::add_file`.
+
+use crate::changes::restyled;
+use crate::deb822::signature::{KeyReference, Signature};
+use crate::source_deb822::SourceListDeb822;
+use crate::{
+    SourceEntry, SourceError, SourceFormat, SourceLine, SourceListLineStyle, SourceListType,
+};
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Options whose values are integers.
+const INTEGER_OPTIONS: &[&str] = &["valid-until-min", "valid-until-max", "date-max-future"];
+
+/// Options whose values are lists, even if they have a single value.
+const LIST_OPTIONS: &[&str] = &["lang", "target"];
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum EntryKind {
+    Deb,
+    DebSrc,
+    Unsupported,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct EntrySchema {
+    #[serde(default = "enabled")]
+    enabled: bool,
+    #[serde(rename = "type")]
+    kind: EntryKind,
+    uri: String,
+    suite: String,
+    #[serde(default)]
+    components: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    architectures: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signed_by: Option<Signature>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    trusted: bool,
+    #[serde(default, skip_serializing_if = "Options::is_empty")]
+    options: Options,
+    #[serde(default = "one_line")]
+    format: SourceFormat,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn one_line() -> SourceFormat {
+    SourceFormat::OneLine
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl From<SourceEntry> for EntrySchema {
+    fn from(mut entry: SourceEntry) -> Self {
+        let format = if entry.is_deb822 {
+            SourceFormat::Deb822
+        } else {
+            SourceFormat::OneLine
+        };
+
+        entry.set_style(false);
+        EntrySchema {
+            enabled: entry.enabled,
+            kind: if entry.unsupported_type {
+                EntryKind::Unsupported
+            } else if entry.source {
+                EntryKind::DebSrc
+            } else {
+                EntryKind::Deb
+            },
+            uri: entry.url,
+            suite: entry.suite,
+            components: entry.components,
+            architectures: entry.archs,
+            signed_by: entry.signed_by,
+            trusted: entry.trusted,
+            options: Options(
+                entry
+                    .options
+                    .into_iter()
+                    .map(|(name, values)| {
+                        let value = OptionValue::typed(&name, values);
+                        (name, value)
+                    })
+                    .collect(),
+            ),
+            format,
+        }
+    }
+}
+
+impl TryFrom<EntrySchema> for SourceEntry {
+    type Error = SourceError;
+
+    fn try_from(schema: EntrySchema) -> Result<Self, Self::Error> {
+        if schema.uri.is_empty() {
+            return Err(SourceError::MissingField { field: "uri" });
+        }
+
+        if schema.suite.is_empty() {
+            return Err(SourceError::MissingField { field: "suite" });
+        }
+
+        let unsupported_type = matches!(schema.kind, EntryKind::Unsupported);
+        if unsupported_type && schema.format != SourceFormat::Deb822 {
+            return Err(SourceError::InvalidValue {
+                field: "type",
+                value: "an unsupported type can only be kept in the deb822 format".into(),
+            });
+        }
+
+        let mut entry = SourceEntry {
+            enabled: schema.enabled,
+            source: matches!(schema.kind, EntryKind::DebSrc),
+            options: schema
+                .options
+                .0
+                .into_iter()
+                .map(|(name, value)| (name, value.into_values()))
+                .collect(),
+            url: schema.uri,
+            suite: schema.suite,
+            components: schema.components,
+            archs: schema.architectures,
+            signed_by: schema.signed_by,
+            trusted: schema.trusted,
+            unsupported_type,
+            is_deb822: false,
+        };
+
+        entry.set_style(schema.format == SourceFormat::Deb822);
+        Ok(entry)
+    }
+}
+
+/// The value of an option, typed from its text.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum OptionValue {
+    Bool(bool),
+    Integer(u64),
+    Text(String),
+    List(Vec<String>),
+}
+
+impl OptionValue {
+    fn typed(name: &str, mut values: Vec<String>) -> Self {
+        if values.len() != 1 || LIST_OPTIONS.contains(&name) {
+            return OptionValue::List(values);
+        }
+
+        let value = values.remove(0);
+        match value.as_str() {
+            "yes" => OptionValue::Bool(true),
+            "no" => OptionValue::Bool(false),
+            _ if INTEGER_OPTIONS.contains(&name) => match value.parse() {
+                Ok(number) => OptionValue::Integer(number),
+                Err(_) => OptionValue::Text(value),
+            },
+            _ => OptionValue::Text(value),
+        }
+    }
+
+    fn into_values(self) -> Vec<String> {
+        match self {
+            OptionValue::Bool(true) => vec!["yes".into()],
+            OptionValue::Bool(false) => vec!["no".into()],
+            OptionValue::Integer(number) => vec![number.to_string()],
+            OptionValue::Text(value) => vec![value],
+            OptionValue::List(values) => values,
+        }
+    }
+}
+
+/// Options as a map which keeps their order.
+#[derive(Clone, Debug, Default)]
+struct Options(Vec<(String, OptionValue)>);
+
+impl Options {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Serialize for Options {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, value)?;
+        }
+
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Options {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OptionsVisitor;
+
+        impl<'de> Visitor<'de> for OptionsVisitor {
+            type Value = Options;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt.write_str("a map of options")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Options, A::Error> {
+                let mut options = Vec::new();
+                while let Some(option) = map.next_entry()? {
+                    options.push(option);
+                }
+
+                Ok(Options(options))
+            }
+        }
+
+        deserializer.deserialize_map(OptionsVisitor)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum SignatureSchema {
+    KeyBlock { armor: String },
+    Keys { keys: Vec<String> },
+}
+
+impl From<Signature> for SignatureSchema {
+    fn from(signature: Signature) -> Self {
+        match signature {
+            Signature::KeyBlock(armor) => SignatureSchema::KeyBlock { armor },
+            keys => SignatureSchema::Keys {
+                keys: keys
+                    .keys()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(KeyReference::to_string)
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl TryFrom<SignatureSchema> for Signature {
+    type Error = SourceError;
+
+    fn try_from(schema: SignatureSchema) -> Result<Self, Self::Error> {
+        match schema {
+            SignatureSchema::KeyBlock { armor } => Ok(Signature::KeyBlock(armor)),
+            SignatureSchema::Keys { keys } if keys.is_empty() => {
+                Err(SourceError::MissingField { field: "keys" })
+            }
+            SignatureSchema::Keys { keys } => Ok(Signature::from_keys(
+                keys.iter().map(|key| KeyReference::parse(key)),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LineSchema {
+    Comment(String),
+    Empty,
+    Entry(SourceEntry),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "format", rename_all = "kebab-case")]
+pub(crate) enum ListSchema {
+    OneLine { lines: Vec<LineSchema> },
+    Deb822 { entries: Vec<SourceEntry> },
+}
+
+impl From<SourceListType> for ListSchema {
+    fn from(list: SourceListType) -> Self {
+        match list {
+            SourceListType::SourceLine(lines) => ListSchema::OneLine {
+                lines: lines
+                    .0
+                    .into_iter()
+                    .map(|line| match line {
+                        SourceLine::Comment(comment) => LineSchema::Comment(comment),
+                        SourceLine::Empty => LineSchema::Empty,
+                        SourceLine::Entry(entry) => LineSchema::Entry(entry),
+                    })
+                    .collect(),
+            },
+            SourceListType::Deb822(list) => ListSchema::Deb822 {
+                entries: list.entries,
+            },
+        }
+    }
+}
+
+impl TryFrom<ListSchema> for SourceListType {
+    type Error = SourceError;
+
+    fn try_from(schema: ListSchema) -> Result<Self, Self::Error> {
+        let list = match schema {
+            ListSchema::OneLine { lines } => {
+                let lines = lines
+                    .into_iter()
+                    .map(|line| match line {
+                        LineSchema::Comment(comment) if comment.starts_with('#') => {
+                            Ok(SourceLine::Comment(comment))
+                        }
+                        LineSchema::Comment(comment) => Err(SourceError::InvalidValue {
+                            field: "comment",
+                            value: comment,
+                        }),
+                        LineSchema::Empty => Ok(SourceLine::Empty),
+                        LineSchema::Entry(entry) => {
+                            Ok(SourceLine::Entry(restyled(&entry, SourceFormat::OneLine)?))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                SourceListType::SourceLine(SourceListLineStyle(lines))
+            }
+            ListSchema::Deb822 { entries } => {
+                let entries = entries
+                    .iter()
+                    .map(|entry| restyled(entry, SourceFormat::Deb822))
+                    .collect::<Result<_, _>>()?;
+
+                SourceListType::Deb822(SourceListDeb822 { entries })
+            }
+        };
+
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SourceEntry, SourceListType, SourcesList};
+
+    #[test]
+    fn entry() {
+        let entry: SourceEntry =
+            "deb [arch=amd64,arm64 lang=en check-valid-until=no valid-until-max=604800 \
+             signed-by=/usr/share/keyrings/debian.gpg] http://deb.debian.org/debian bookworm main"
+                .parse()
+                .unwrap();
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "enabled": true,
+                "type": "deb",
+                "uri": "http://deb.debian.org/debian",
+                "suite": "bookworm",
+                "components": ["main"],
+                "architectures": ["amd64", "arm64"],
+                "signed-by": {
+                    "kind": "keys",
+                    "keys": ["/usr/share/keyrings/debian.gpg"],
+                },
+                "options": {
+                    "lang": ["en"],
+                    "check-valid-until": false,
+                    "valid-until-max": 604800,
+                },
+                "format": "one-line",
+            })
+        );
+
+        // The order of the options is kept, which a `serde_json::Value` does not do.
+        let text = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<SourceEntry>(&text).unwrap(), entry);
+
+        let imported: SourceEntry = serde_json::from_str(
+            r#"{ "type": "deb-src", "uri": "https://example.org/repo", "suite": "stable",
+                 "signed-by": { "kind": "keys", "keys": ["8356B50980AE5E15DD75D36B7EFE4E79A5238717"] } }"#,
+        )
+        .unwrap();
+        assert!(imported.enabled && imported.source);
+        assert_eq!(
+            imported.to_string(),
+            "deb-src [signed-by=8356B50980AE5E15DD75D36B7EFE4E79A5238717] https://example.org/repo stable"
+        );
+
+        assert!(serde_json::from_str::<SourceEntry>(r#"{ "type": "rpm" }"#).is_err());
+        assert!(serde_json::from_str::<SourceEntry>(
+            r#"{ "type": "deb", "uri": "https://example.org/repo", "suite": "" }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn lists() {
+        let one_line = SourcesList {
+            path: "debian.list".into(),
+            entries: SourceListType::SourceLine(
+                "# Debian\n\ndeb http://deb.debian.org/debian bookworm main\n"
+                    .parse()
+                    .unwrap(),
+            ),
+        };
+        let deb822 = SourcesList {
+            path: "debian.sources".into(),
+            entries: SourceListType::Deb822(
+                "Types: deb deb-src\n\
+                 URIs: http://deb.debian.org/debian\n\
+                 Suites: bookworm\n\
+                 Components: main\n\
+                 Languages: en\n\
+                 Enabled: no\n"
+                    .parse()
+                    .unwrap(),
+            ),
+        };
+
+        for list in [one_line, deb822] {
+            let json = serde_json::to_string(&list).unwrap();
+            let imported: SourcesList = serde_json::from_str(&json).unwrap();
+            assert_eq!(imported.path, list.path);
+            assert_eq!(imported.entries, list.entries);
+            assert_eq!(imported.to_string(), list.to_string());
+        }
+
+        let json = serde_json::json!({
+            "path": "debian.list",
+            "format": "one-line",
+            "lines": [{ "comment": "# Debian" }, "empty"],
+        });
+        assert_eq!(
+            serde_json::from_value::<SourcesList>(json)
+                .unwrap()
+                .to_string(),
+            "# Debian\n\n"
+        );
+
+        // Entries which cannot be written in the one-line format are refused, rather than
+        // losing their embedded key or their types.
+        let entry = |entry: serde_json::Value| {
+            serde_json::json!({
+                "path": "example.list",
+                "format": "one-line",
+                "lines": [{ "entry": entry }],
+            })
+        };
+        let embedded = entry(serde_json::json!({
+            "type": "deb",
+            "uri": "https://example.org/repo",
+            "suite": "stable",
+            "components": ["main"],
+            "signed-by": {
+                "kind": "key-block",
+                "armor": "-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nAAAA\n-----END PGP PUBLIC KEY BLOCK-----",
+            },
+        }));
+        assert!(serde_json::from_value::<SourcesList>(embedded).is_err());
+
+        let unsupported = entry(serde_json::json!({
+            "type": "unsupported",
+            "uri": "http://example.com/fedora",
+            "suite": "40",
+            "components": ["main"],
+            "options": { "Types": "rpm" },
+            "format": "deb822",
+        }));
+        assert!(serde_json::from_value::<SourcesList>(unsupported).is_err());
+    }
+}
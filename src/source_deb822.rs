@@ -1,9 +1,9 @@
-use std::{fmt, ops::Deref, str::FromStr};
+use std::{fmt, str::FromStr};
 
-use deb822_lossless::{Paragraph, ToDeb822Paragraph};
+use deb822_lossless::{Deb822, FromDeb822Paragraph, ParseError};
 
 use crate::{
-    deb822::{Repositories, RepositoryType},
+    deb822::{Repository, RepositoryType},
     SourceEntry, SourceError,
 };
 
@@ -14,29 +14,80 @@ pub struct SourceListDeb822 {
 
 impl fmt::Display for SourceListDeb822 {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let mut uris = vec![];
-        for i in &self.entries {
-            if uris.contains(&(&i.url, i.source, &i.options)) {
+        let mut written = vec![false; self.entries.len()];
+        for (pos, i) in self.entries.iter().enumerate() {
+            if written[pos] {
                 continue;
             }
 
-            writeln!(fmt, "Types: {}", if i.source { "deb-src" } else { "deb" })?;
-            writeln!(fmt, "URIs: {}", i.url)?;
+            if written.iter().any(|&w| w) {
+                writeln!(fmt)?;
+            }
 
-            uris.push((&i.url, i.source, &i.options));
+            // Binary and source entries share a stanza when they have the same suites.
+            let suites_of = |source: bool| {
+                let mut suites: Vec<&str> = Vec::new();
+                for (x, _) in self
+                    .entries
+                    .iter()
+                    .zip(&written)
+                    .filter(|(x, &w)| !w && x.source == source && same_stanza_but_type(x, i))
+                {
+                    if !suites.contains(&x.suite.as_str()) {
+                        suites.push(&x.suite);
+                    }
+                }
+                suites
+            };
+            let suites = suites_of(i.source);
+            let others = suites_of(!i.source);
+            let both_types = !others.is_empty()
+                && others.len() == suites.len()
+                && others.iter().all(|s| suites.contains(s));
+
+            for (x, w) in self.entries.iter().zip(&mut written) {
+                if same_stanza_but_type(x, i) && (both_types || x.source == i.source) {
+                    *w = true;
+                }
+            }
 
-            let suites = self
-                .entries
+            if !i.enabled {
+                writeln!(fmt, "Enabled: no")?;
+            }
+
+            let own_types: &[&str] = match (i.unsupported_type, i.source, both_types) {
+                (true, ..) => &[],
+                (false, true, true) => &["deb-src", "deb"],
+                (false, false, true) => &["deb", "deb-src"],
+                (false, true, false) => &["deb-src"],
+                (false, false, false) => &["deb"],
+            };
+            let types = own_types
                 .iter()
-                .filter(|x| x.url == i.url && i.source == x.source && i.options == x.options)
-                .map(|x| x.suite.clone());
+                .copied()
+                .chain(
+                    i.option("Types")
+                        .unwrap_or_default()
+                        .iter()
+                        .map(String::as_str),
+                )
+                .collect::<Vec<_>>();
+            writeln!(fmt, "Types: {}", types.join(" "))?;
+            writeln!(fmt, "URIs: {}", i.url)?;
+
             write!(fmt, "Suites: ")?;
             for i in suites {
                 write!(fmt, "{} ", i)?;
             }
             writeln!(fmt)?;
 
-            writeln!(fmt, "Components: {}", i.components.join(" "))?;
+            if !i.components.is_empty() {
+                writeln!(fmt, "Components: {}", i.components.join(" "))?;
+            }
+
+            if let Some(archs) = &i.archs {
+                writeln!(fmt, "Architectures: {}", archs.join(" "))?;
+            }
 
             if i.trusted {
                 writeln!(fmt, "Trusted: yes")?;
@@ -46,7 +97,11 @@ impl fmt::Display for SourceListDeb822 {
                 writeln!(fmt, "Signed-By:{}", signed_by)?;
             }
 
-            for j in &i.options {
+            for j in i
+                .options
+                .iter()
+                .filter(|j| !j.0.eq_ignore_ascii_case("Types"))
+            {
                 writeln!(fmt, "{}: {}", j.0, j.1.join(" "))?;
             }
         }
@@ -55,54 +110,107 @@ impl fmt::Display for SourceListDeb822 {
     }
 }
 
+/// Whether two entries only differ by their suite, and thus can share a stanza.
+pub(crate) fn same_stanza(a: &SourceEntry, b: &SourceEntry) -> bool {
+    a.source == b.source && same_stanza_but_type(a, b)
+}
+
+/// Whether two entries only differ by their type and suite. Such entries share a stanza if
+/// there are entries of both types for each of its suites.
+pub(crate) fn same_stanza_but_type(a: &SourceEntry, b: &SourceEntry) -> bool {
+    a.url == b.url
+        && a.enabled == b.enabled
+        && a.components == b.components
+        && a.archs == b.archs
+        && a.signed_by == b.signed_by
+        && a.trusted == b.trusted
+        && a.unsupported_type == b.unsupported_type
+        && a.options == b.options
+}
+
+/// Fields which are stored in dedicated `SourceEntry` fields rather than in its options.
+pub(crate) const CORE_FIELDS: &[&str] = &[
+    "Enabled",
+    "Types",
+    "URIs",
+    "Suites",
+    "Components",
+    "Architectures",
+    "Signed-By",
+    "Trusted",
+];
+
 impl FromStr for SourceListDeb822 {
     type Err = SourceError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let sources_list: Repositories =
-            s.parse().map_err(|e| SourceError::SyntaxError { why: e })?;
+        let deb822: Deb822 = s
+            .parse()
+            .map_err(|e: ParseError| SourceError::SyntaxError { why: e.to_string() })?;
 
         let mut entries = vec![];
 
-        for source in sources_list.deref() {
-            let p: Paragraph = source.to_paragraph();
-            for suite in &source.suites {
+        for p in deb822.paragraphs() {
+            let source =
+                Repository::from_paragraph(&p).map_err(|e| SourceError::SyntaxError { why: e })?;
+
+            // Options are taken from the paragraph as written, so that fields which are not
+            // known to `Repository` are preserved. Core fields are read from their first
+            // occurrence, and any repetition is kept as an option for validation.
+            let mut seen = vec![];
+            let mut types = vec![];
+            let mut options = vec![];
+            for (key, value) in p.items() {
+                let values = value
+                    .split_ascii_whitespace()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>();
+
+                let core = CORE_FIELDS.iter().find(|f| f.eq_ignore_ascii_case(&key));
+                match core {
+                    Some(&"Types") if !seen.contains(&"Types") => {
+                        seen.push("Types");
+                        let (known, unknown): (Vec<_>, Vec<_>) = values
+                            .into_iter()
+                            .partition(|t| t.parse::<RepositoryType>().is_ok());
+                        types.extend(known);
+                        if !unknown.is_empty() {
+                            options.push((key, unknown));
+                        }
+                    }
+                    Some(field) if !seen.contains(field) => seen.push(field),
+                    _ => options.push((key, values)),
+                }
+            }
+
+            // A stanza without any supported type is kept, with its types in the options, so
+            // that it is written back as it was and reported by validation.
+            let unsupported_type = types.is_empty();
+            if unsupported_type {
+                types.push("deb".to_owned());
+            }
+
+            let suites = if source.suites.is_empty() {
+                vec![String::new()]
+            } else {
+                source.suites.clone()
+            };
+
+            for suite in &suites {
                 for url in &source.uris {
-                    for source_type in &source.types {
+                    for source_type in &types {
                         let entry = SourceEntry {
                             enabled: source.enabled.unwrap_or(true),
-                            source: *source_type == RepositoryType::Source,
+                            source: source_type == "deb-src",
                             url: url.to_string(),
                             suite: suite.to_string(),
                             components: source.components.clone().unwrap_or(vec![]),
                             is_deb822: true,
-                            options: p
-                                .items()
-                                .filter(|x| {
-                                    ![
-                                        "Enabled",
-                                        "Types",
-                                        "URIs",
-                                        "Suites",
-                                        "Components",
-                                        "Architectures",
-                                        "Signed-By",
-                                        "Trusted",
-                                    ]
-                                    .contains(&x.0.as_str())
-                                })
-                                .map(|x| {
-                                    (
-                                        x.0,
-                                        x.1.split_ascii_whitespace()
-                                            .map(|x| x.to_string())
-                                            .collect::<Vec<_>>(),
-                                    )
-                                })
-                                .collect::<Vec<_>>(),
+                            options: options.clone(),
                             archs: source.architectures.clone(),
                             trusted: source.trusted.unwrap_or(false),
                             signed_by: source.signature.clone(),
+                            unsupported_type,
                         };
 
                         entries.push(entry);
@@ -144,6 +252,7 @@ Signed-By: /etc/apt/trusted.gpg.d/crosspaste.asc
                 is_deb822: true,
                 archs: None,
                 trusted: false,
+                unsupported_type: false,
             }]
         }
     );
@@ -195,6 +304,7 @@ Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg
                     is_deb822: true,
                     archs: None,
                     trusted: false,
+                    unsupported_type: false,
                 },
                 SourceEntry {
                     enabled: true,
@@ -214,6 +324,7 @@ Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg
                     ])),
                     archs: None,
                     trusted: false,
+                    unsupported_type: false,
                 },
                 SourceEntry {
                     enabled: true,
@@ -233,6 +344,7 @@ Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg
                         "/usr/share/keyrings/ubuntu-archive-keyring.gpg".into()
                     ])),
                     trusted: false,
+                    unsupported_type: false,
                 },
                 SourceEntry {
                     enabled: true,
@@ -252,6 +364,7 @@ Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg
                         "/usr/share/keyrings/ubuntu-archive-keyring.gpg".into()
                     ])),
                     trusted: false,
+                    unsupported_type: false,
                 },
             ]
         }
@@ -281,6 +394,7 @@ fn test_serialize_deb822() {
                     "/usr/share/keyrings/ubuntu-archive-keyring.gpg".into(),
                 ])),
                 trusted: false,
+                unsupported_type: false,
             },
             SourceEntry {
                 enabled: true,
@@ -300,6 +414,7 @@ fn test_serialize_deb822() {
                     "/usr/share/keyrings/ubuntu-archive-keyring.gpg".into(),
                 ])),
                 trusted: false,
+                unsupported_type: false,
             },
             SourceEntry {
                 enabled: true,
@@ -319,6 +434,7 @@ fn test_serialize_deb822() {
                     "/usr/share/keyrings/ubuntu-archive-keyring.gpg".into(),
                 ])),
                 trusted: false,
+                unsupported_type: false,
             },
         ],
     };
@@ -333,3 +449,60 @@ Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg
 "#
     );
 }
+
+#[test]
+fn test_unsupported_types() {
+    let text = "Types: rpm
+URIs: http://example.com/fedora
+Suites: 40 
+Components: main
+
+Types: deb
+URIs: http://deb.debian.org/debian
+Suites: bookworm 
+Components: main
+";
+
+    let sources = SourceListDeb822::from_str(text).unwrap();
+    assert_eq!(sources.entries.len(), 2);
+    assert!(sources.entries[0].unsupported_type);
+    assert_eq!(
+        sources.entries[0].option("Types"),
+        Some(&["rpm".to_string()][..])
+    );
+    assert!(!sources.entries[1].unsupported_type);
+    assert_eq!(sources.to_string(), text);
+    assert_eq!(
+        sources.entries[0].to_one_line(),
+        "rpm http://example.com/fedora 40 main"
+    );
+}
+
+#[test]
+fn test_binary_and_source_types() {
+    let text = "Types: deb deb-src
+URIs: http://deb.debian.org/debian
+Suites: bookworm bookworm-updates 
+Components: main
+";
+
+    let mut sources = SourceListDeb822::from_str(text).unwrap();
+    assert_eq!(sources.entries.len(), 4);
+    assert_eq!(sources.to_string(), text);
+
+    // Without source code for one of the suites, each type has a stanza of its own.
+    sources.entries.remove(3);
+    assert_eq!(
+        sources.to_string(),
+        "Types: deb
+URIs: http://deb.debian.org/debian
+Suites: bookworm bookworm-updates 
+Components: main
+
+Types: deb-src
+URIs: http://deb.debian.org/debian
+Suites: bookworm 
+Components: main
+"
+    );
+}
@@ -1,4 +1,5 @@
-use deb822::signature::Signature;
+use crate::duplicates::normalized_url;
+use deb822::signature::{KeyPinning, KeyReference, Signature};
 
 use super::*;
 use std::fmt;
@@ -6,6 +7,14 @@ use std::str::FromStr;
 
 /// An apt source entry that is active on the system.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::schema::EntrySchema",
+        try_from = "crate::schema::EntrySchema"
+    )
+)]
 pub struct SourceEntry {
     /// Whether the entry is enabled or not.
     pub enabled: bool,
@@ -26,6 +35,9 @@ pub struct SourceEntry {
     /// Trusted
     pub trusted: bool,
     pub is_deb822: bool,
+    /// Whether the entry comes from a deb822 stanza without a supported type, such as
+    /// `Types: rpm`. Its types are kept in the `Types` option, and apt rejects it.
+    pub unsupported_type: bool,
 }
 
 impl fmt::Display for SourceEntry {
@@ -40,25 +52,47 @@ impl fmt::Display for SourceEntry {
                 fmt.write_str("# ")?;
             }
 
-            fmt.write_str(if self.source { "deb-src " } else { "deb " })?;
+            // The types of a stanza without a supported type are kept in its options.
+            let types = self
+                .options
+                .iter()
+                .find(|(k, _)| self.unsupported_type && k == "Types");
+            match types {
+                Some((_, types)) => write!(fmt, "{} ", types.join(","))?,
+                None => fmt.write_str(if self.source { "deb-src " } else { "deb " })?,
+            }
+
             let mut options_string = vec![];
-            if !self.options.is_empty() {
-                for (k, v) in &self.options {
-                    options_string.push(format!("{k}={}", v.join(",")));
+            if let Some(archs) = &self.archs {
+                options_string.push(format!("arch={}", archs.join(",")));
+            }
+
+            if let Some(signed_by) = self.signed_by.as_ref().and_then(|s| s.to_option_value()) {
+                options_string.push(format!("signed-by={signed_by}"));
+            }
+
+            if self.trusted {
+                options_string.push("trusted=yes".to_string());
+            }
+
+            for option @ (k, v) in &self.options {
+                if types == Some(option) {
+                    continue;
                 }
+
+                options_string.push(format!("{k}={}", v.join(",")));
             }
 
             if !options_string.is_empty() {
                 write!(fmt, "[{}] ", options_string.join(" "))?;
             }
 
-            write!(
-                fmt,
-                "{} {} {}",
-                self.url,
-                self.suite,
-                self.components.join(" ")
-            )
+            write!(fmt, "{} {}", self.url, self.suite)?;
+            for component in &self.components {
+                write!(fmt, " {}", component)?;
+            }
+
+            Ok(())
         }
     }
 }
@@ -133,7 +167,7 @@ impl FromStr for SourceEntry {
             url = field.into();
         }
 
-        if options.as_ref().map_or(false, String::is_empty) {
+        if options.as_ref().is_some_and(String::is_empty) {
             options = None;
         }
 
@@ -169,20 +203,18 @@ impl FromStr for SourceEntry {
         let mut signed_by = None;
 
         if let Some(pos) = options.iter().position(|x| x.0 == "signed-by") {
-            signed_by = Some(Signature::KeyPath(
-                options
-                    .remove(pos)
-                    .1
-                    .iter()
-                    .map(|x| x.into())
-                    .collect::<Vec<_>>(),
+            signed_by = Some(Signature::from_keys(
+                options.remove(pos).1.iter().map(|x| KeyReference::parse(x)),
             ))
         }
 
         let mut trusted = false;
 
-        if let Some(pos) = options.iter().position(|x| x.0 == "trusted") {
-            trusted = options.remove(pos).1.first().is_some_and(|x| x == "yes")
+        // Values other than yes and no are left in the options for `SourcesList::validate`.
+        if let Some(pos) = options.iter().position(|x| {
+            x.0 == "trusted" && matches!(x.1.as_slice(), [v] if v == "yes" || v == "no")
+        }) {
+            trusted = options.remove(pos).1[0] == "yes";
         }
 
         Ok(SourceEntry {
@@ -196,11 +228,188 @@ impl FromStr for SourceEntry {
             archs,
             signed_by,
             trusted,
+            unsupported_type: false,
         })
     }
 }
 
+/// Converts a URI to a file name the way apt does: the scheme and credentials are dropped,
+/// unsafe characters are percent-encoded, and slashes are replaced by underscores.
+fn uri_to_filename(uri: &str) -> String {
+    let rest = match uri.split_once(':') {
+        Some((scheme, rest)) if !scheme.contains('/') => rest,
+        _ => uri,
+    };
+
+    let rest = match rest.strip_prefix("//") {
+        Some(authority) => {
+            let end = authority.find('/').unwrap_or(authority.len());
+            match authority[..end].rfind('@') {
+                Some(at) => &authority[at + 1..],
+                None => authority,
+            }
+        }
+        None => rest,
+    };
+
+    let mut name = String::with_capacity(rest.len());
+    for byte in rest.bytes() {
+        match byte {
+            b'/' => name.push('_'),
+            0..=0x20 | 0x7F.. => name.push_str(&format!("%{:02x}", byte)),
+            b if b"\\|{}[]<>\"^~_=!@#$%&*".contains(&b) => name.push_str(&format!("%{:02x}", b)),
+            b => name.push(b as char),
+        }
+    }
+
+    name
+}
+
+/// Names of the options of `sources.list(5)`, in the one-line style and in the deb822 style.
+const OPTION_NAMES: &[(&str, &str)] = &[
+    ("arch", "Architectures"),
+    ("lang", "Languages"),
+    ("target", "Targets"),
+    ("pdiffs", "PDiffs"),
+    ("by-hash", "By-Hash"),
+    ("allow-insecure", "Allow-Insecure"),
+    ("allow-weak", "Allow-Weak"),
+    ("allow-downgrade-to-insecure", "Allow-Downgrade-To-Insecure"),
+    ("trusted", "Trusted"),
+    ("signed-by", "Signed-By"),
+    ("check-valid-until", "Check-Valid-Until"),
+    ("valid-until-min", "Valid-Until-Min"),
+    ("valid-until-max", "Valid-Until-Max"),
+    ("check-date", "Check-Date"),
+    ("date-max-future", "Date-Max-Future"),
+    ("inrelease-path", "InRelease-Path"),
+    ("snapshot", "Snapshot"),
+];
+
+/// Whether two option names refer to the same option, whichever style they are written in.
+pub(crate) fn same_option(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+        || OPTION_NAMES.iter().any(|(line, deb822)| {
+            (a.eq_ignore_ascii_case(line) || a.eq_ignore_ascii_case(deb822))
+                && (b.eq_ignore_ascii_case(line) || b.eq_ignore_ascii_case(deb822))
+        })
+}
+
 impl SourceEntry {
+    /// Fetches the values of an option, given either its one-line or its deb822 name.
+    pub fn option(&self, name: &str) -> Option<&[String]> {
+        self.options
+            .iter()
+            .find(|(k, _)| same_option(k, name))
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Sets an option, given either its one-line or its deb822 name.
+    ///
+    /// The option is stored under the name matching the style of this entry, replacing any
+    /// previous value.
+    pub fn set_option(&mut self, name: &str, values: Vec<String>) {
+        let name = OPTION_NAMES
+            .iter()
+            .find(|(line, deb822)| {
+                name.eq_ignore_ascii_case(line) || name.eq_ignore_ascii_case(deb822)
+            })
+            .map_or(
+                name,
+                |(line, deb822)| if self.is_deb822 { deb822 } else { line },
+            );
+
+        match self.options.iter_mut().find(|(k, _)| same_option(k, name)) {
+            Some(option) => *option = (name.to_owned(), values),
+            None => self.options.push((name.to_owned(), values)),
+        }
+    }
+
+    /// Removes an option, given either its one-line or its deb822 name.
+    ///
+    /// Returns the values of the removed option.
+    pub fn remove_option(&mut self, name: &str) -> Option<Vec<String>> {
+        let pos = self
+            .options
+            .iter()
+            .position(|(k, _)| same_option(k, name))?;
+        Some(self.options.remove(pos).1)
+    }
+
+    /// Converts the entry to the deb822 or the one-line style, renaming its options.
+    pub(crate) fn set_style(&mut self, deb822: bool) {
+        if self.is_deb822 == deb822 {
+            return;
+        }
+
+        self.is_deb822 = deb822;
+        for (name, _) in &mut self.options {
+            if let Some((line, deb822_name)) = OPTION_NAMES.iter().find(|(line, deb822)| {
+                name.eq_ignore_ascii_case(line) || name.eq_ignore_ascii_case(deb822)
+            }) {
+                *name = if deb822 { deb822_name } else { line }.to_string();
+            }
+        }
+    }
+
+    /// Formats the entry as a line of `sources.list`, whichever style it was read in.
+    ///
+    /// An embedded key cannot be written in this style, and is left out. The entries of a
+    /// stanza without a supported type are written with its types, such as `rpm`, which apt
+    /// rejects.
+    pub fn to_one_line(&self) -> String {
+        let mut entry = self.clone();
+        entry.set_style(false);
+        entry.to_string()
+    }
+
+    /// Whether both entries fetch the same type of index, from the same URI and suite.
+    pub fn same_target(&self, other: &SourceEntry) -> bool {
+        self.source == other.source
+            && self.suite == other.suite
+            && normalized_url(&self.url) == normalized_url(&other.url)
+    }
+
+    /// How the keys of the entry are pinned, or `None` if it has no `signed-by` option.
+    pub fn key_pinning(&self) -> Option<KeyPinning> {
+        self.signed_by.as_ref().map(Signature::pinning)
+    }
+
+    /// The suite of the entry, split into its codename and pocket.
+    pub fn parsed_suite(&self) -> Suite {
+        Suite::from(self.suite.as_str())
+    }
+
+    /// Points the entry to another release, keeping its pocket.
+    ///
+    /// If `from` has a pocket, such as `bookworm-updates`, only entries with exactly that suite
+    /// are changed to `to`. Otherwise, every pocket of the `from` release is moved to the
+    /// same pocket of the `to` release. Flat repositories are never changed.
+    ///
+    /// Returns `true` if the suite was changed.
+    pub fn replace_release(&mut self, from: &str, to: &str) -> bool {
+        let suite = self.parsed_suite();
+        let from = Suite::from(from);
+        let new = if from.pocket().is_some() || from.is_flat() {
+            if suite != from {
+                return false;
+            }
+
+            to.to_owned()
+        } else if suite.codename() == from.codename() {
+            suite.with_codename(to).to_string()
+        } else {
+            return false;
+        };
+
+        if new == self.suite {
+            return false;
+        }
+
+        self.suite = new;
+        true
+    }
+
     pub fn url(&self) -> &str {
         let mut url: &str = &self.url;
         while url.ends_with('/') {
@@ -220,6 +429,19 @@ impl SourceEntry {
         url.replace('/', "_")
     }
 
+    /// The name under which apt stores a file of the release of this entry in its lists
+    /// directory, such as `deb.debian.org_debian_dists_bookworm_InRelease` for `InRelease`.
+    pub fn lists_filename(&self, file: &str) -> String {
+        let base = [self.url(), "/"].concat();
+        let release = match self.suite.as_str() {
+            "/" => base,
+            flat if flat.ends_with('/') => [&base, flat].concat(),
+            suite => [&base, "dists/", suite, "/"].concat(),
+        };
+
+        uri_to_filename(&[&release, file].concat())
+    }
+
     /// Returns the root URL for this entry's dist path.
     ///
     /// For an entry such as:
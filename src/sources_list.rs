@@ -1,4 +1,4 @@
-use self::source_deb822::SourceListDeb822;
+use self::source_deb822::{same_stanza, SourceListDeb822};
 
 use super::*;
 use std::collections::HashSet;
@@ -9,13 +9,91 @@ use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// The position of an entry within a `SourcesLists`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryLocation {
+    /// The file which contains the entry.
+    pub path: PathBuf,
+    /// The line of the entry in one-line style files, or the position of the entry among the
+    /// expanded entries of a deb822 file.
+    pub index: usize,
+}
+
+impl Display for EntryLocation {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}", self.path.display(), self.index)
+    }
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourcesList {
     pub path: PathBuf,
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub entries: SourceListType,
 }
 
+/// The syntax of a sources file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum SourceFormat {
+    /// The one-line style of `.list` files.
+    OneLine,
+    /// The deb822 style of `.sources` files.
+    Deb822,
+}
+
+impl SourceFormat {
+    /// The format that apt expects for a file, given its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension() {
+            Some(x) if x == "list" => Some(SourceFormat::OneLine),
+            Some(x) if x == "sources" => Some(SourceFormat::Deb822),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SourceFormat {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            SourceFormat::OneLine => "one-line",
+            SourceFormat::Deb822 => "deb822",
+        })
+    }
+}
+
+/// What `SourcesLists::insert_entry` did with an entry.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InsertOutcome {
+    /// The entry was added at the location.
+    Inserted(EntryLocation),
+    /// The entry was merged into the one at the location.
+    Updated(EntryLocation),
+}
+
+impl InsertOutcome {
+    pub fn location(&self) -> &EntryLocation {
+        match self {
+            InsertOutcome::Inserted(location) | InsertOutcome::Updated(location) => location,
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::schema::ListSchema",
+        try_from = "crate::schema::ListSchema"
+    )
+)]
 pub enum SourceListType {
     SourceLine(SourceListLineStyle),
     Deb822(SourceListDeb822),
@@ -46,28 +124,43 @@ impl FromStr for SourceListLineStyle {
 
 impl SourcesList {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, SourcesListError> {
+        let path = path.as_ref();
+        let format =
+            SourceFormat::from_path(path).ok_or_else(|| SourcesListError::UnknownFile {
+                path: path.to_path_buf(),
+            })?;
+
+        Self::new_as(path, format)
+    }
+
+    /// Parses the file at the path in the given format, whatever its extension.
+    pub fn new_as<P: AsRef<Path>>(path: P, format: SourceFormat) -> Result<Self, SourcesListError> {
         let path = path.as_ref();
         let data = fs::read_to_string(path).map_err(|why| SourcesListError::SourcesListOpen {
             path: path.to_path_buf(),
             why,
         })?;
 
-        let mut sources_file = match path.extension() {
-            Some(x) if x == "sources" => SourcesList {
+        Self::parse_as(path, &data, format)
+    }
+
+    /// Parses the contents of a file in the given format.
+    pub(crate) fn parse_as(
+        path: &Path,
+        data: &str,
+        format: SourceFormat,
+    ) -> Result<Self, SourcesListError> {
+        let mut sources_file = match format {
+            SourceFormat::Deb822 => SourcesList {
                 path: path.to_path_buf(),
-                entries: SourceListType::Deb822(SourceListDeb822::from_str(&data).map_err(
-                    |e| SourcesListError::Deb822 {
+                entries: SourceListType::Deb822(SourceListDeb822::from_str(data).map_err(|e| {
+                    SourcesListError::Deb822 {
                         path: path.to_path_buf(),
                         why: e,
-                    },
-                )?),
+                    }
+                })?),
             },
-            Some(x) if x == "list" => get_line_style_sources_list(&data)?,
-            _ => {
-                return Err(SourcesListError::UnknownFile {
-                    path: path.to_path_buf(),
-                })
-            }
+            SourceFormat::OneLine => get_line_style_sources_list(data)?,
         };
 
         sources_file.path = path.to_path_buf();
@@ -85,7 +178,10 @@ impl SourcesList {
                     false
                 }
             }),
-            SourceListType::Deb822(e) => e.entries.iter().position(|x| x.url == entry),
+            SourceListType::Deb822(e) => e
+                .entries
+                .iter()
+                .position(|x| !x.unsupported_type && x.url == entry),
         }
     }
 
@@ -107,7 +203,7 @@ impl SourcesList {
             }
             SourceListType::Deb822(ref mut e) => {
                 Box::new(e.entries.iter_mut().filter_map(move |e| {
-                    if entry == e.url {
+                    if !e.unsupported_type && entry == e.url {
                         return Some(e);
                     }
 
@@ -117,6 +213,70 @@ impl SourcesList {
         }
     }
 
+    /// Iterates on the source entries of this list, skipping comments, empty lines and stanzas
+    /// without a supported type.
+    pub fn source_entries(&self) -> Box<dyn Iterator<Item = &SourceEntry> + '_> {
+        Box::new(self.indexed_entries().map(|(_, entry)| entry))
+    }
+
+    /// Iterates on the source entries of this list along with their index, skipping stanzas
+    /// without a supported type.
+    ///
+    /// See `EntryLocation::index` for the meaning of the index.
+    pub fn indexed_entries(&self) -> Box<dyn Iterator<Item = (usize, &SourceEntry)> + '_> {
+        Box::new(
+            self.all_indexed_entries()
+                .filter(|(_, entry)| !entry.unsupported_type),
+        )
+    }
+
+    /// Iterates on every entry of this list along with its index, including the entries of
+    /// stanzas without a supported type, which apt rejects.
+    pub fn all_indexed_entries(&self) -> Box<dyn Iterator<Item = (usize, &SourceEntry)> + '_> {
+        match &self.entries {
+            SourceListType::SourceLine(lines) => {
+                Box::new(lines.0.iter().enumerate().filter_map(|(pos, x)| {
+                    if let SourceLine::Entry(entry) = x {
+                        Some((pos, entry))
+                    } else {
+                        None
+                    }
+                }))
+            }
+            SourceListType::Deb822(e) => Box::new(e.entries.iter().enumerate()),
+        }
+    }
+
+    /// Fetches the entry at the given index.
+    pub fn entry(&self, index: usize) -> Option<&SourceEntry> {
+        match &self.entries {
+            SourceListType::SourceLine(lines) => match lines.0.get(index) {
+                Some(SourceLine::Entry(entry)) => Some(entry),
+                _ => None,
+            },
+            SourceListType::Deb822(e) => e.entries.get(index),
+        }
+    }
+
+    /// Fetches the entry at the given index for modification.
+    pub fn entry_mut(&mut self, index: usize) -> Option<&mut SourceEntry> {
+        match &mut self.entries {
+            SourceListType::SourceLine(lines) => match lines.0.get_mut(index) {
+                Some(SourceLine::Entry(entry)) => Some(entry),
+                _ => None,
+            },
+            SourceListType::Deb822(e) => e.entries.get_mut(index),
+        }
+    }
+
+    /// The format of this list.
+    pub fn format(&self) -> SourceFormat {
+        match self.entries {
+            SourceListType::SourceLine(_) => SourceFormat::OneLine,
+            SourceListType::Deb822(_) => SourceFormat::Deb822,
+        }
+    }
+
     pub fn is_active(&self) -> bool {
         match &self.entries {
             SourceListType::SourceLine(line) => line
@@ -127,12 +287,48 @@ impl SourcesList {
         }
     }
 
+    /// Overwrites the file with the contents of this list.
+    ///
+    /// The new contents are written to a temporary file which then replaces the original, so
+    /// that the file is never left half-written. If the file is a symbolic link, the file it
+    /// points to is replaced instead.
     pub fn write_sync(&mut self) -> io::Result<()> {
-        fs::OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .open(&self.path)
-            .and_then(|mut file| writeln!(&mut file, "{}", self))
+        let (staged, target) = self.stage(&self.path)?;
+        fs::rename(&staged, target).inspect_err(|_| {
+            let _ = fs::remove_file(&staged);
+        })
+    }
+
+    /// Writes the contents of this list to a temporary file next to the file that its path
+    /// resolves to, with the permissions and owner of the file at `origin`, if it exists.
+    ///
+    /// Returns the temporary file, and the file that it is to replace.
+    fn stage(&self, origin: &Path) -> io::Result<(PathBuf, PathBuf)> {
+        let target = match fs::canonicalize(&self.path) {
+            Ok(target) => target,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => self.path.clone(),
+            Err(why) => return Err(why),
+        };
+
+        let staged = staging_path(&target)?;
+        let result = File::create(&staged)
+            .and_then(|mut file| {
+                write!(&mut file, "{}", self)?;
+                file.sync_all()
+            })
+            .and_then(|_| match fs::metadata(origin) {
+                Ok(metadata) => copy_metadata(&metadata, &staged),
+                Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(why) => Err(why),
+            });
+
+        match result {
+            Ok(()) => Ok((staged, target)),
+            Err(why) => {
+                let _ = fs::remove_file(&staged);
+                Err(why)
+            }
+        }
     }
 
     pub fn reload(&mut self) -> Result<(), SourcesListError> {
@@ -168,11 +364,7 @@ impl Display for SourcesList {
                     writeln!(fmt, "{}", line)?;
                 }
             }
-            SourceListType::Deb822(e) => {
-                for entry in &e.entries {
-                    writeln!(fmt, "{}", entry)?;
-                }
-            }
+            SourceListType::Deb822(e) => write!(fmt, "{}", e)?,
         }
 
         Ok(())
@@ -183,7 +375,33 @@ impl Display for SourcesList {
 /// Stores all apt source information fetched from the system.
 pub struct SourcesLists {
     pub(crate) files: Vec<SourcesList>,
-    pub(crate) modified: Vec<u16>,
+    /// The paths of the files whose contents must be written.
+    pub(crate) modified: Vec<PathBuf>,
+    /// The renames and removals of files to perform, in order.
+    pub(crate) operations: Vec<FileOperation>,
+    /// Files which were disabled with unwritten changes, to be written at their new path,
+    /// along with the path of the original file on the disk.
+    ///
+    /// They are no longer part of `files`, as apt ignores them, but are written along with
+    /// the modified files, with the permissions of the original.
+    pub(crate) detached: Vec<(SourcesList, PathBuf)>,
+}
+
+/// A change to a file on the disk, which is staged until `SourcesLists::write_sync`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FileOperation {
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Remove {
+        path: PathBuf,
+    },
+    /// Writes a file which is not a sources file, such as a keyring.
+    Write {
+        path: PathBuf,
+        contents: Vec<u8>,
+    },
 }
 
 impl Deref for SourcesLists {
@@ -225,6 +443,8 @@ impl SourcesLists {
 
         Ok(SourcesLists {
             modified: Vec::with_capacity(files.len()),
+            operations: Vec::new(),
+            detached: Vec::new(),
             files,
         })
     }
@@ -234,16 +454,17 @@ impl SourcesLists {
         let &mut Self {
             ref mut modified,
             ref mut files,
+            ..
         } = self;
 
-        let iterator = files
-            .iter_mut()
-            .enumerate()
-            .flat_map(|(pos, list)| list.get_entries_mut(repo).map(move |e| (pos, e)));
+        let iterator = files.iter_mut().flat_map(|list| {
+            let path = list.path.clone();
+            list.get_entries_mut(repo).map(move |e| (path.clone(), e))
+        });
 
         let mut found = false;
-        for (pos, entry) in iterator {
-            add_modified(modified, pos as u16);
+        for (path, entry) in iterator {
+            add_modified(modified, &path);
             entry.enabled = enabled;
             found = true;
         }
@@ -253,19 +474,7 @@ impl SourcesLists {
 
     /// Constructs an iterator of enabled source entries from a sources list.
     pub fn entries(&self) -> impl Iterator<Item = &SourceEntry> {
-        self.iter()
-            .flat_map(|list| -> Box<dyn Iterator<Item = &SourceEntry>> {
-                match &list.entries {
-                    SourceListType::SourceLine(lines) => Box::new(lines.0.iter().filter_map(|x| {
-                        if let SourceLine::Entry(entry) = x {
-                            Some(entry)
-                        } else {
-                            None
-                        }
-                    })),
-                    SourceListType::Deb822(e) => Box::new(e.entries.iter()),
-                }
-            })
+        self.iter().flat_map(SourcesList::source_entries)
     }
 
     /// A callback-based iterator that tracks which files have been modified.
@@ -273,22 +482,23 @@ impl SourcesLists {
         let &mut Self {
             ref mut files,
             ref mut modified,
+            ..
         } = self;
-        for (pos, list) in files.iter_mut().enumerate() {
+        for list in files.iter_mut() {
             match list.entries {
                 SourceListType::SourceLine(ref mut lines) => {
                     for entry in &mut lines.0 {
                         if let SourceLine::Entry(entry) = entry {
                             if func(entry) {
-                                add_modified(modified, pos as u16)
+                                add_modified(modified, &list.path)
                             }
                         }
                     }
                 }
                 SourceListType::Deb822(ref mut e) => {
-                    for entry in &mut e.entries {
+                    for entry in e.entries.iter_mut().filter(|e| !e.unsupported_type) {
                         if func(entry) {
-                            add_modified(modified, pos as u16)
+                            add_modified(modified, &list.path)
                         }
                     }
                 }
@@ -298,54 +508,135 @@ impl SourcesLists {
 
     /// Insert a source entry to the lists.
     ///
-    /// If the entry already exists, it will be modified.
-    /// Otherwise, the entry will be added to the preferred list.
-    /// If the preferred list does not exist, it will be created.
+    /// If an entry of the same type, URI and suite already exists in the file, the entry is
+    /// merged into it: its components and options are added, as are its architectures if the
+    /// existing entry is restricted to some. Its `signed-by` setting replaces the existing one
+    /// if set, and the existing entry becomes trusted if the new one is. Otherwise, in deb822
+    /// files, the entry is added to a stanza which differs only by its suites, if there is one,
+    /// or is appended to the file. If the file does not exist, it is created, in the format
+    /// given by its extension, unless a file is still to be renamed from its path.
+    ///
+    /// The file is marked as modified, to be written by `SourcesLists::write_sync`.
     pub fn insert_entry<P: AsRef<Path>>(
         &mut self,
         path: P,
         entry: SourceEntry,
-    ) -> SourceResult<()> {
+    ) -> SourceResult<InsertOutcome> {
+        let path = path.as_ref();
+        let format = match self.iter().find(|list| list.path == path) {
+            Some(list) => list.format(),
+            None => SourceFormat::from_path(path).ok_or_else(|| SourceError::UnknownFormat {
+                path: path.to_path_buf(),
+            })?,
+        };
+
+        self.insert_entry_as(path, entry, format)
+    }
+
+    /// Insert a source entry to the lists, in the given format.
+    ///
+    /// This behaves as `SourcesLists::insert_entry`, except that a file which does not exist
+    /// yet is created in the given format, whatever its extension. The entry is converted to
+    /// the format of its file.
+    pub fn insert_entry_as<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mut entry: SourceEntry,
+        format: SourceFormat,
+    ) -> SourceResult<InsertOutcome> {
         let path = path.as_ref();
+        if format == SourceFormat::OneLine
+            && matches!(entry.signed_by, Some(Signature::KeyBlock(_)))
+        {
+            return Err(SourceError::InvalidValue {
+                field: "signed-by",
+                value: "an embedded key cannot be written in the one-line format".into(),
+            });
+        }
+
+        // A new file cannot take the path of a file which is still to be renamed, which
+        // `SourcesLists::write_sync` would write before moving it away.
+        if self.renames_from(path) {
+            return Err(SourceError::FileExists {
+                path: path.to_path_buf(),
+            });
+        }
+
         let &mut Self {
             ref mut modified,
             ref mut files,
+            ..
         } = self;
 
-        for (id, list) in files.iter_mut().enumerate() {
-            if list.path == path {
-                match list.contains_entry(&entry.url) {
-                    Some(pos) => match list.entries {
-                        SourceListType::SourceLine(ref mut lines) => {
-                            lines.0[pos] = SourceLine::Entry(entry)
-                        }
-                        SourceListType::Deb822(ref mut e) => {
-                            e.entries[pos] = entry;
-                        }
-                    },
-                    None => match list.entries {
-                        SourceListType::SourceLine(ref mut lines) => {
-                            lines.0.push(SourceLine::Entry(entry))
-                        }
-                        SourceListType::Deb822(ref mut e) => {
-                            e.entries.push(entry);
+        let id = match files.iter().position(|list| list.path == path) {
+            Some(id) if files[id].format() != format => {
+                return Err(SourceError::FormatMismatch {
+                    path: path.to_path_buf(),
+                    format,
+                })
+            }
+            Some(id) => id,
+            None => {
+                files.push(SourcesList {
+                    path: path.to_path_buf(),
+                    entries: match format {
+                        SourceFormat::OneLine => {
+                            SourceListType::SourceLine(SourceListLineStyle(Vec::new()))
                         }
+                        SourceFormat::Deb822 => SourceListType::Deb822(SourceListDeb822 {
+                            entries: Vec::new(),
+                        }),
                     },
+                });
+                files.len() - 1
+            }
+        };
+
+        entry.set_style(format == SourceFormat::Deb822);
+
+        let list = &mut files[id];
+        let existing = list
+            .indexed_entries()
+            .find(|(_, e)| e.same_target(&entry))
+            .map(|(index, _)| index);
+
+        let outcome = match existing {
+            Some(index) => {
+                if let Some(e) = list.entry_mut(index) {
+                    merge_entry(e, entry);
                 }
 
-                add_modified(modified, id as u16);
-                return Ok(());
+                InsertOutcome::Updated(EntryLocation {
+                    path: path.to_path_buf(),
+                    index,
+                })
             }
-        }
+            None => {
+                let index = match list.entries {
+                    SourceListType::SourceLine(ref mut lines) => {
+                        lines.0.push(SourceLine::Entry(entry));
+                        lines.0.len() - 1
+                    }
+                    SourceListType::Deb822(ref mut e) => {
+                        let index = e
+                            .entries
+                            .iter()
+                            .rposition(|x| same_stanza(x, &entry))
+                            .map_or(e.entries.len(), |pos| pos + 1);
+                        e.entries.insert(index, entry);
+                        index
+                    }
+                };
 
-        files.push(SourcesList {
-            path: path.to_path_buf(),
-            entries: SourceListType::SourceLine(SourceListLineStyle(vec![SourceLine::Entry(
-                entry,
-            )])),
-        });
+                InsertOutcome::Inserted(EntryLocation {
+                    path: path.to_path_buf(),
+                    index,
+                })
+            }
+        };
 
-        Ok(())
+        add_modified(modified, path);
+        Ok(outcome)
     }
 
     /// Remove the source entry from each file in the sources lists.
@@ -353,8 +644,9 @@ impl SourcesLists {
         let &mut Self {
             ref mut modified,
             ref mut files,
+            ..
         } = self;
-        for (id, list) in files.iter_mut().enumerate() {
+        for list in files.iter_mut() {
             if let Some(line) = list.contains_entry(repo) {
                 match list.entries {
                     SourceListType::SourceLine(ref mut lines) => {
@@ -364,47 +656,17 @@ impl SourcesLists {
                         e.entries.remove(line);
                     }
                 }
-                add_modified(modified, id as u16);
+                add_modified(modified, &list.path);
             }
         }
     }
 
     /// Modify all sources with the `from_suite` to point to the `to_suite`.
     ///
-    /// Changes are only applied in-memory. Use `SourcesLists::wirte_sync` to write
-    /// all changes to the disk.
+    /// See `SourceEntry::replace_release` for how suites are matched. Changes are only
+    /// applied in-memory. Use `SourcesLists::write_sync` to write all changes to the disk.
     pub fn dist_replace(&mut self, from_suite: &str, to_suite: &str) {
-        let &mut Self {
-            ref mut modified,
-            ref mut files,
-        } = self;
-        for (id, file) in files.iter_mut().enumerate() {
-            let mut changed = false;
-            match file.entries {
-                SourceListType::SourceLine(ref mut lines) => {
-                    for line in &mut lines.0 {
-                        if let SourceLine::Entry(ref mut entry) = line {
-                            if entry.suite.starts_with(from_suite) {
-                                entry.suite = entry.suite.replace(from_suite, to_suite);
-                                changed = true;
-                            }
-                        }
-                    }
-                }
-                SourceListType::Deb822(ref mut e) => {
-                    for entry in &mut e.entries {
-                        if entry.suite.starts_with(from_suite) {
-                            entry.suite = entry.suite.replace(from_suite, to_suite);
-                            changed = true;
-                        }
-                    }
-                }
-            }
-
-            if changed {
-                add_modified(modified, id as u16);
-            }
-        }
+        self.entries_mut(|entry| entry.replace_release(from_suite, to_suite));
     }
 
     /// Upgrade entries so that they point to a new release.
@@ -456,9 +718,8 @@ impl SourcesLists {
                             if let SourceLine::Entry(entry) = line {
                                 if !retain.contains(entry.url.as_str())
                                     && entry.url.starts_with("http")
-                                    && entry.suite.starts_with(from_suite)
                                 {
-                                    entry.suite = entry.suite.replace(from_suite, to_suite);
+                                    entry.replace_release(from_suite, to_suite);
                                 }
                             }
 
@@ -497,13 +758,8 @@ impl SourcesLists {
         to_suite: &'a str,
     ) -> impl Iterator<Item = String> + 'a {
         self.entries().filter_map(move |entry| {
-            if entry.url.starts_with("http") && entry.suite.starts_with(from_suite) {
-                let entry = {
-                    let mut entry = entry.clone();
-                    entry.suite = entry.suite.replace(from_suite, to_suite);
-                    entry
-                };
-
+            let mut entry = entry.clone();
+            if entry.url.starts_with("http") && entry.replace_release(from_suite, to_suite) {
                 let dist_path = entry.dist_path();
                 Some(dist_path)
             } else {
@@ -512,18 +768,318 @@ impl SourcesLists {
         })
     }
 
-    /// Overwrite all files which were modified.
+    /// Overwrite all files which were modified, and apply the staged renames and removals
+    /// of files.
+    ///
+    /// Every modified file is first written to a temporary file. The originals are only
+    /// replaced once all of them have been written successfully. Files are renamed and
+    /// removed afterwards, so that no file is removed before its replacement is in place.
+    ///
+    /// On error, the operations which were applied are dropped, so that calling this again
+    /// rewrites the modified files and resumes with the remaining operations.
     pub fn write_sync(&mut self) -> io::Result<()> {
         let &mut Self {
             ref mut modified,
             ref mut files,
+            ref mut operations,
+            ref mut detached,
         } = self;
-        modified
-            .drain(..)
-            .try_for_each(|id| files[id as usize].write_sync())
+
+        // A renamed or disabled file keeps the permissions of the original.
+        let lists = modified
+            .iter()
+            .filter_map(|path| files.iter().find(|list| list.path == *path))
+            .map(|list| (list, origin(operations, &list.path)))
+            .chain(detached.iter().map(|(list, from)| (list, from.as_path())));
+
+        let mut staged = Vec::with_capacity(modified.len() + detached.len());
+        for (list, origin) in lists {
+            match list.stage(origin) {
+                Ok((path, target)) => staged.push((path, target, &list.path)),
+                Err(why) => {
+                    for (path, ..) in staged {
+                        let _ = fs::remove_file(path);
+                    }
+
+                    return Err(why);
+                }
+            }
+        }
+
+        let mut written = Vec::with_capacity(staged.len());
+        let mut staged = staged.into_iter();
+        while let Some((path, target, list_path)) = staged.next() {
+            if let Err(why) = fs::rename(&path, &target) {
+                let _ = fs::remove_file(path);
+                for (path, ..) in staged {
+                    let _ = fs::remove_file(path);
+                }
+
+                return Err(why);
+            }
+
+            written.push(list_path.clone());
+        }
+
+        let ignore_missing = |result: io::Result<()>| match result {
+            Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+
+        // A path which was written has its final contents, whatever was renamed to or
+        // removed from it before. Paths which are still to be renamed cannot be written, as
+        // refused by `add_file` and `rename_file`.
+        let mut applied = 0;
+        let result = operations.iter().try_for_each(|op| -> io::Result<()> {
+            match op {
+                FileOperation::Rename { from, to } if written.contains(to) => {
+                    ignore_missing(fs::remove_file(from))?
+                }
+                FileOperation::Rename { from, to } => ignore_missing(fs::rename(from, to))?,
+                FileOperation::Remove { path } if written.contains(path) => (),
+                FileOperation::Remove { path } => ignore_missing(fs::remove_file(path))?,
+                FileOperation::Write { path, contents } => write_file(path, contents)?,
+            }
+
+            applied += 1;
+            Ok(())
+        });
+
+        operations.drain(..applied);
+        result?;
+
+        modified.clear();
+        operations.clear();
+        detached.clear();
+        Ok(())
+    }
+
+    /// Adds a new file to the lists, to be created by `SourcesLists::write_sync`.
+    ///
+    /// Any file which already exists at its path will be overwritten, unless it is still to be
+    /// renamed.
+    pub fn add_file(&mut self, list: SourcesList) -> SourceResult<()> {
+        if self.iter().any(|l| l.path == list.path) || self.renames_from(&list.path) {
+            return Err(SourceError::FileExists { path: list.path });
+        }
+
+        add_modified(&mut self.modified, &list.path);
+        self.files.push(list);
+        Ok(())
+    }
+
+    /// Removes a file from the lists, to be deleted by `SourcesLists::write_sync`.
+    ///
+    /// Returns the removed list.
+    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> SourceResult<SourcesList> {
+        let path = path.as_ref();
+        let pos = self
+            .iter()
+            .position(|list| list.path == path)
+            .ok_or(SourceError::FileNotFound)?;
+
+        self.modified.retain(|p| p != path);
+        self.operations.push(FileOperation::Remove {
+            path: path.to_path_buf(),
+        });
+        Ok(self.files.remove(pos))
+    }
+
+    /// Renames a file of the lists, to be moved by `SourcesLists::write_sync`.
+    ///
+    /// The new name must not change the format that apt expects from the extension of the
+    /// file, nor be taken by another file, on the disk or still to be written.
+    pub fn rename_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> SourceResult<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        if self.clashes_with(to) {
+            return Err(SourceError::FileExists {
+                path: to.to_path_buf(),
+            });
+        }
+
+        let list = self
+            .files
+            .iter_mut()
+            .find(|list| list.path == from)
+            .ok_or(SourceError::FileNotFound)?;
+
+        if let Some(format) = SourceFormat::from_path(to).filter(|f| *f != list.format()) {
+            return Err(SourceError::FormatMismatch {
+                path: to.to_path_buf(),
+                format,
+            });
+        }
+
+        list.path = to.to_path_buf();
+        for path in &mut self.modified {
+            if path == from {
+                *path = to.to_path_buf();
+            }
+        }
+
+        self.operations.push(FileOperation::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Disables a file by removing it from the lists, and renaming it with a `.disabled`
+    /// suffix which apt ignores, through `SourcesLists::write_sync`.
+    ///
+    /// Changes to the file which were not written yet are written to the disabled file. The
+    /// file is then no longer part of the lists, but it is still listed by
+    /// `SourcesLists::modified_paths` under its new path until it is written. A disabled
+    /// file which already exists, such as an earlier backup, is never replaced.
+    ///
+    /// Returns the path of the disabled file.
+    pub fn disable_file<P: AsRef<Path>>(&mut self, path: P) -> SourceResult<PathBuf> {
+        let path = path.as_ref();
+        let mut disabled = path.as_os_str().to_owned();
+        disabled.push(".disabled");
+        let disabled = PathBuf::from(disabled);
+        if self.clashes_with(&disabled) {
+            return Err(SourceError::FileExists { path: disabled });
+        }
+
+        let was_modified = self.modified.iter().any(|p| p == path);
+        let from = origin(&self.operations, path).to_path_buf();
+        let mut list = self.remove_file(path)?;
+        if was_modified {
+            list.path = disabled.clone();
+            self.detached.push((list, from));
+        } else {
+            self.operations.pop();
+            self.operations.push(FileOperation::Rename {
+                from: path.to_path_buf(),
+                to: disabled.clone(),
+            });
+        }
+
+        Ok(disabled)
+    }
+
+    /// The renames, removals and writes of files which were not applied yet.
+    pub fn pending_operations(&self) -> &[FileOperation] {
+        &self.operations
+    }
+
+    /// Whether a file is still to be written at the path by `SourcesLists::write_sync`.
+    pub(crate) fn writes_to(&self, path: &Path) -> bool {
+        self.operations
+            .iter()
+            .any(|op| matches!(op, FileOperation::Write { path: p, .. } if p == path))
+    }
+
+    /// Whether a file exists at the path, on the disk or in the lists, or is still to be
+    /// written or renamed from it.
+    fn clashes_with(&self, path: &Path) -> bool {
+        path.exists()
+            || self.iter().any(|list| list.path == path)
+            || self.writes_to(path)
+            || self.renames_from(path)
+    }
+
+    /// Whether the file at the path is still to be renamed by `SourcesLists::write_sync`.
+    fn renames_from(&self, path: &Path) -> bool {
+        self.operations
+            .iter()
+            .any(|op| matches!(op, FileOperation::Rename { from, .. } if from == path))
+    }
+
+    /// The paths of the files whose changes were not written yet.
+    pub fn modified_paths(&self) -> impl Iterator<Item = &Path> {
+        self.modified
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(self.detached.iter().map(|(list, _)| list.path.as_path()))
+    }
+
+    /// Lists how each entry pins its keys, and entries which do not pin any key.
+    pub fn key_pinnings(&self) -> impl Iterator<Item = (EntryLocation, Option<KeyPinning>)> + '_ {
+        self.located_entries()
+            .map(|(location, entry)| (location, entry.key_pinning()))
+    }
+
+    /// Iterates on every source entry along with its location, skipping stanzas without a
+    /// supported type.
+    pub fn located_entries(&self) -> impl Iterator<Item = (EntryLocation, &SourceEntry)> {
+        self.all_located_entries()
+            .filter(|(_, entry)| !entry.unsupported_type)
+    }
+
+    /// Iterates on every entry along with its location, including the entries of stanzas
+    /// without a supported type.
+    pub fn all_located_entries(&self) -> impl Iterator<Item = (EntryLocation, &SourceEntry)> {
+        self.iter().flat_map(|list| {
+            list.all_indexed_entries().map(move |(index, entry)| {
+                let location = EntryLocation {
+                    path: list.path.clone(),
+                    index,
+                };
+                (location, entry)
+            })
+        })
+    }
+
+    /// Fetches the entry at the given location.
+    pub fn entry_at(&self, location: &EntryLocation) -> Option<&SourceEntry> {
+        self.iter()
+            .find(|list| list.path == location.path)
+            .and_then(|list| list.entry(location.index))
     }
 }
 
+/// The temporary file used to stage the new contents of a sources file.
+pub(crate) fn staging_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("filename not found for apt source at '{}'", path.display()),
+        )
+    })?;
+
+    let mut staged = std::ffi::OsString::from(".");
+    staged.push(name);
+    staged.push(".tmp");
+    Ok(path.with_file_name(staged))
+}
+
+/// Gives a staged file the permissions and owner of the file it replaces.
+fn copy_metadata(metadata: &fs::Metadata, staged: &Path) -> io::Result<()> {
+    fs::set_permissions(staged, metadata.permissions())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let current = fs::metadata(staged)?;
+        if (current.uid(), current.gid()) != (metadata.uid(), metadata.gid()) {
+            std::os::unix::fs::chown(staged, Some(metadata.uid()), Some(metadata.gid()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a file through a temporary file, so that it is never left half-written.
+pub(crate) fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let staged = staging_path(path)?;
+    fs::write(&staged, contents)
+        .and_then(|_| fs::rename(&staged, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&staged);
+        })
+}
+
 fn scan_inner<P: AsRef<Path>>(dir: P) -> Result<SourcesLists, SourcesListError> {
     let paths = sources_list(dir)?;
 
@@ -545,7 +1101,7 @@ pub(crate) fn sources_list<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Sourc
             let path = entry.path();
             if path
                 .extension()
-                .map_or(false, |e| e == "list" || e == "sources")
+                .is_some_and(|e| e == "list" || e == "sources")
             {
                 paths.push(path);
             }
@@ -555,8 +1111,53 @@ pub(crate) fn sources_list<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Sourc
     Ok(paths)
 }
 
-fn add_modified(modified: &mut Vec<u16>, list: u16) {
-    if !modified.iter().any(|&v| v == list) {
-        modified.push(list);
+/// The path that a file had on the disk, before the given operations.
+pub(crate) fn origin<'a>(operations: &'a [FileOperation], path: &'a Path) -> &'a Path {
+    operations.iter().rev().fold(path, |path, op| match op {
+        FileOperation::Rename { from, to } if to == path => from,
+        _ => path,
+    })
+}
+
+/// Merges an entry into an existing one of the same target, as `insert_entry` does.
+fn merge_entry(existing: &mut SourceEntry, entry: SourceEntry) {
+    fn extend(values: &mut Vec<String>, new: Vec<String>) {
+        for value in new {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+    }
+
+    existing.enabled = entry.enabled;
+    existing.url = entry.url;
+    extend(&mut existing.components, entry.components);
+    // An entry without architectures already covers every architecture.
+    if let (Some(archs), Some(new)) = (&mut existing.archs, entry.archs) {
+        extend(archs, new);
+    }
+
+    if entry.signed_by.is_some() {
+        existing.signed_by = entry.signed_by;
+    }
+    if entry.trusted {
+        existing.trusted = true;
+    }
+
+    for (key, values) in entry.options {
+        match existing
+            .options
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(&key))
+        {
+            Some(option) => option.1 = values,
+            None => existing.options.push((key, values)),
+        }
+    }
+}
+
+pub(crate) fn add_modified(modified: &mut Vec<PathBuf>, path: &Path) {
+    if !modified.iter().any(|p| p == path) {
+        modified.push(path.to_path_buf());
     }
 }
@@ -0,0 +1,275 @@
+//! A parsed representation of the suite of a source entry.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// A pocket of a distribution release, such as `-updates` in `bookworm-updates`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Pocket {
+    /// `-updates`
+    Updates,
+    /// `-security`
+    Security,
+    /// `-backports`
+    Backports,
+    /// `-backports-sloppy`
+    BackportsSloppy,
+    /// `-proposed`
+    Proposed,
+    /// `-proposed-updates`
+    ProposedUpdates,
+    /// `/updates`, used for security updates by Debian up to buster.
+    LegacySecurity,
+}
+
+impl Pocket {
+    /// Every known pocket, longest suffixes first so that they can be matched in order.
+    const ALL: &'static [Pocket] = &[
+        Pocket::BackportsSloppy,
+        Pocket::ProposedUpdates,
+        Pocket::Backports,
+        Pocket::Security,
+        Pocket::Proposed,
+        Pocket::Updates,
+        Pocket::LegacySecurity,
+    ];
+
+    /// The suffix appended to the codename for this pocket.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Pocket::Updates => "-updates",
+            Pocket::Security => "-security",
+            Pocket::Backports => "-backports",
+            Pocket::BackportsSloppy => "-backports-sloppy",
+            Pocket::Proposed => "-proposed",
+            Pocket::ProposedUpdates => "-proposed-updates",
+            Pocket::LegacySecurity => "/updates",
+        }
+    }
+}
+
+impl fmt::Display for Pocket {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.suffix())
+    }
+}
+
+/// The suite of a source entry, split into its parts.
+///
+/// ```
+/// use oma_apt_sources_lists::{Pocket, Suite};
+///
+/// let suite = "bookworm-security".parse::<Suite>().unwrap();
+/// assert_eq!(suite.codename(), Some("bookworm"));
+/// assert_eq!(suite.pocket(), Some(&Pocket::Security));
+/// assert_eq!(suite.with_codename("trixie").to_string(), "trixie-security");
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Suite {
+    /// An exact path of a flat repository, such as `./`, which always ends with a slash.
+    Flat(String),
+    /// A release of a distribution, with an optional pocket.
+    ///
+    /// Suffixes which are not known pockets are kept in the codename, so that suites such
+    /// as `kali-rolling` are left intact.
+    Dist {
+        codename: String,
+        pocket: Option<Pocket>,
+    },
+}
+
+impl Suite {
+    /// The codename of the release, or `None` for flat repositories.
+    pub fn codename(&self) -> Option<&str> {
+        match self {
+            Suite::Flat(_) => None,
+            Suite::Dist { codename, .. } => Some(codename),
+        }
+    }
+
+    /// The pocket of the release, if there is one.
+    pub fn pocket(&self) -> Option<&Pocket> {
+        match self {
+            Suite::Flat(_) => None,
+            Suite::Dist { pocket, .. } => pocket.as_ref(),
+        }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        matches!(self, Suite::Flat(_))
+    }
+
+    /// Returns the same suite for another codename, keeping the pocket.
+    ///
+    /// Flat repositories are returned unchanged.
+    pub fn with_codename(&self, codename: &str) -> Suite {
+        match self {
+            Suite::Flat(_) => self.clone(),
+            Suite::Dist { pocket, .. } => Suite::Dist {
+                codename: codename.to_owned(),
+                pocket: pocket.clone(),
+            },
+        }
+    }
+
+    /// Returns the same release with another pocket, or without any.
+    ///
+    /// Flat repositories are returned unchanged.
+    pub fn with_pocket(&self, pocket: Option<Pocket>) -> Suite {
+        match self {
+            Suite::Flat(_) => self.clone(),
+            Suite::Dist { codename, .. } => Suite::Dist {
+                codename: codename.clone(),
+                pocket,
+            },
+        }
+    }
+
+    /// The suites that a complete system usually enables along with this one: the release
+    /// itself and its `-updates`, `-security` and `-backports` pockets.
+    ///
+    /// Flat repositories have no siblings besides themselves.
+    pub fn siblings(&self) -> Vec<Suite> {
+        if self.is_flat() {
+            return vec![self.clone()];
+        }
+
+        [
+            None,
+            Some(Pocket::Updates),
+            Some(Pocket::Security),
+            Some(Pocket::Backports),
+        ]
+        .into_iter()
+        .map(|pocket| self.with_pocket(pocket))
+        .collect()
+    }
+
+    /// Whether both suites refer to the same release, regardless of their pockets.
+    pub fn same_release(&self, other: &Suite) -> bool {
+        match (self, other) {
+            (Suite::Flat(a), Suite::Flat(b)) => a == b,
+            _ => self.codename().is_some() && self.codename() == other.codename(),
+        }
+    }
+}
+
+impl From<&str> for Suite {
+    fn from(suite: &str) -> Self {
+        if suite.ends_with('/') {
+            return Suite::Flat(suite.to_owned());
+        }
+
+        for pocket in Pocket::ALL {
+            if let Some(codename) = suite.strip_suffix(pocket.suffix()) {
+                if !codename.is_empty() {
+                    return Suite::Dist {
+                        codename: codename.to_owned(),
+                        pocket: Some(pocket.clone()),
+                    };
+                }
+            }
+        }
+
+        Suite::Dist {
+            codename: suite.to_owned(),
+            pocket: None,
+        }
+    }
+}
+
+impl FromStr for Suite {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Suite::from(s))
+    }
+}
+
+impl fmt::Display for Suite {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Suite::Flat(path) => fmt.write_str(path),
+            Suite::Dist { codename, pocket } => {
+                fmt.write_str(codename)?;
+                if let Some(pocket) = pocket {
+                    fmt.write_str(pocket.suffix())?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let dist = |codename: &str, pocket| Suite::Dist {
+            codename: codename.into(),
+            pocket,
+        };
+
+        assert_eq!(Suite::from("bookworm"), dist("bookworm", None));
+        assert_eq!(
+            Suite::from("bookworm-backports-sloppy"),
+            dist("bookworm", Some(Pocket::BackportsSloppy))
+        );
+        assert_eq!(
+            Suite::from("buster/updates"),
+            dist("buster", Some(Pocket::LegacySecurity))
+        );
+        assert_eq!(Suite::from("kali-rolling"), dist("kali-rolling", None));
+        assert_eq!(Suite::from("-updates"), dist("-updates", None));
+        assert_eq!(Suite::from("./"), Suite::Flat("./".into()));
+        assert_eq!(Suite::from("stable/"), Suite::Flat("stable/".into()));
+
+        for suite in [
+            "bookworm",
+            "noble-proposed",
+            "buster/updates",
+            "./",
+            "kali-rolling",
+        ] {
+            assert_eq!(Suite::from(suite).to_string(), suite);
+        }
+    }
+
+    #[test]
+    fn change_codename() {
+        // The codename appears twice, which a plain string replacement would corrupt.
+        let suite = Suite::from("sid-sid-updates");
+        assert_eq!(
+            suite.with_codename("unstable").to_string(),
+            "unstable-updates"
+        );
+
+        let flat = Suite::from("./");
+        assert_eq!(flat.with_codename("trixie"), flat);
+    }
+
+    #[test]
+    fn siblings() {
+        let siblings = Suite::from("noble-proposed")
+            .siblings()
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            siblings,
+            [
+                "noble",
+                "noble-updates",
+                "noble-security",
+                "noble-backports"
+            ]
+        );
+        assert!(Suite::from("noble").same_release(&Suite::from("noble-security")));
+        assert!(!Suite::from("noble").same_release(&Suite::from("jammy")));
+    }
+}
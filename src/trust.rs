@@ -0,0 +1,700 @@
+//! Migration of repositories away from the keyrings that apt trusts for every repository.
+//!
+//! Entries without a `signed-by` option accept any key of **/etc/apt/trusted.gpg** and
+//! **/etc/apt/trusted.gpg.d**, which is deprecated. The signatures of the releases cached by
+//! apt tell which of these keys each repository actually needs, so that it can be moved to a
+//! dedicated keyring referenced by `signed-by`.
+
+use super::*;
+use crate::migration::glob_match;
+use crate::openpgp::{read_signatures, rooted, SignaturePacket};
+use crate::sources_list::add_modified;
+use crate::verify::{check_made_by, read_signed};
+use std::fmt;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Keyring files shipped by distributions, whose keys are shared by all of their repositories.
+const SHARED_KEYRINGS: &[&str] = &[
+    "debian-archive-*",
+    "debian-ports-archive-*",
+    "ubuntu-keyring-*",
+    "ubuntu-archive-*",
+];
+
+/// The directory recommended by `sources.list(5)` for keyrings managed by the administrator.
+const KEYRINGS_DIR: &str = "/etc/apt/keyrings";
+
+/// A key of a global keyring.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GlobalKey {
+    /// The keyring which contains the key, as a path of the system.
+    pub keyring: PathBuf,
+    pub certificate: Certificate,
+}
+
+/// Why the keys which sign a repository could not be determined.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UnresolvedSigner {
+    /// Neither `InRelease` nor `Release.gpg` is cached for the repository.
+    NoCachedRelease,
+    /// The cached signature could not be read.
+    Unreadable(String),
+    /// None of the global keyrings has the key which made the signature.
+    UnknownKey { issuer: String },
+    /// The signature names a global key, but was not made by it.
+    Unverified(String),
+}
+
+impl fmt::Display for UnresolvedSigner {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnresolvedSigner::NoCachedRelease => fmt.write_str("no cached release"),
+            UnresolvedSigner::Unreadable(why) => write!(fmt, "unreadable signature: {}", why),
+            UnresolvedSigner::UnknownKey { issuer } => {
+                write!(fmt, "signed by unknown key {}", issuer)
+            }
+            UnresolvedSigner::Unverified(why) => write!(fmt, "unverified signature: {}", why),
+        }
+    }
+}
+
+/// The global keys which sign the repository of an entry.
+#[derive(Clone, Debug)]
+pub struct GlobalSigners {
+    pub location: EntryLocation,
+    pub signers: Result<Vec<GlobalKey>, UnresolvedSigner>,
+}
+
+/// A key to be copied from a global keyring to a dedicated one.
+#[derive(Clone, Debug)]
+pub struct KeyMove {
+    pub certificate: Certificate,
+    /// The global keyring which contains the key.
+    pub from: PathBuf,
+    /// The dedicated keyring which the entries will refer to.
+    pub to: PathBuf,
+    /// The entries which are signed by the key.
+    pub entries: Vec<EntryLocation>,
+}
+
+/// The changes needed to stop relying on global keyrings.
+#[derive(Clone, Debug, Default)]
+pub struct TrustMigrationPlan {
+    pub moves: Vec<KeyMove>,
+    /// Entries signed by distribution keys, which are left unchanged.
+    pub shared: Vec<EntryLocation>,
+    /// Entries whose keys could not be determined.
+    pub unresolved: Vec<(EntryLocation, UnresolvedSigner)>,
+}
+
+/// Plans the migration of entries from global keyrings to dedicated keyrings.
+#[derive(Clone, Debug)]
+pub struct TrustMigrator {
+    keyrings_dir: PathBuf,
+    shared: Vec<String>,
+}
+
+impl Default for TrustMigrator {
+    fn default() -> Self {
+        TrustMigrator {
+            keyrings_dir: PathBuf::from(KEYRINGS_DIR),
+            shared: SHARED_KEYRINGS.iter().map(|&p| p.to_owned()).collect(),
+        }
+    }
+}
+
+impl TrustMigrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The directory to create dedicated keyrings in, **/etc/apt/keyrings** by default.
+    pub fn keyrings_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.keyrings_dir = dir.into();
+        self
+    }
+
+    /// Adds a glob pattern of keyring file names whose keys are shared by the repositories of
+    /// a distribution, and thus are not moved.
+    pub fn shared_keyring(mut self, pattern: &str) -> Self {
+        self.shared.push(pattern.to_owned());
+        self
+    }
+
+    fn is_shared_file(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|n| n.to_string_lossy());
+        name.is_some_and(|name| self.shared.iter().any(|p| glob_match(p, &name)))
+    }
+
+    /// Plans which keys to move, reading keyrings and cached releases below the root argument.
+    ///
+    /// Keys which are also found in a shared keyring of **/usr/share/keyrings** are left in
+    /// the global keyrings, along with the entries that they sign. Dedicated keyrings are
+    /// named after the host of the repository, and never take the name of an existing file.
+    pub fn plan<P: AsRef<Path>>(&self, lists: &SourcesLists, root: P) -> TrustMigrationPlan {
+        let root = root.as_ref();
+        let mut shared_keys = Vec::new();
+        if let Ok(dir) = fs::read_dir(root.join("usr/share/keyrings")) {
+            for path in dir.filter_map(|e| e.ok().map(|e| e.path())) {
+                if self.is_shared_file(&path) {
+                    if let Ok(keyring) = Keyring::open(&path) {
+                        shared_keys.extend(keyring.certificates);
+                    }
+                }
+            }
+        }
+
+        let is_shared = |key: &GlobalKey| {
+            self.is_shared_file(&key.keyring)
+                || shared_keys
+                    .iter()
+                    .any(|c| c.fingerprint() == key.certificate.fingerprint())
+        };
+
+        let mut plan = TrustMigrationPlan::default();
+        for GlobalSigners { location, signers } in lists.global_key_signers(root) {
+            let signers = match signers {
+                Ok(signers) => signers,
+                Err(why) => {
+                    plan.unresolved.push((location, why));
+                    continue;
+                }
+            };
+
+            if signers.iter().any(is_shared) {
+                plan.shared.push(location);
+                continue;
+            }
+
+            for key in signers {
+                let fingerprint = key.certificate.fingerprint();
+                if let Some(existing) = plan
+                    .moves
+                    .iter_mut()
+                    .find(|m| m.certificate.fingerprint() == fingerprint)
+                {
+                    existing.entries.push(location.clone());
+                    continue;
+                }
+
+                let url = lists.entry_at(&location).map(|e| e.url.clone());
+                let host = url
+                    .and_then(|url| Url::parse(&url).ok())
+                    .and_then(|url| url.host_str().map(str::to_owned));
+                let key_id = key.certificate.primary.key_id().to_owned();
+
+                // The keyring must not replace one of the plan, nor a file of the system.
+                let name = host.unwrap_or_else(|| key_id.clone());
+                let to = iter::once(name.clone())
+                    .chain(iter::once([&name, "-", &key_id].concat()))
+                    .chain((2..).map(|n| format!("{}-{}-{}", name, key_id, n)))
+                    .map(|name| self.keyring_path(&name))
+                    .find(|path| {
+                        let rooted = rooted(root, path);
+                        !plan.moves.iter().any(|m| m.to == *path)
+                            && !rooted.exists()
+                            && !lists.writes_to(&rooted)
+                    })
+                    .expect("an unused keyring name");
+
+                plan.moves.push(KeyMove {
+                    to,
+                    from: key.keyring,
+                    certificate: key.certificate,
+                    entries: vec![location.clone()],
+                });
+            }
+        }
+
+        plan
+    }
+
+    fn keyring_path(&self, name: &str) -> PathBuf {
+        self.keyrings_dir.join([name, ".gpg"].concat())
+    }
+}
+
+impl SourcesLists {
+    /// Determines which keys of the global keyrings sign the repository of every entry that
+    /// has no `signed-by` option.
+    ///
+    /// The signatures are read from the `InRelease` or `Release.gpg` files cached in
+    /// **var/lib/apt/lists**, and the keyrings from **etc/apt**, below the root argument.
+    pub fn global_key_signers<P: AsRef<Path>>(&self, root: P) -> Vec<GlobalSigners> {
+        let root = root.as_ref();
+        let keyrings = trusted_keyrings(root)
+            .into_iter()
+            .filter_map(|(path, keyring)| Some((path, keyring.ok()?)))
+            .collect::<Vec<_>>();
+
+        let lists_dir = root.join("var/lib/apt/lists");
+        self.located_entries()
+            .filter(|(_, entry)| entry.signed_by.is_none())
+            .map(|(location, entry)| {
+                let in_release = lists_dir.join(entry.lists_filename("InRelease"));
+                let detached = lists_dir.join(entry.lists_filename("Release.gpg"));
+                let cached = if in_release.exists() {
+                    Some((in_release, None))
+                } else if detached.exists() {
+                    Some((
+                        lists_dir.join(entry.lists_filename("Release")),
+                        Some(detached),
+                    ))
+                } else {
+                    None
+                };
+
+                let signers = match cached {
+                    None => Err(UnresolvedSigner::NoCachedRelease),
+                    Some((release, detached)) => read_signed(&release, detached.as_deref())
+                        .map_err(UnresolvedSigner::Unreadable)
+                        .and_then(|(message, signature)| {
+                            read_signatures(&signature)
+                                .map(|signatures| (message, signatures))
+                                .map_err(|why| UnresolvedSigner::Unreadable(why.to_string()))
+                        })
+                        .and_then(|(message, signatures)| {
+                            signers(root, &keyrings, &message, &signatures)
+                        }),
+                };
+
+                GlobalSigners { location, signers }
+            })
+            .collect()
+    }
+
+    /// Copies the keys of the plan to their dedicated keyrings, and sets the `signed-by`
+    /// option of the entries that they sign.
+    ///
+    /// Keyrings are written below the root argument by `SourcesLists::write_sync`, after the
+    /// entries. If `remove_from_global` is set, the keys are also removed from the global
+    /// keyrings, and keyring files left empty are deleted. This is refused if an entry which
+    /// is not migrated may still rely on one of these keys, such as an entry whose signers
+    /// are unresolved. Entries which were given a `signed-by` option since the plan was made
+    /// are left unchanged. Nothing is changed if a dedicated keyring of the plan already
+    /// exists, or is still to be written.
+    pub fn apply_trust_migration<P: AsRef<Path>>(
+        &mut self,
+        plan: &TrustMigrationPlan,
+        root: P,
+        remove_from_global: bool,
+    ) -> Result<(), KeyError> {
+        let root = root.as_ref();
+        for key_move in &plan.moves {
+            let path = rooted(root, &key_move.to);
+            if path.exists() || self.writes_to(&path) {
+                return Err(KeyError::KeyringExists { path });
+            }
+        }
+
+        let mut operations = Vec::new();
+        if remove_from_global {
+            self.check_unneeded(plan, root)?;
+            operations = global_removals(plan, root)?;
+        }
+
+        let &mut Self {
+            ref mut modified,
+            ref mut files,
+            ..
+        } = self;
+
+        for list in files.iter_mut() {
+            let indices = list.indexed_entries().map(|(i, _)| i).collect::<Vec<_>>();
+            for index in indices {
+                let location = EntryLocation {
+                    path: list.path.clone(),
+                    index,
+                };
+
+                let keyrings = plan
+                    .moves
+                    .iter()
+                    .filter(|m| m.entries.contains(&location))
+                    .map(|m| m.to.clone())
+                    .collect::<Vec<_>>();
+
+                if keyrings.is_empty() {
+                    continue;
+                }
+
+                if let Some(entry) = list.entry_mut(index) {
+                    if entry.signed_by.is_none() {
+                        entry.signed_by = Some(Signature::KeyPath(keyrings));
+                        add_modified(modified, &location.path);
+                    }
+                }
+            }
+        }
+
+        for key_move in &plan.moves {
+            let keyring = Keyring {
+                certificates: vec![key_move.certificate.clone()],
+            };
+
+            self.operations.push(FileOperation::Write {
+                path: rooted(root, &key_move.to),
+                contents: keyring.to_bytes(),
+            });
+        }
+
+        self.operations.extend(operations);
+        Ok(())
+    }
+
+    /// Fails if an entry which keeps relying on the global keyrings may need a key that the
+    /// plan moves.
+    fn check_unneeded(&self, plan: &TrustMigrationPlan, root: &Path) -> Result<(), KeyError> {
+        let Some(first) = plan.moves.first() else {
+            return Ok(());
+        };
+
+        for GlobalSigners { location, signers } in self.global_key_signers(root) {
+            if plan.moves.iter().any(|m| m.entries.contains(&location)) {
+                continue;
+            }
+
+            let needed = match signers {
+                Ok(signers) => plan.moves.iter().find(|m| {
+                    signers
+                        .iter()
+                        .any(|k| k.certificate.fingerprint() == m.certificate.fingerprint())
+                }),
+                // The key which signs the repository is not a global key.
+                Err(UnresolvedSigner::UnknownKey { .. }) => None,
+                // Any global key may sign the repository.
+                Err(_) => Some(first),
+            };
+
+            if let Some(key_move) = needed {
+                return Err(KeyError::KeyInUse {
+                    fingerprint: key_move.certificate.fingerprint().to_owned(),
+                    location,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The rewrites and removals of the global keyrings which the keys of the plan are moved from.
+fn global_removals(plan: &TrustMigrationPlan, root: &Path) -> Result<Vec<FileOperation>, KeyError> {
+    let mut operations = Vec::new();
+    let mut paths: Vec<&Path> = Vec::new();
+    for key_move in &plan.moves {
+        if !paths.contains(&key_move.from.as_path()) {
+            paths.push(&key_move.from);
+        }
+    }
+
+    for path in paths {
+        let path = rooted(root, path);
+        let mut keyring = Keyring::open(&path)?;
+        keyring.certificates.retain(|certificate| {
+            !plan
+                .moves
+                .iter()
+                .any(|m| m.certificate.fingerprint() == certificate.fingerprint())
+        });
+
+        if keyring.certificates.is_empty() {
+            operations.push(FileOperation::Remove { path });
+            continue;
+        }
+
+        let contents = if path.extension().is_some_and(|x| x == "asc") {
+            keyring.to_armored().into_bytes()
+        } else {
+            keyring.to_bytes()
+        };
+
+        operations.push(FileOperation::Write { path, contents });
+    }
+
+    Ok(operations)
+}
+
+/// Finds the global keys which made the signatures of the message.
+///
+/// Signatures by unknown keys are ignored as long as one of the keys is found, since apt
+/// only requires a single good signature. A key is only found once the signature is checked
+/// against it, so that a stale or forged signature cannot name an unrelated key.
+fn signers(
+    root: &Path,
+    keyrings: &[(PathBuf, Keyring)],
+    message: &[u8],
+    signatures: &[SignaturePacket],
+) -> Result<Vec<GlobalKey>, UnresolvedSigner> {
+    let mut found: Vec<GlobalKey> = Vec::new();
+    let mut unknown = None;
+    let mut unverified = None;
+
+    for signature in signatures {
+        let key = keyrings.iter().find_map(|(path, keyring)| {
+            keyring.certificates.iter().find_map(|certificate| {
+                let key = certificate.issuer_of(signature)?;
+                Some((path, certificate, key))
+            })
+        });
+
+        match key {
+            Some((path, certificate, key)) => {
+                if let Err(status) = check_made_by(message, signature, certificate, key) {
+                    unverified = Some(status.to_string());
+                } else if !found.iter().any(|k| k.certificate == *certificate) {
+                    found.push(GlobalKey {
+                        keyring: Path::new("/").join(path.strip_prefix(root).unwrap_or(path)),
+                        certificate: certificate.clone(),
+                    });
+                }
+            }
+            None => {
+                unknown = signature
+                    .issuer_fingerprint
+                    .clone()
+                    .or_else(|| signature.issuer.clone())
+            }
+        }
+    }
+
+    if found.is_empty() {
+        return Err(match unverified {
+            Some(why) => UnresolvedSigner::Unverified(why),
+            None => UnresolvedSigner::UnknownKey {
+                issuer: unknown.unwrap_or_default(),
+            },
+        });
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, TempRoot};
+
+    const SOURCES: &str = "\
+deb https://example.org/debian stable main
+deb-src https://example.org/debian stable main
+deb http://deb.debian.org/debian bookworm main
+deb https://legacy.example.com/apt ./
+deb https://unknown.example.net/debian stable main
+deb [signed-by=/usr/share/keyrings/example-archive.asc] https://example.org/debian stable contrib
+";
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[("sources.list", SOURCES)])
+    }
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/root")
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn signers() {
+        let signers = lists().global_key_signers(fixtures());
+        let found = signers
+            .iter()
+            .map(|s| match &s.signers {
+                Ok(keys) => Ok(keys
+                    .iter()
+                    .map(|k| {
+                        (
+                            k.keyring.to_string_lossy().into_owned(),
+                            k.certificate.fingerprint(),
+                        )
+                    })
+                    .collect::<Vec<_>>()),
+                Err(why) => Err(why.clone()),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            [
+                Ok(vec![(
+                    "/etc/apt/trusted.gpg".into(),
+                    "8356B50980AE5E15DD75D36B7EFE4E79A5238717"
+                )]),
+                Ok(vec![(
+                    "/etc/apt/trusted.gpg".into(),
+                    "8356B50980AE5E15DD75D36B7EFE4E79A5238717"
+                )]),
+                Ok(vec![(
+                    "/etc/apt/trusted.gpg.d/debian-archive-test-automatic.asc".into(),
+                    "F805F32E7D18FBC85EF01B536EAD5CF79296E117"
+                )]),
+                Ok(vec![(
+                    "/etc/apt/trusted.gpg".into(),
+                    "B92634F02D319DE1C0ED0AF1028C4910BBE90A9C"
+                )]),
+                Err(UnresolvedSigner::NoCachedRelease),
+            ]
+        );
+    }
+
+    #[test]
+    fn forged_signature() {
+        let root = TempRoot::new("trust-forged");
+        copy_dir(&fixtures(), &root);
+
+        // The signature still names the key of trusted.gpg, but no longer matches the release.
+        let name = root.join("var/lib/apt/lists/example.org_debian_dists_stable_InRelease");
+        let release = fs::read_to_string(&name).unwrap();
+        fs::write(&name, release.replacen("stable", "unstable", 1)).unwrap();
+
+        let signers = lists().global_key_signers(&root);
+        assert_eq!(
+            signers[0].signers,
+            Err(UnresolvedSigner::Unverified(
+                "bad signature by 8356B50980AE5E15DD75D36B7EFE4E79A5238717".into()
+            ))
+        );
+
+        let plan = TrustMigrator::new().plan(&lists(), &root);
+        assert!(plan
+            .moves
+            .iter()
+            .all(|m| !m.entries.contains(&tests::location("sources.list", 0))));
+    }
+
+    #[test]
+    fn existing_keyrings() {
+        let root = TempRoot::new("trust-existing");
+        copy_dir(&fixtures(), &root);
+
+        let mut lists = lists();
+        let plan = TrustMigrator::new().plan(&lists, &root);
+        let keyrings = root.join("etc/apt/keyrings");
+        fs::create_dir_all(&keyrings).unwrap();
+        fs::write(
+            keyrings.join("example.org.gpg"),
+            "managed by the administrator",
+        )
+        .unwrap();
+
+        // A keyring which appeared since the plan was made is not replaced.
+        assert!(matches!(
+            lists.apply_trust_migration(&plan, &root, false),
+            Err(KeyError::KeyringExists { path }) if path == keyrings.join("example.org.gpg")
+        ));
+        assert!(lists.modified.is_empty() && lists.pending_operations().is_empty());
+
+        // A new plan names the keyring after the key as well.
+        let plan = TrustMigrator::new().plan(&lists, &root);
+        assert_eq!(
+            plan.moves[0].to,
+            Path::new("/etc/apt/keyrings/example.org-7EFE4E79A5238717.gpg")
+        );
+        lists.apply_trust_migration(&plan, &root, false).unwrap();
+        assert!(matches!(
+            lists.apply_trust_migration(&plan, &root, false),
+            Err(KeyError::KeyringExists { .. })
+        ));
+    }
+
+    #[test]
+    fn migrate() {
+        let root = TempRoot::new("trust");
+        copy_dir(&fixtures(), &root);
+
+        let mut lists = lists();
+        let sources = root.join("etc/apt/sources.list");
+        lists.files[0].path = sources.clone();
+        let plan = TrustMigrator::new().plan(&lists, &root);
+        let at = |index| EntryLocation {
+            path: sources.clone(),
+            index,
+        };
+
+        let moves = plan
+            .moves
+            .iter()
+            .map(|m| (m.to.clone(), m.entries.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            moves,
+            [
+                (
+                    PathBuf::from("/etc/apt/keyrings/example.org.gpg"),
+                    vec![at(0), at(1)]
+                ),
+                (
+                    PathBuf::from("/etc/apt/keyrings/legacy.example.com.gpg"),
+                    vec![at(3)]
+                ),
+            ]
+        );
+        assert_eq!(plan.shared, [at(2)]);
+        assert_eq!(
+            plan.unresolved,
+            [(at(4), UnresolvedSigner::NoCachedRelease)]
+        );
+
+        // The unresolved entry may be signed by any of the global keys.
+        assert!(matches!(
+            lists.apply_trust_migration(&plan, &root, true),
+            Err(KeyError::KeyInUse { location, .. }) if location == at(4)
+        ));
+        assert!(lists.modified.is_empty() && lists.pending_operations().is_empty());
+
+        lists.files[0].entry_mut(4).unwrap().signed_by = Some(Signature::KeyPath(vec![
+            "/usr/share/keyrings/example-archive.asc".into(),
+        ]));
+        lists.apply_trust_migration(&plan, &root, true).unwrap();
+
+        // Nothing is written before the entries.
+        assert!(!root.join("etc/apt/keyrings/example.org.gpg").exists());
+        assert!(root.join("etc/apt/trusted.gpg").exists());
+        assert_eq!(lists.modified, [sources]);
+        lists.write_sync().unwrap();
+
+        let keyring = Keyring::open(root.join("etc/apt/keyrings/example.org.gpg")).unwrap();
+        let global_removed = !root.join("etc/apt/trusted.gpg").exists();
+        let shared_kept = root
+            .join("etc/apt/trusted.gpg.d/debian-archive-test-automatic.asc")
+            .exists();
+
+        assert_eq!(
+            keyring.certificates[0].fingerprint(),
+            "8356B50980AE5E15DD75D36B7EFE4E79A5238717"
+        );
+        assert!(global_removed);
+        assert!(shared_kept);
+        assert_eq!(lists.modified_paths().count(), 0);
+
+        let signed_by = lists
+            .entries()
+            .map(|e| e.signed_by.as_ref().and_then(Signature::to_option_value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            signed_by,
+            [
+                Some("/etc/apt/keyrings/example.org.gpg".into()),
+                Some("/etc/apt/keyrings/example.org.gpg".into()),
+                None,
+                Some("/etc/apt/keyrings/legacy.example.com.gpg".into()),
+                Some("/usr/share/keyrings/example-archive.asc".into()),
+                Some("/usr/share/keyrings/example-archive.asc".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Planning of release upgrades.
+//!
+//! Rather than rewriting files straight away like `SourcesLists::dist_upgrade`, an
+//! `UpgradePlanner` walks every entry and decides what should happen to it. The resulting
+//! `UpgradePlan` can be inspected and edited before being applied with
+//! `SourcesLists::apply_upgrade_plan`.
+
+use super::*;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Hosts of the Debian and Ubuntu archives.
+const OFFICIAL_HOSTS: &[&str] = &["debian.org", "ubuntu.com"];
+
+/// What to do with repositories that are not part of the distribution archive.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ThirdPartyPolicy {
+    /// Disable them, as they may not provide packages for the new release yet.
+    #[default]
+    Disable,
+    /// Leave them untouched.
+    Keep,
+    /// Point them to the new release like the distribution archive.
+    Upgrade,
+}
+
+/// Why an entry is not simply upgraded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PlanReason {
+    /// The URL of the entry is in the retain list.
+    Retained,
+    /// The entry does not point to the release that is being upgraded.
+    OtherSuite,
+    /// The entry already points to the new release.
+    AlreadyUpgraded,
+    /// The entry is not part of the distribution archive.
+    ThirdParty,
+    /// The entry is fetched from a local medium, such as `cdrom:` or `file:`.
+    NonNetworkUri,
+    /// The entry enables the `-proposed` pocket, which is rarely wanted on a new release.
+    ProposedPocket,
+    /// The entry uses the `codename/updates` security layout, which was renamed over time.
+    LegacySecurityLayout,
+    /// The suite has a suffix that is not a known pocket.
+    UnknownPocket(String),
+    /// A reason given when editing the plan.
+    Other(String),
+}
+
+impl fmt::Display for PlanReason {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanReason::Retained => fmt.write_str("repository is retained"),
+            PlanReason::OtherSuite => fmt.write_str("entry does not point to the old release"),
+            PlanReason::AlreadyUpgraded => fmt.write_str("entry already points to the new release"),
+            PlanReason::ThirdParty => fmt.write_str("third-party repository"),
+            PlanReason::NonNetworkUri => {
+                fmt.write_str("repository is not fetched from the network")
+            }
+            PlanReason::ProposedPocket => fmt.write_str("proposed pocket"),
+            PlanReason::LegacySecurityLayout => {
+                fmt.write_str("security suite uses the legacy 'codename/updates' layout")
+            }
+            PlanReason::UnknownPocket(pocket) => write!(fmt, "unknown pocket '{}'", pocket),
+            PlanReason::Other(why) => fmt.write_str(why),
+        }
+    }
+}
+
+/// What will happen to an entry when the plan is applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PlannedAction {
+    /// The suite of the entry will be replaced.
+    Upgrade { suite: String },
+    /// The entry will be left as is.
+    Keep(PlanReason),
+    /// The entry will be disabled.
+    Disable(PlanReason),
+    /// The entry will be left as is, but should be reviewed before upgrading.
+    NeedsAttention(PlanReason),
+}
+
+/// The planned action for a single entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedEntry {
+    pub location: EntryLocation,
+    /// The entry as it was when the plan was made.
+    pub original: SourceEntry,
+    pub action: PlannedAction,
+}
+
+impl PlannedEntry {
+    /// The entry as it will be once the plan is applied.
+    pub fn planned(&self) -> SourceEntry {
+        let mut entry = self.original.clone();
+        match &self.action {
+            PlannedAction::Upgrade { suite } => entry.suite.clone_from(suite),
+            PlannedAction::Disable(_) => entry.enabled = false,
+            PlannedAction::Keep(_) | PlannedAction::NeedsAttention(_) => (),
+        }
+
+        entry
+    }
+}
+
+/// The result of planning a release upgrade.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpgradePlan {
+    pub from_suite: String,
+    pub to_suite: String,
+    pub entries: Vec<PlannedEntry>,
+}
+
+impl UpgradePlan {
+    /// Finds the planned action for the entry at the given location.
+    pub fn get(&self, location: &EntryLocation) -> Option<&PlannedEntry> {
+        self.entries.iter().find(|e| &e.location == location)
+    }
+
+    /// Replaces the planned action for the entry at the given location.
+    ///
+    /// Returns `false` if the entry is not part of the plan.
+    pub fn set_action(&mut self, location: &EntryLocation, action: PlannedAction) -> bool {
+        match self.entries.iter_mut().find(|e| &e.location == location) {
+            Some(entry) => {
+                entry.action = action;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Entries which should be reviewed before applying the plan.
+    pub fn needs_attention(&self) -> impl Iterator<Item = &PlannedEntry> {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.action, PlannedAction::NeedsAttention(_)))
+    }
+
+    /// Whether applying the plan would change anything.
+    pub fn has_changes(&self) -> bool {
+        self.entries.iter().any(|e| {
+            matches!(
+                e.action,
+                PlannedAction::Upgrade { .. } | PlannedAction::Disable(_)
+            )
+        })
+    }
+
+    /// Dist URLs of the enabled entries once upgraded, which can be used to check that the new
+    /// release is available before applying the plan.
+    pub fn dist_paths(&self) -> impl Iterator<Item = String> + '_ {
+        self.entries.iter().filter_map(|e| match &e.action {
+            PlannedAction::Upgrade { .. } if e.original.enabled => Some(e.planned().dist_path()),
+            _ => None,
+        })
+    }
+}
+
+/// Decides how every entry should change when upgrading from one release to another.
+#[derive(Clone, Debug)]
+pub struct UpgradePlanner {
+    from_suite: String,
+    to_suite: String,
+    retain: HashSet<Box<str>>,
+    official: Vec<String>,
+    third_party: ThirdPartyPolicy,
+}
+
+impl UpgradePlanner {
+    pub fn new(from_suite: &str, to_suite: &str) -> Self {
+        UpgradePlanner {
+            from_suite: from_suite.to_owned(),
+            to_suite: to_suite.to_owned(),
+            retain: HashSet::new(),
+            official: Vec::new(),
+            third_party: ThirdPartyPolicy::default(),
+        }
+    }
+
+    /// URLs of repositories which must be kept as they are.
+    pub fn retain(mut self, retain: HashSet<Box<str>>) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Treats repositories whose URL starts with the given prefix as part of the distribution.
+    ///
+    /// Hosts of the Debian and Ubuntu archives are recognized by default. Mirrors on other
+    /// hosts must be given here, as any host may serve a `/debian` path.
+    pub fn official_uri(mut self, prefix: &str) -> Self {
+        self.official.push(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// What to do with repositories that are not part of the distribution.
+    pub fn third_party(mut self, policy: ThirdPartyPolicy) -> Self {
+        self.third_party = policy;
+        self
+    }
+
+    /// Plans the upgrade of every entry in the lists.
+    pub fn plan(&self, lists: &SourcesLists) -> UpgradePlan {
+        let entries = lists
+            .located_entries()
+            .map(|(location, entry)| PlannedEntry {
+                location,
+                original: entry.clone(),
+                action: self.action(entry),
+            })
+            .collect();
+
+        UpgradePlan {
+            from_suite: self.from_suite.clone(),
+            to_suite: self.to_suite.clone(),
+            entries,
+        }
+    }
+
+    fn action(&self, entry: &SourceEntry) -> PlannedAction {
+        if self.retain.contains(entry.url.as_str()) {
+            return PlannedAction::Keep(PlanReason::Retained);
+        }
+
+        let suite = entry.parsed_suite();
+        let codename = suite.codename().unwrap_or_default();
+        if codename != self.from_suite {
+            // Suffixes which are not known pockets are kept in the codename.
+            if let Some(pocket) = codename
+                .strip_prefix(self.from_suite.as_str())
+                .filter(|rest| rest.starts_with(['-', '/']))
+            {
+                return PlannedAction::NeedsAttention(PlanReason::UnknownPocket(pocket.to_owned()));
+            }
+
+            return PlannedAction::Keep(if codename == self.to_suite {
+                PlanReason::AlreadyUpgraded
+            } else {
+                PlanReason::OtherSuite
+            });
+        }
+
+        if !["http://", "https://", "ftp://", "mirror"]
+            .iter()
+            .any(|scheme| entry.url.starts_with(scheme))
+        {
+            return PlannedAction::NeedsAttention(PlanReason::NonNetworkUri);
+        }
+
+        if !self.is_official(entry) {
+            match self.third_party {
+                ThirdPartyPolicy::Keep => return PlannedAction::Keep(PlanReason::ThirdParty),
+                ThirdPartyPolicy::Disable if entry.enabled => {
+                    return PlannedAction::Disable(PlanReason::ThirdParty)
+                }
+                ThirdPartyPolicy::Disable => return PlannedAction::Keep(PlanReason::ThirdParty),
+                ThirdPartyPolicy::Upgrade => (),
+            }
+        }
+
+        match suite.pocket() {
+            Some(Pocket::Proposed) => PlannedAction::NeedsAttention(PlanReason::ProposedPocket),
+            Some(Pocket::LegacySecurity) => {
+                PlannedAction::NeedsAttention(PlanReason::LegacySecurityLayout)
+            }
+            _ => PlannedAction::Upgrade {
+                suite: suite.with_codename(&self.to_suite).to_string(),
+            },
+        }
+    }
+
+    fn is_official(&self, entry: &SourceEntry) -> bool {
+        let url = entry.url();
+        let configured = self.official.iter().any(|prefix| {
+            url.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        if configured {
+            return true;
+        }
+
+        let Ok(parsed) = url::Url::parse(url) else {
+            return false;
+        };
+
+        let host = parsed.host_str().unwrap_or_default();
+        OFFICIAL_HOSTS
+            .iter()
+            .any(|h| host == *h || host.ends_with(&[".", h].concat()))
+    }
+}
+
+impl SourcesLists {
+    /// Plans an upgrade from one release to another with the default settings.
+    ///
+    /// Use an `UpgradePlanner` to customize how entries are handled.
+    pub fn plan_upgrade(&self, from_suite: &str, to_suite: &str) -> UpgradePlan {
+        UpgradePlanner::new(from_suite, to_suite).plan(self)
+    }
+
+    /// Applies an upgrade plan to the entries in memory.
+    ///
+    /// Every entry of the plan is checked to be unchanged since the plan was made before
+    /// anything is modified, so that either the whole plan or nothing is applied. Use
+    /// `SourcesLists::write_sync` to write the changes to the disk.
+    pub fn apply_upgrade_plan(&mut self, plan: &UpgradePlan) -> SourceResult<()> {
+        for planned in &plan.entries {
+            if self.entry_at(&planned.location) != Some(&planned.original) {
+                return Err(SourceError::StaleEntry {
+                    location: planned.location.clone(),
+                });
+            }
+        }
+
+        for planned in &plan.entries {
+            if !matches!(
+                planned.action,
+                PlannedAction::Upgrade { .. } | PlannedAction::Disable(_)
+            ) {
+                continue;
+            }
+
+            let location = &planned.location;
+            let &mut Self {
+                ref mut modified,
+                ref mut files,
+                ..
+            } = self;
+
+            if let Some(list) = files.iter_mut().find(|list| list.path == location.path) {
+                if let Some(entry) = list.entry_mut(location.index) {
+                    *entry = planned.planned();
+                    add_modified(modified, &location.path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests;
+
+    const SOURCES: &str = "\
+deb http://deb.debian.org/debian bookworm main contrib
+deb-src http://deb.debian.org/debian bookworm main
+deb http://deb.debian.org/debian bookworm-updates main
+deb http://security.debian.org/debian-security bookworm-security main
+deb http://deb.debian.org/debian bookworm-proposed main
+deb http://deb.debian.org/debian bookworm-weird main
+deb https://mirrors.ustc.edu.cn/debian bookworm-backports main
+deb [signed-by=/usr/share/keyrings/docker.gpg] https://download.docker.com/linux/debian bookworm stable
+deb https://repo.example.org/apt bookworm main
+deb file:/srv/mirror bookworm main
+deb http://deb.debian.org/debian sid main
+";
+
+    fn lists() -> SourcesLists {
+        tests::lists(&[("sources.list", SOURCES)])
+    }
+
+    fn at(index: usize) -> EntryLocation {
+        tests::location("sources.list", index)
+    }
+
+    #[test]
+    fn classify_entries() {
+        let lists = lists();
+        let retain = HashSet::from(["https://repo.example.org/apt".into()]);
+        let plan = UpgradePlanner::new("bookworm", "trixie")
+            .retain(retain)
+            .official_uri("https://mirrors.ustc.edu.cn/debian/")
+            .plan(&lists);
+
+        let actions = plan.entries.iter().map(|e| &e.action).collect::<Vec<_>>();
+        let upgrade = |suite: &str| PlannedAction::Upgrade {
+            suite: suite.to_owned(),
+        };
+
+        assert_eq!(
+            actions,
+            vec![
+                &upgrade("trixie"),
+                &upgrade("trixie"),
+                &upgrade("trixie-updates"),
+                &upgrade("trixie-security"),
+                &PlannedAction::NeedsAttention(PlanReason::ProposedPocket),
+                &PlannedAction::NeedsAttention(PlanReason::UnknownPocket("-weird".into())),
+                &upgrade("trixie-backports"),
+                &PlannedAction::Disable(PlanReason::ThirdParty),
+                &PlannedAction::Keep(PlanReason::Retained),
+                &PlannedAction::NeedsAttention(PlanReason::NonNetworkUri),
+                &PlannedAction::Keep(PlanReason::OtherSuite),
+            ]
+        );
+        assert_eq!(plan.needs_attention().count(), 3);
+        assert_eq!(
+            plan.dist_paths().next().as_deref(),
+            Some("http://deb.debian.org/debian/dists/trixie")
+        );
+    }
+
+    #[test]
+    fn mirrors_need_configuration() {
+        let lists = lists();
+        let action = |planner: UpgradePlanner| planner.plan(&lists).entries[6].action.clone();
+
+        // A `/debian` path alone does not make a host part of the distribution.
+        assert_eq!(
+            action(UpgradePlanner::new("bookworm", "trixie")),
+            PlannedAction::Disable(PlanReason::ThirdParty)
+        );
+        assert_eq!(
+            action(
+                UpgradePlanner::new("bookworm", "trixie")
+                    .official_uri("https://mirrors.ustc.edu.cn/deb")
+            ),
+            PlannedAction::Disable(PlanReason::ThirdParty)
+        );
+        assert_eq!(
+            action(
+                UpgradePlanner::new("bookworm", "trixie")
+                    .official_uri("https://mirrors.ustc.edu.cn")
+            ),
+            PlannedAction::Upgrade {
+                suite: "trixie-backports".into()
+            }
+        );
+    }
+
+    #[test]
+    fn edit_and_apply() {
+        let mut lists = lists();
+        let mut plan = UpgradePlanner::new("bookworm", "trixie")
+            .third_party(ThirdPartyPolicy::Upgrade)
+            .plan(&lists);
+
+        assert!(plan.set_action(&at(4), PlannedAction::Disable(PlanReason::ProposedPocket)));
+
+        lists.apply_upgrade_plan(&plan).unwrap();
+        assert_eq!(lists.modified, [lists.files[0].path.clone()]);
+
+        let entries = lists.entries().collect::<Vec<_>>();
+        assert_eq!(entries[0].suite, "trixie");
+        assert!(!entries[4].enabled);
+        assert_eq!(entries[4].suite, "bookworm-proposed");
+        assert_eq!(
+            entries[7].to_string(),
+            "deb [signed-by=/usr/share/keyrings/docker.gpg] \
+             https://download.docker.com/linux/debian trixie stable"
+        );
+        assert_eq!(entries[10].suite, "sid");
+    }
+
+    #[test]
+    fn stale_plan() {
+        let mut lists = lists();
+        let plan = lists.plan_upgrade("bookworm", "trixie");
+        lists.repo_modify("https://repo.example.org/apt", false);
+
+        assert!(matches!(
+            lists.apply_upgrade_plan(&plan),
+            Err(SourceError::StaleEntry { location }) if location == at(8)
+        ));
+        assert_eq!(lists.entries().next().unwrap().suite, "bookworm");
+    }
+}
@@ -0,0 +1,462 @@
+//! Semantic checks of source entries, following the rules of `sources.list(5)`.
+//!
+//! Parsing only ensures that a file is well-formed. The checks here report entries which apt
+//! would reject or misinterpret, without failing to load the file.
+
+use crate::deb822::{RepositoryType, YesNoForce};
+use crate::source_deb822::CORE_FIELDS;
+use crate::source_entry::same_option;
+use crate::{EntryLocation, SourceEntry, SourceListType, SourcesList, SourcesLists};
+use crate::{KeyReference, Signature};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use url::Url;
+
+/// Options which only accept `yes` or `no`.
+const YES_NO_OPTIONS: &[&str] = &[
+    "pdiffs",
+    "allow-insecure",
+    "allow-weak",
+    "allow-downgrade-to-insecure",
+    "trusted",
+    "check-valid-until",
+    "check-date",
+];
+
+/// How apt treats a problem found by validation.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// The entry is accepted, but probably not as intended.
+    Warning,
+    /// The entry is rejected or cannot work.
+    Error,
+}
+
+/// A problem with a source entry.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ValidationIssue {
+    /// A suite which is not a flat path must be followed by at least one component.
+    MissingComponents { suite: String },
+    /// A flat suite, ending with a slash, cannot have components.
+    ComponentsOnFlatSuite { suite: String },
+    /// The URI has no scheme, and thus cannot be fetched.
+    RelativeUri { uri: String },
+    /// A type other than `deb` and `deb-src`.
+    UnknownType { name: String },
+    /// The entry has no suite.
+    EmptySuite,
+    /// An option is given more than once, and only one of the values is used.
+    DuplicateOption { name: String },
+    /// An option has a value that it does not accept.
+    InvalidOptionValue { name: String, value: String },
+    /// A `signed-by` value which is neither an absolute path nor a fingerprint.
+    InvalidSignedBy { value: String },
+}
+
+impl ValidationIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidationIssue::DuplicateOption { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            ValidationIssue::MissingComponents { suite } => {
+                write!(fmt, "suite '{}' requires at least one component", suite)
+            }
+            ValidationIssue::ComponentsOnFlatSuite { suite } => {
+                write!(fmt, "flat suite '{}' cannot have components", suite)
+            }
+            ValidationIssue::RelativeUri { uri } => {
+                write!(fmt, "URI '{}' is not absolute", uri)
+            }
+            ValidationIssue::UnknownType { name } => write!(fmt, "unknown type '{}'", name),
+            ValidationIssue::EmptySuite => fmt.write_str("no suite is given"),
+            ValidationIssue::DuplicateOption { name } => {
+                write!(fmt, "option '{}' is given more than once", name)
+            }
+            ValidationIssue::InvalidSignedBy { value } => write!(
+                fmt,
+                "signed-by value '{}' is neither an absolute path nor a fingerprint",
+                value
+            ),
+            ValidationIssue::InvalidOptionValue { name, value } => {
+                write!(
+                    fmt,
+                    "'{}' is not a valid value for option '{}'",
+                    value, name
+                )
+            }
+        }
+    }
+}
+
+/// A problem found by validation, along with where it was found.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Diagnostic {
+    pub location: EntryLocation,
+    pub severity: Severity,
+    pub issue: ValidationIssue,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(fmt, "{}: {}: {}", self.location, severity, self.issue)
+    }
+}
+
+/// Lists every problem with a single entry.
+fn entry_issues(entry: &SourceEntry) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    if entry.suite.is_empty() {
+        issues.push(ValidationIssue::EmptySuite);
+    } else if entry.parsed_suite().is_flat() {
+        if !entry.components.is_empty() {
+            issues.push(ValidationIssue::ComponentsOnFlatSuite {
+                suite: entry.suite.clone(),
+            });
+        }
+    } else if entry.components.is_empty() {
+        issues.push(ValidationIssue::MissingComponents {
+            suite: entry.suite.clone(),
+        });
+    }
+
+    if Url::parse(&entry.url).is_err() {
+        issues.push(ValidationIssue::RelativeUri {
+            uri: entry.url.clone(),
+        });
+    }
+
+    for key in entry
+        .signed_by
+        .as_ref()
+        .and_then(Signature::keys)
+        .unwrap_or_default()
+    {
+        if let KeyReference::Path(path) = key {
+            if !path.is_absolute() {
+                issues.push(ValidationIssue::InvalidSignedBy {
+                    value: path.to_string_lossy().into_owned(),
+                });
+            }
+        }
+    }
+
+    let mut seen: Vec<&str> = Vec::new();
+    for (name, values) in &entry.options {
+        // Unknown types are kept as an option of deb822 entries; known types there come
+        // from a repeated `Types` field.
+        if entry.is_deb822 && name.eq_ignore_ascii_case("Types") {
+            for value in values {
+                if value.parse::<RepositoryType>().is_err() {
+                    issues.push(ValidationIssue::UnknownType {
+                        name: value.clone(),
+                    });
+                } else if !issues.contains(&duplicate(name)) {
+                    issues.push(duplicate(name));
+                }
+            }
+
+            continue;
+        }
+
+        // The first occurrence of these was moved to a dedicated field, so any remaining one
+        // is a repetition.
+        let repeated = if entry.is_deb822 {
+            CORE_FIELDS.iter().any(|f| f.eq_ignore_ascii_case(name))
+        } else {
+            (same_option(name, "arch") && entry.archs.is_some())
+                || (same_option(name, "signed-by") && entry.signed_by.is_some())
+                || (same_option(name, "trusted") && is_yes_no(values))
+        };
+
+        if (repeated || seen.iter().any(|s| same_option(s, name)))
+            && !issues.contains(&duplicate(name))
+        {
+            issues.push(duplicate(name));
+        }
+        seen.push(name);
+
+        let valid = if same_option(name, "by-hash") {
+            values.len() == 1 && YesNoForce::from_str(&values[0]).is_ok()
+        } else if YES_NO_OPTIONS.iter().any(|o| same_option(o, name)) {
+            is_yes_no(values)
+        } else {
+            true
+        };
+
+        if !valid {
+            issues.push(ValidationIssue::InvalidOptionValue {
+                name: name.clone(),
+                value: values.join(","),
+            });
+        }
+    }
+
+    issues
+}
+
+fn duplicate(name: &str) -> ValidationIssue {
+    ValidationIssue::DuplicateOption {
+        name: name.to_owned(),
+    }
+}
+
+fn is_yes_no(values: &[String]) -> bool {
+    matches!(values, [v] if v == "yes" || v == "no")
+}
+
+/// Whether two expanded deb822 entries may come from the same stanza, and thus share the
+/// problems of its fields.
+pub(crate) fn same_fields(a: &SourceEntry, b: &SourceEntry) -> bool {
+    a.enabled == b.enabled
+        && a.components == b.components
+        && a.archs == b.archs
+        && a.signed_by == b.signed_by
+        && a.trusted == b.trusted
+        && a.unsupported_type == b.unsupported_type
+        && a.options == b.options
+}
+
+impl SourcesList {
+    /// Checks every entry of the file against the rules of `sources.list(5)`.
+    ///
+    /// A deb822 stanza is expanded into one entry per type, URI and suite, but each problem
+    /// is only reported once per stanza, at its first entry.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut stanza: Vec<ValidationIssue> = Vec::new();
+        let mut previous: Option<&SourceEntry> = None;
+
+        for (index, entry) in self.all_indexed_entries() {
+            let shared = matches!(self.entries, SourceListType::Deb822(_))
+                && previous.is_some_and(|p| same_fields(p, entry));
+            if !shared {
+                stanza.clear();
+            }
+            previous = Some(entry);
+
+            for issue in entry_issues(entry) {
+                if stanza.contains(&issue) {
+                    continue;
+                }
+
+                stanza.push(issue.clone());
+                diagnostics.push(Diagnostic {
+                    location: EntryLocation {
+                        path: self.path.clone(),
+                        index,
+                    },
+                    severity: issue.severity(),
+                    issue,
+                });
+            }
+        }
+
+        diagnostics
+    }
+}
+
+impl SourcesLists {
+    /// Checks every entry of every file against the rules of `sources.list(5)`.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self.iter().flat_map(SourcesList::validate).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_deb822::SourceListDeb822;
+    use crate::SourceListLineStyle;
+    use indoc::indoc;
+
+    fn issues(path: &str, text: &str) -> Vec<(usize, ValidationIssue)> {
+        let entries = if path.ends_with(".sources") {
+            SourceListType::Deb822(text.parse::<SourceListDeb822>().unwrap())
+        } else {
+            SourceListType::SourceLine(text.parse::<SourceListLineStyle>().unwrap())
+        };
+
+        SourcesList {
+            path: path.into(),
+            entries,
+        }
+        .validate()
+        .into_iter()
+        .map(|d| (d.location.index, d.issue))
+        .collect()
+    }
+
+    #[test]
+    fn one_line() {
+        let text = indoc! {"
+            deb http://deb.debian.org/debian bookworm main
+            deb http://deb.debian.org/debian bookworm
+            deb http://example.com/repo ./ main
+            deb [arch=amd64 arch=i386 by-hash=sometimes trusted=maybe] deb.debian.org/debian bookworm main
+        "};
+
+        assert_eq!(
+            issues("sources.list", text),
+            [
+                (
+                    1,
+                    ValidationIssue::MissingComponents {
+                        suite: "bookworm".into()
+                    }
+                ),
+                (
+                    2,
+                    ValidationIssue::ComponentsOnFlatSuite { suite: "./".into() }
+                ),
+                (
+                    3,
+                    ValidationIssue::RelativeUri {
+                        uri: "deb.debian.org/debian".into()
+                    }
+                ),
+                (3, duplicate("arch")),
+                (
+                    3,
+                    ValidationIssue::InvalidOptionValue {
+                        name: "by-hash".into(),
+                        value: "sometimes".into()
+                    }
+                ),
+                (
+                    3,
+                    ValidationIssue::InvalidOptionValue {
+                        name: "trusted".into(),
+                        value: "maybe".into()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn deb822() {
+        let text = indoc! {"
+            Types: deb deb-src rpm
+            URIs: http://deb.debian.org/debian
+            Suites: bookworm bookworm-updates
+            Allow-Weak: perhaps
+
+            Types: deb
+            URIs: http://deb.debian.org/debian
+            Suites: bookworm
+            Components: main
+            Components: contrib
+        "};
+
+        let issues = issues("debian.sources", text);
+        assert_eq!(
+            issues,
+            [
+                (
+                    0,
+                    ValidationIssue::MissingComponents {
+                        suite: "bookworm".into()
+                    }
+                ),
+                (0, ValidationIssue::UnknownType { name: "rpm".into() }),
+                (
+                    0,
+                    ValidationIssue::InvalidOptionValue {
+                        name: "Allow-Weak".into(),
+                        value: "perhaps".into()
+                    }
+                ),
+                (
+                    2,
+                    ValidationIssue::MissingComponents {
+                        suite: "bookworm-updates".into()
+                    }
+                ),
+                (4, duplicate("Components")),
+            ]
+        );
+        assert_eq!(issues[4].1.severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn deb822_relative_uri() {
+        let text = indoc! {"
+            Types: deb
+            URIs: deb.debian.org/debian http://deb.debian.org/debian
+            Suites: bookworm
+            Components: main
+        "};
+
+        assert_eq!(
+            issues("debian.sources", text),
+            [(
+                0,
+                ValidationIssue::RelativeUri {
+                    uri: "deb.debian.org/debian".into()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn signed_by() {
+        let text = "deb [signed-by=debian.gpg] http://deb.debian.org/debian bookworm main";
+
+        assert_eq!(
+            issues("sources.list", text),
+            [(
+                0,
+                ValidationIssue::InvalidSignedBy {
+                    value: "debian.gpg".into()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn unsupported_type() {
+        let text = indoc! {"
+            Types: rpm rpm-src
+            URIs: http://example.com/fedora
+            Suites: 40 41
+            Components: main
+        "};
+
+        assert_eq!(
+            issues("example.sources", text),
+            [
+                (0, ValidationIssue::UnknownType { name: "rpm".into() }),
+                (
+                    0,
+                    ValidationIssue::UnknownType {
+                        name: "rpm-src".into()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn valid() {
+        let text = indoc! {"
+            deb [arch=amd64 signed-by=/usr/share/keyrings/debian.gpg] http://deb.debian.org/debian bookworm main
+            deb [signed-by=A7236886F3CCCAAD148A27F80E98404D386FA1D9!] http://deb.debian.org/debian bookworm main
+            deb [trusted=yes] file:/srv/mirror ./
+        "};
+
+        assert!(issues("sources.list", text).is_empty());
+    }
+}
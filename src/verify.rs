@@ -0,0 +1,589 @@
+//! Offline verification of the releases cached by apt, against the keys allowed by entries.
+//!
+//! Signatures by RSA and Ed25519 keys are verified, which are the algorithms used by
+//! Debian, Ubuntu and most third-party repositories.
+
+use super::*;
+use crate::openpgp::{
+    read_signatures, rooted, split_clearsigned, Reader, SignaturePacket, SigningBinding,
+};
+use ed25519_dalek::Verifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha1::{Digest, Sha1};
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The result of checking a release signature.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SignatureStatus {
+    /// The signature was made by an allowed key.
+    Good { fingerprint: String },
+    /// The signature does not match the release, or its key.
+    Bad { fingerprint: String },
+    /// The key which made the signature has expired.
+    Expired { fingerprint: String },
+    /// The key which made the signature has been revoked.
+    Revoked { fingerprint: String },
+    /// The key or the digest of the signature is too weak for apt.
+    Weak { fingerprint: String },
+    /// The signature was made by a key which the entry does not allow.
+    UnknownKey { issuer: String },
+    /// The signature uses an algorithm which cannot be verified here.
+    Unsupported { what: String },
+    /// The release or the keyrings could not be read.
+    Unreadable { why: String },
+    /// No signed release is cached for the entry.
+    Missing,
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureStatus::Good { fingerprint } => {
+                write!(fmt, "good signature by {}", fingerprint)
+            }
+            SignatureStatus::Bad { fingerprint } => write!(fmt, "bad signature by {}", fingerprint),
+            SignatureStatus::Expired { fingerprint } => {
+                write!(fmt, "key {} has expired", fingerprint)
+            }
+            SignatureStatus::Revoked { fingerprint } => {
+                write!(fmt, "key {} has been revoked", fingerprint)
+            }
+            SignatureStatus::Weak { fingerprint } => {
+                write!(fmt, "signature by {} is too weak", fingerprint)
+            }
+            SignatureStatus::UnknownKey { issuer } => write!(fmt, "unknown key {}", issuer),
+            SignatureStatus::Unsupported { what } => write!(fmt, "unsupported {}", what),
+            SignatureStatus::Unreadable { why } => write!(fmt, "unreadable: {}", why),
+            SignatureStatus::Missing => fmt.write_str("no signed release"),
+        }
+    }
+}
+
+/// The verification of the release of an entry.
+#[derive(Clone, Debug)]
+pub struct ReleaseVerification {
+    pub location: EntryLocation,
+    /// The cached `InRelease` or `Release` file, if there is one.
+    pub release: Option<PathBuf>,
+    /// The overall status, as apt would see it.
+    pub status: SignatureStatus,
+    /// The status of every signature of the release.
+    pub signatures: Vec<SignatureStatus>,
+}
+
+impl ReleaseVerification {
+    pub fn is_good(&self) -> bool {
+        matches!(self.status, SignatureStatus::Good { .. })
+    }
+}
+
+/// A certificate allowed by an entry, possibly restricted to one of its keys.
+struct AllowedKey {
+    certificate: Certificate,
+    /// Set by fingerprints ending with `!`, which only allow the key that they name.
+    only: Option<String>,
+}
+
+impl SourcesLists {
+    /// Verifies the cached release of every entry against the keys that the entry allows.
+    ///
+    /// Releases are read from **var/lib/apt/lists** and keyrings from their paths, below the
+    /// root argument. Entries without a `signed-by` option allow every key of the trusted
+    /// keyrings. Keys are checked for expiry at the given date.
+    pub fn verify_releases<P: AsRef<Path>>(&self, root: P, date: Date) -> Vec<ReleaseVerification> {
+        let root = root.as_ref();
+        let trusted = trusted_keyrings(root)
+            .into_iter()
+            .filter_map(|(_, keyring)| keyring.ok())
+            .flat_map(|keyring| keyring.certificates)
+            .collect::<Vec<_>>();
+
+        let lists_dir = root.join("var/lib/apt/lists");
+        self.located_entries()
+            .map(|(location, entry)| {
+                let in_release = lists_dir.join(entry.lists_filename("InRelease"));
+                let release = lists_dir.join(entry.lists_filename("Release"));
+                let detached = lists_dir.join(entry.lists_filename("Release.gpg"));
+
+                let (release, signed) = if in_release.exists() {
+                    (Some(in_release.clone()), Some((in_release, None)))
+                } else if release.exists() && detached.exists() {
+                    (Some(release.clone()), Some((release, Some(detached))))
+                } else {
+                    (release.exists().then_some(release), None)
+                };
+
+                let signatures = match signed {
+                    None => vec![SignatureStatus::Missing],
+                    Some((release, detached)) => match allowed_keys(entry, root, &trusted) {
+                        Ok(allowed) => {
+                            verify_release(&release, detached.as_deref(), &allowed, date)
+                        }
+                        Err(why) => vec![SignatureStatus::Unreadable {
+                            why: why.to_string(),
+                        }],
+                    },
+                };
+
+                ReleaseVerification {
+                    location,
+                    release,
+                    status: overall(&signatures),
+                    signatures,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Any bad signature fails the release. Otherwise, a single good signature is enough.
+fn overall(signatures: &[SignatureStatus]) -> SignatureStatus {
+    let find = |pred: fn(&SignatureStatus) -> bool| signatures.iter().find(|s| pred(s)).cloned();
+
+    find(|s| matches!(s, SignatureStatus::Bad { .. }))
+        .or_else(|| find(|s| matches!(s, SignatureStatus::Good { .. })))
+        .or_else(|| signatures.first().cloned())
+        .unwrap_or(SignatureStatus::Missing)
+}
+
+/// Collects the certificates that the `signed-by` option of an entry allows.
+fn allowed_keys(
+    entry: &SourceEntry,
+    root: &Path,
+    trusted: &[Certificate],
+) -> Result<Vec<AllowedKey>, KeyError> {
+    let unrestricted = |certificate: Certificate| AllowedKey {
+        certificate,
+        only: None,
+    };
+
+    let keys = match &entry.signed_by {
+        None => return Ok(trusted.iter().cloned().map(unrestricted).collect()),
+        Some(Signature::KeyBlock(armor)) => {
+            let keyring = Keyring::parse(armor.as_bytes())?;
+            return Ok(keyring.certificates.into_iter().map(unrestricted).collect());
+        }
+        Some(signature) => signature.keys().unwrap_or_default(),
+    };
+
+    let mut allowed = Vec::new();
+    for key in keys {
+        match key {
+            KeyReference::Path(path) => {
+                let keyring = Keyring::open(rooted(root, &path))?;
+                allowed.extend(keyring.certificates.into_iter().map(unrestricted));
+            }
+            KeyReference::Fingerprint(fingerprint) => {
+                allowed.extend(trusted.iter().filter(|c| c.matches(&fingerprint)).map(|c| {
+                    AllowedKey {
+                        certificate: c.clone(),
+                        only: fingerprint.is_exact().then(|| fingerprint.hex().to_owned()),
+                    }
+                }))
+            }
+        }
+    }
+
+    Ok(allowed)
+}
+
+/// Checks every signature of a release, which is either clearsigned or has a detached
+/// signature.
+fn verify_release(
+    release: &Path,
+    detached: Option<&Path>,
+    allowed: &[AllowedKey],
+    date: Date,
+) -> Vec<SignatureStatus> {
+    let (message, signature) = match read_signed(release, detached) {
+        Ok(signed) => signed,
+        Err(why) => return vec![SignatureStatus::Unreadable { why }],
+    };
+
+    match read_signatures(&signature) {
+        Ok(signatures) if signatures.is_empty() => vec![SignatureStatus::Missing],
+        Ok(signatures) => signatures
+            .iter()
+            .map(|signature| verify_signature(&message, signature, allowed, date))
+            .collect(),
+        Err(why) => vec![SignatureStatus::Unreadable {
+            why: why.to_string(),
+        }],
+    }
+}
+
+/// Reads the signed data and the signature of a release, which is either clearsigned or has
+/// a detached signature.
+pub(crate) fn read_signed(
+    release: &Path,
+    detached: Option<&Path>,
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let read = |path: &Path| fs::read(path).map_err(|why| format!("{}: {}", path.display(), why));
+
+    let data = read(release)?;
+    match detached {
+        Some(detached) => Ok((data, read(detached)?)),
+        None => {
+            let text = String::from_utf8_lossy(&data);
+            let (message, signature) = split_clearsigned(&text)
+                .ok_or_else(|| String::from("the release is not clearsigned"))?;
+            Ok((message, signature.as_bytes().to_vec()))
+        }
+    }
+}
+
+fn verify_signature(
+    message: &[u8],
+    signature: &SignaturePacket,
+    allowed: &[AllowedKey],
+    date: Date,
+) -> SignatureStatus {
+    let issuer = allowed.iter().find_map(|allowed| {
+        let key = allowed.certificate.issuer_of(signature)?;
+        let permitted = allowed
+            .only
+            .as_ref()
+            .is_none_or(|only| only == key.fingerprint());
+        permitted.then_some((&allowed.certificate, key))
+    });
+
+    let (certificate, key) = match issuer {
+        Some(issuer) => issuer,
+        None => {
+            return SignatureStatus::UnknownKey {
+                issuer: signature
+                    .issuer_fingerprint
+                    .clone()
+                    .or_else(|| signature.issuer.clone())
+                    .unwrap_or_default(),
+            }
+        }
+    };
+
+    if let Err(status) = check_made_by(message, signature, certificate, key) {
+        return status;
+    }
+
+    let fingerprint = key.fingerprint().to_owned();
+    if key.is_revoked() || certificate.primary.is_revoked() {
+        SignatureStatus::Revoked { fingerprint }
+    } else if key.is_expired(date) || certificate.is_expired(date) {
+        SignatureStatus::Expired { fingerprint }
+    } else if key.is_weak() || signature.hash_algorithm == 2 {
+        // apt rejects signatures made with SHA-1.
+        SignatureStatus::Weak { fingerprint }
+    } else {
+        SignatureStatus::Good { fingerprint }
+    }
+}
+
+/// Checks that the signature of the message was made by a key of the certificate, whether
+/// or not the key may still be used.
+pub(crate) fn check_made_by(
+    message: &[u8],
+    signature: &SignaturePacket,
+    certificate: &Certificate,
+    key: &PublicKey,
+) -> Result<(), SignatureStatus> {
+    let fingerprint = key.fingerprint().to_owned();
+    let message = match signed_message(message, signature) {
+        Ok(message) => message,
+        Err(what) => return Err(SignatureStatus::Unsupported { what }),
+    };
+
+    match verify_data(key, signature, &message) {
+        Ok(true) => (),
+        Ok(false) => return Err(SignatureStatus::Bad { fingerprint }),
+        Err(what) => return Err(SignatureStatus::Unsupported { what }),
+    }
+
+    // A subkey only signs for the certificate once bound to it for signing, both ways.
+    if key.fingerprint() != certificate.primary.fingerprint() {
+        match &key.signing {
+            SigningBinding::Signing => (),
+            SigningBinding::Unverified(what) => {
+                return Err(SignatureStatus::Unsupported {
+                    what: format!("subkey binding: {}", what),
+                })
+            }
+            SigningBinding::None => return Err(SignatureStatus::Bad { fingerprint }),
+        }
+    }
+
+    Ok(())
+}
+
+/// The form of a document which is hashed by the signature.
+///
+/// Binary signatures hash the data as is, and text signatures with CRLF line endings.
+fn signed_message<'a>(
+    message: &'a [u8],
+    signature: &SignaturePacket,
+) -> Result<Cow<'a, [u8]>, String> {
+    match signature.sig_type {
+        0x00 => Ok(Cow::Borrowed(message)),
+        0x01 => Ok(Cow::Owned(
+            String::from_utf8_lossy(message)
+                .replace("\r\n", "\n")
+                .replace('\n', "\r\n")
+                .into_bytes(),
+        )),
+        other => Err(format!("signature type {:#04x}", other)),
+    }
+}
+
+/// Checks a signature of the data by the key.
+///
+/// Returns an error describing what is not supported, such as the algorithm.
+pub(crate) fn verify_data(
+    key: &PublicKey,
+    signature: &SignaturePacket,
+    data: &[u8],
+) -> Result<bool, String> {
+    let digest = signed_digest(data, signature)?;
+    if digest[..2] != signature.digest_prefix {
+        return Ok(false);
+    }
+
+    check_signature(key, signature, &digest)
+}
+
+/// Hashes the signed data along with the signature, as described by RFC 9580.
+fn signed_digest(message: &[u8], signature: &SignaturePacket) -> Result<Vec<u8>, String> {
+    if !matches!(signature.version, 4 | 6) {
+        return Err(format!("version {} signature", signature.version));
+    }
+
+    let mut trailer = vec![signature.version, 0xFF];
+    trailer.extend_from_slice(&(signature.hashed.len() as u32).to_be_bytes());
+    let parts: [&[u8]; 4] = [&signature.salt, message, &signature.hashed, &trailer];
+
+    fn hash<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = D::new();
+        for part in parts {
+            hasher.update(part);
+        }
+
+        hasher.finalize().to_vec()
+    }
+
+    match signature.hash_algorithm {
+        2 => Ok(hash::<Sha1>(&parts)),
+        8 => Ok(hash::<Sha256>(&parts)),
+        9 => Ok(hash::<Sha384>(&parts)),
+        10 => Ok(hash::<Sha512>(&parts)),
+        11 => Ok(hash::<Sha224>(&parts)),
+        other => Err(format!("hash algorithm {}", other)),
+    }
+}
+
+/// Verifies the signature of a digest with the key.
+///
+/// Returns an error describing the algorithm if it is not supported.
+fn check_signature(
+    key: &PublicKey,
+    signature: &SignaturePacket,
+    digest: &[u8],
+) -> Result<bool, String> {
+    let unsupported = || format!("{:?} signature", signature.algorithm);
+    let mut key_material = Reader::new(&key.material);
+    let mut material = Reader::new(&signature.material);
+
+    match (key.algorithm(), signature.algorithm) {
+        (PublicKeyAlgorithm::Rsa, PublicKeyAlgorithm::Rsa) => {
+            let (Ok((_, n)), Ok((_, e)), Ok((_, s))) =
+                (key_material.mpi(), key_material.mpi(), material.mpi())
+            else {
+                return Ok(false);
+            };
+
+            let Ok(public) =
+                RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+            else {
+                return Ok(false);
+            };
+
+            // The signature is an integer, which may be shorter than the modulus.
+            let mut padded = vec![0; n.len().saturating_sub(s.len())];
+            padded.extend_from_slice(s);
+
+            let scheme = match signature.hash_algorithm {
+                2 => Pkcs1v15Sign::new::<Sha1>(),
+                8 => Pkcs1v15Sign::new::<Sha256>(),
+                9 => Pkcs1v15Sign::new::<Sha384>(),
+                10 => Pkcs1v15Sign::new::<Sha512>(),
+                11 => Pkcs1v15Sign::new::<Sha224>(),
+                _ => return Err(unsupported()),
+            };
+
+            Ok(public.verify(scheme, digest, &padded).is_ok())
+        }
+        (PublicKeyAlgorithm::EdDsaLegacy, PublicKeyAlgorithm::EdDsaLegacy) => {
+            if key.curve() != Some(&Curve::Ed25519) {
+                return Err(unsupported());
+            }
+
+            // The point is prefixed by 0x40, after the OID of the curve.
+            let point = key_material
+                .u8()
+                .and_then(|len| key_material.take(len as usize))
+                .and_then(|_| key_material.mpi());
+            let (Ok((_, point)), Ok((_, r)), Ok((_, s))) = (point, material.mpi(), material.mpi())
+            else {
+                return Ok(false);
+            };
+
+            let mut raw = [0; 64];
+            let (Some(point), true, true) =
+                (point.strip_prefix(&[0x40]), r.len() <= 32, s.len() <= 32)
+            else {
+                return Ok(false);
+            };
+            raw[32 - r.len()..32].copy_from_slice(r);
+            raw[64 - s.len()..].copy_from_slice(s);
+
+            Ok(verify_ed25519(point, &raw, digest))
+        }
+        (PublicKeyAlgorithm::Ed25519, PublicKeyAlgorithm::Ed25519) => {
+            Ok(verify_ed25519(&key.material, &signature.material, digest))
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+fn verify_ed25519(public: &[u8], signature: &[u8], digest: &[u8]) -> bool {
+    let (Ok(public), Ok(signature)) = (
+        <[u8; 32]>::try_from(public),
+        <[u8; 64]>::try_from(signature),
+    ) else {
+        return false;
+    };
+
+    ed25519_dalek::VerifyingKey::from_bytes(&public).is_ok_and(|key| {
+        key.verify(digest, &ed25519_dalek::Signature::from_bytes(&signature))
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{self, TempRoot};
+
+    const SOURCES: &str = "\
+deb [signed-by=/usr/share/keyrings/example-archive.asc] https://example.org/debian stable main
+deb http://deb.debian.org/debian bookworm main
+deb [signed-by=/usr/share/keyrings/legacy-rsa1024.gpg] https://legacy.example.com/apt ./
+deb https://unknown.example.net/debian stable main
+deb [signed-by=/usr/share/keyrings/legacy-rsa1024.gpg] https://example.org/debian stable main
+deb [signed-by=8356B50980AE5E15DD75D36B7EFE4E79A5238717] https://example.org/debian stable main
+";
+
+    fn lists(sources: &str) -> SourcesLists {
+        tests::lists(&[("sources.list", sources)])
+    }
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/root")
+    }
+
+    #[test]
+    fn releases() {
+        let example = "8356B50980AE5E15DD75D36B7EFE4E79A5238717".to_owned();
+        let statuses = lists(SOURCES)
+            .verify_releases(fixtures(), Date::new(2026, 1, 1))
+            .into_iter()
+            .map(|v| v.status)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            statuses,
+            [
+                SignatureStatus::Good {
+                    fingerprint: example.clone()
+                },
+                SignatureStatus::Good {
+                    fingerprint: "F805F32E7D18FBC85EF01B536EAD5CF79296E117".into()
+                },
+                SignatureStatus::Expired {
+                    fingerprint: "B92634F02D319DE1C0ED0AF1028C4910BBE90A9C".into()
+                },
+                SignatureStatus::Missing,
+                SignatureStatus::UnknownKey {
+                    issuer: example.clone()
+                },
+                SignatureStatus::Good {
+                    fingerprint: example
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tampered() {
+        let root = TempRoot::new("verify-tampered");
+        let lists_dir = root.join("var/lib/apt/lists");
+        fs::create_dir_all(&lists_dir).unwrap();
+
+        let name = "example.org_debian_dists_stable_InRelease";
+        let release = fs::read_to_string(fixtures().join("var/lib/apt/lists").join(name)).unwrap();
+        fs::write(
+            lists_dir.join(name),
+            release.replacen("stable", "unstable", 1),
+        )
+        .unwrap();
+
+        let keyring = "usr/share/keyrings/example-archive.asc";
+        fs::create_dir_all(root.join("usr/share/keyrings")).unwrap();
+        fs::copy(fixtures().join(keyring), root.join(keyring)).unwrap();
+
+        let verification = lists(SOURCES).verify_releases(&root, Date::new(2026, 1, 1));
+
+        assert_eq!(
+            verification[0].status,
+            SignatureStatus::Bad {
+                fingerprint: "8356B50980AE5E15DD75D36B7EFE4E79A5238717".into()
+            }
+        );
+        assert!(!verification[0].is_good());
+    }
+
+    #[test]
+    fn subkey_binding() {
+        let subkey = "deb [signed-by=/usr/share/keyrings/subkey-archive.gpg] \
+                      https://subkey.example.org/debian stable main\n";
+        let verification = lists(subkey).verify_releases(fixtures(), Date::new(2026, 1, 1));
+        assert_eq!(
+            verification[0].status,
+            SignatureStatus::Good {
+                fingerprint: "B6927B14B4425D277B6ED29ADF7FDC230068FBA3".into()
+            }
+        );
+
+        // Without the binding by the primary key, the subkey does not sign for the certificate.
+        let root = TempRoot::new("verify-subkey");
+        let name = "var/lib/apt/lists/subkey.example.org_debian_dists_stable_InRelease";
+        let keyring = "usr/share/keyrings/subkey-archive.gpg";
+        fs::create_dir_all(root.join("var/lib/apt/lists")).unwrap();
+        fs::create_dir_all(root.join("usr/share/keyrings")).unwrap();
+        fs::copy(fixtures().join(name), root.join(name)).unwrap();
+
+        let data = fs::read(fixtures().join(keyring)).unwrap();
+        let packets = crate::openpgp::packets(&data).unwrap();
+        let binding = packets.last().unwrap();
+        assert_eq!((binding.tag, binding.body[1]), (2, 0x18));
+        fs::write(root.join(keyring), &data[..data.len() - binding.raw.len()]).unwrap();
+
+        let verification = lists(subkey).verify_releases(&root, Date::new(2026, 1, 1));
+        assert_eq!(
+            verification[0].status,
+            SignatureStatus::Bad {
+                fingerprint: "B6927B14B4425D277B6ED29ADF7FDC230068FBA3".into()
+            }
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Runs `apt-sources` on a copy of the system in **tests/data/root**.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A copy of the test system, with the given sources list, which is removed when dropped.
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str, sources: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "oma-apt-sources-lists-cli-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/root"),
+            &path,
+        );
+        fs::write(path.join("etc/apt/sources.list"), sources).unwrap();
+        Root(path)
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_apt-sources"))
+            .arg("--root")
+            .arg(&self.0)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn sources(&self) -> String {
+        fs::read_to_string(self.0.join("etc/apt/sources.list")).unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+const SOURCES: &str = "\
+deb [signed-by=/usr/share/keyrings/example-archive.asc] https://example.org/debian stable main
+deb-src [signed-by=/usr/share/keyrings/example-archive.asc] https://example.org/debian stable main
+";
+
+#[test]
+fn list() {
+    let root = Root::new("list", SOURCES);
+    let output = root.run(&["list"]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "/etc/apt/sources.list:0\tdeb [signed-by=/usr/share/keyrings/example-archive.asc] \
+         https://example.org/debian stable main\n\
+         /etc/apt/sources.list:1\tdeb-src [signed-by=/usr/share/keyrings/example-archive.asc] \
+         https://example.org/debian stable main\n"
+    );
+}
+
+#[test]
+fn list_unsupported() {
+    let root = Root::new("list-unsupported", "");
+    let dir = root.0.join("etc/apt/sources.list.d");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("fedora.sources"),
+        "Types: rpm\nURIs: http://example.com/fedora\nSuites: 40\nComponents: main\n",
+    )
+    .unwrap();
+
+    let output = root.run(&["list"]);
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "/etc/apt/sources.list.d/fedora.sources:0\trpm http://example.com/fedora 40 main\n"
+    );
+}
+
+#[test]
+fn dry_run() {
+    let root = Root::new("dry-run", SOURCES);
+    let output = root.run(&["--dry-run", "disable", "/etc/apt/sources.list:1"]);
+
+    assert!(output.status.success());
+    let diff = stdout(&output);
+    assert!(diff.starts_with("--- /etc/apt/sources.list\n+++ /etc/apt/sources.list\n"));
+    assert!(diff.contains(
+        "\n-deb-src [signed-by=/usr/share/keyrings/example-archive.asc] \
+         https://example.org/debian stable main\n"
+    ));
+    assert!(diff.contains(
+        "\n+# deb-src [signed-by=/usr/share/keyrings/example-archive.asc] \
+         https://example.org/debian stable main\n"
+    ));
+    assert_eq!(root.sources(), SOURCES);
+
+    // Without a dry run, the change is written.
+    let output = root.run(&["disable", "/etc/apt/sources.list:1"]);
+    assert!(output.status.success());
+    assert!(root.sources().contains("\n# deb-src "));
+}
+
+#[test]
+fn lint() {
+    let clean = Root::new("lint-clean", SOURCES);
+    let output = clean.run(&["lint"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert_eq!(stdout(&output), "");
+
+    let insecure = Root::new(
+        "lint-insecure",
+        "deb [trusted=yes] https://example.org/debian stable main\n",
+    );
+    let output = insecure.run(&["lint"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("/etc/apt/sources.list:0: error: "));
+}
+
+#[test]
+fn errors() {
+    let root = Root::new("errors", SOURCES);
+
+    // A location outside of the lists fails without touching them.
+    let output = root.run(&["remove", "/etc/apt/sources.list:5"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(root.sources(), SOURCES);
+
+    // Locations which cannot be parsed are rejected by the argument parser.
+    let output = root.run(&["remove", "/etc/apt/sources.list"]);
+    assert!(!output.status.success());
+}